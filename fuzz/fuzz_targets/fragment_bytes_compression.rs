@@ -0,0 +1,39 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+extern crate pmd_wan;
+use pmd_wan::{CompressionMethod, FragmentBytes};
+use std::io::{Cursor, Seek, SeekFrom};
+
+// Check that FragmentBytes compressed with the optimised compression are decoded back identically
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 + 64 {
+        return;
+    }
+    let is_256_color = data[0] & 1 == 1;
+    let pixel_per_byte = if is_256_color { 1 } else { 2 };
+    let compression = CompressionMethod::CompressionMethodOptimised {
+        multiple_of_value: (data[1] as usize % 32 + 1) * pixel_per_byte,
+        min_transparent_to_compress: data[2] as usize,
+    };
+    let pixel_amount = ((data.len() - 3) / 64 * 64).min(64 * 64);
+    let mixed_pixels: Vec<u8> = data[3..3 + pixel_amount]
+        .iter()
+        // make transparent pixels frequent
+        .map(|pixel| if pixel & 0x80 == 0 { 0 } else { pixel & 0x7F })
+        .map(|pixel| if is_256_color { pixel } else { pixel & 0x0F })
+        .collect();
+    let fragment_bytes = FragmentBytes {
+        mixed_pixels,
+        z_index: 1,
+    };
+
+    // nothing is ever stored at the position 0, as this mean the part is transparent
+    let mut file = Cursor::new(vec![0xAA; 4]);
+    file.seek(SeekFrom::End(0)).unwrap();
+    let (assembly_table_offset, _) = fragment_bytes
+        .write(&mut file, &compression, is_256_color)
+        .unwrap();
+    file.seek(SeekFrom::Start(assembly_table_offset)).unwrap();
+    let decoded = FragmentBytes::new_from_bytes(&mut file, is_256_color, false).unwrap();
+    assert_eq!(decoded, fragment_bytes);
+});
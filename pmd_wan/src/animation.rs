@@ -1,9 +1,9 @@
-use crate::{AnimationFrame, WanError};
+use crate::{AnimationFrame, WanError, WanWriteError};
 use std::io::{Read, Write};
 
 /// An [`Animation`] is a set of [`AnimationFrame`], that will be played one after the other, and that would loop most of the time.
 /// The duration between an [`AnimationFrame`] and the next one is contained in the [`AnimationFrame`]
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, PartialEq, Eq, Default, Clone)]
 pub struct Animation {
     pub frames: Vec<AnimationFrame>,
 }
@@ -29,7 +29,7 @@ impl Animation {
         self.len() == 0
     }
 
-    pub fn write<F: Write>(file: &mut F, animation: &Animation) -> Result<(), WanError> {
+    pub fn write<F: Write>(file: &mut F, animation: &Animation) -> Result<(), WanWriteError> {
         for frame in &animation.frames {
             AnimationFrame::write(file, frame)?;
         }
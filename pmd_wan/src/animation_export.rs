@@ -0,0 +1,166 @@
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame,
+};
+use std::io::Write;
+use thiserror::Error;
+
+use crate::{RenderError, RenderedAnimation, WanImage};
+
+#[derive(Debug, Error)]
+pub enum AnimationExportError {
+    #[error("Failed to render the animation")]
+    RenderError(#[from] RenderError),
+    #[error("The animation has no pixel to export (either there are no frame, or all of them are empty)")]
+    EmptyAnimation,
+    #[error("Failed to encode the GIF")]
+    GifError(#[from] image::ImageError),
+    #[error("Failed to encode the APNG")]
+    ApngError(#[from] png::EncodingError),
+}
+
+impl RenderedAnimation {
+    fn check_exportable(&self) -> Result<(u32, u32), AnimationExportError> {
+        let dimensions = self
+            .frames
+            .first()
+            .ok_or(AnimationExportError::EmptyAnimation)?
+            .image
+            .dimensions();
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            return Err(AnimationExportError::EmptyAnimation);
+        }
+        Ok(dimensions)
+    }
+
+    /// Write this animation as a looping animated GIF.
+    ///
+    /// GIF delays are in hundredth of a second, while durations are in 1/60 of a second. Delays are rounded so that the total time elapsed at the end of each frame is as close as possible to the original.
+    pub fn write_gif<W: Write>(&self, writer: W) -> Result<(), AnimationExportError> {
+        self.check_exportable()?;
+        let mut encoder = GifEncoder::new(writer);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let mut elapsed_ticks: u64 = 0;
+        let mut elapsed_centiseconds: u64 = 0;
+        for frame in &self.frames {
+            elapsed_ticks += frame.duration as u64;
+            let target_centiseconds = (elapsed_ticks * 100 + 30) / 60;
+            let delay = target_centiseconds - elapsed_centiseconds;
+            elapsed_centiseconds = target_centiseconds;
+            encoder.encode_frame(Frame::from_parts(
+                frame.image.clone(),
+                0,
+                0,
+                Delay::from_numer_denom_ms(delay as u32 * 10, 1),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Write this animation as a looping animated PNG (APNG). Unlike GIF, the durations and the alpha are kept exactly.
+    pub fn write_apng<W: Write>(&self, writer: W) -> Result<(), AnimationExportError> {
+        let (width, height) = self.check_exportable()?;
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        let mut png_writer = encoder.write_header()?;
+        for frame in &self.frames {
+            png_writer.set_frame_delay(frame.duration as u16, 60)?;
+            png_writer.write_image_data(frame.image.as_raw())?;
+        }
+        png_writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Render the given [`crate::Animation`] and write it as a looping animated GIF.
+/// See [`WanImage::render_animation`] and [`RenderedAnimation::write_gif`].
+pub fn export_animation_gif<W: Write>(
+    wan_image: &WanImage,
+    anim_group_id: usize,
+    animation_id: usize,
+    writer: W,
+) -> Result<(), AnimationExportError> {
+    wan_image
+        .render_animation(anim_group_id, animation_id)?
+        .write_gif(writer)
+}
+
+/// Render the given [`crate::Animation`] and write it as a looping animated PNG.
+/// See [`WanImage::render_animation`] and [`RenderedAnimation::write_apng`].
+pub fn export_animation_apng<W: Write>(
+    wan_image: &WanImage,
+    anim_group_id: usize,
+    animation_id: usize,
+    writer: W,
+) -> Result<(), AnimationExportError> {
+    wan_image
+        .render_animation(anim_group_id, animation_id)?
+        .write_apng(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::gif::GifDecoder, AnimationDecoder, RgbaImage};
+    use std::io::Cursor;
+
+    use crate::{AnimationExportError, RenderedAnimation, RenderedAnimationFrame};
+
+    fn test_animation() -> RenderedAnimation {
+        let mut frames = Vec::new();
+        for (duration, color) in [(1, 255), (2, 128), (3, 0)] {
+            frames.push(RenderedAnimationFrame {
+                image: RgbaImage::from_pixel(4, 2, image::Rgba([color, 0, 0, 255])),
+                duration,
+            });
+        }
+        RenderedAnimation {
+            frames,
+            origin: (0, 0),
+        }
+    }
+
+    #[test]
+    fn test_write_gif() {
+        let mut output = Vec::new();
+        test_animation().write_gif(&mut output).unwrap();
+        let frames = GifDecoder::new(Cursor::new(output))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        // ends at 1/60, 3/60 and 6/60 of a second, that is 2, 5 and 10 hundredth once rounded
+        let delays: Vec<(u32, u32)> = frames.iter().map(|f| f.delay().numer_denom_ms()).collect();
+        assert_eq!(delays, vec![(20, 1), (30, 1), (50, 1)]);
+        assert_eq!(frames[1].buffer().get_pixel(3, 1).0, [128, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_write_apng() {
+        let mut output = Vec::new();
+        test_animation().write_apng(&mut output).unwrap();
+        let decoder = png::Decoder::new(Cursor::new(output));
+        let reader = decoder.read_info().unwrap();
+        let animation_control = reader.info().animation_control().unwrap();
+        assert_eq!(animation_control.num_frames, 3);
+        assert_eq!(animation_control.num_plays, 0);
+    }
+
+    #[test]
+    fn test_write_empty() {
+        let empty = RenderedAnimation {
+            frames: Vec::new(),
+            origin: (0, 0),
+        };
+        assert!(matches!(
+            empty.write_gif(Vec::new()),
+            Err(AnimationExportError::EmptyAnimation)
+        ));
+        assert!(matches!(
+            empty.write_apng(Vec::new()),
+            Err(AnimationExportError::EmptyAnimation)
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{get_opt_le, WanError};
+use crate::{get_opt_le, WanError, WanWriteError};
 use binwrite::BinWrite;
 use byteorder::{ReadBytesExt, LE};
 use std::io::{Read, Write};
@@ -39,7 +39,7 @@ impl AnimationFrame {
         self.duration == 0 && self.frame_id == 0
     }
 
-    pub fn write<F: Write>(file: &mut F, frame: &AnimationFrame) -> Result<(), WanError> {
+    pub fn write<F: Write>(file: &mut F, frame: &AnimationFrame) -> Result<(), WanWriteError> {
         (
             frame.duration,
             frame.flag,
@@ -54,7 +54,7 @@ impl AnimationFrame {
         Ok(())
     }
 
-    pub fn write_null<F: Write>(file: &mut F) -> Result<(), WanError> {
+    pub fn write_null<F: Write>(file: &mut F) -> Result<(), WanWriteError> {
         AnimationFrame::write(
             file,
             &AnimationFrame {
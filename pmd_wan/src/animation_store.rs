@@ -1,4 +1,4 @@
-use crate::{Animation, WanError};
+use crate::{Animation, WanError, WanWriteError};
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use std::io::{Read, Seek, SeekFrom, Write};
 
@@ -107,7 +107,7 @@ impl AnimationStore {
         ))
     }
 
-    pub fn write<F: Write + Seek>(&self, file: &mut F) -> anyhow::Result<Vec<u64>> {
+    pub fn write<F: Write + Seek>(&self, file: &mut F) -> Result<Vec<u64>, WanWriteError> {
         let mut animations_pointer = vec![];
         let mut previous_animation: Option<&Animation> = None;
         let mut previous_pointer = None;
@@ -148,7 +148,7 @@ impl AnimationStore {
         &self,
         file: &mut F,
         animations_pointer: &[u64],
-    ) -> Result<(u64, Vec<u64>), WanError> {
+    ) -> Result<(u64, Vec<u64>), WanWriteError> {
         let mut sir0_animation = Vec::new();
 
         struct AnimGroupData {
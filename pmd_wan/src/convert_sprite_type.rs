@@ -0,0 +1,180 @@
+use crate::{CompressionMethod, FrameOffset, SpriteType, WanImage};
+
+/// What changed when converting a [`WanImage`] to another [`SpriteType`] with [`WanImage::convert_to`].
+///
+/// It contains everything that was lost, so the conversion can be undone with [`ConversionReport::revert`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionReport {
+    pub previous_sprite_type: SpriteType,
+    /// The previous [`CompressionMethod`], if it was changed to the default one of the new [`SpriteType`]
+    pub previous_compression: Option<CompressionMethod>,
+    /// The [`FrameOffset`]s that were removed, with the index of their frame, as only [`SpriteType::Chara`] sprites have them
+    pub dropped_frame_offsets: Vec<(usize, FrameOffset)>,
+    /// The index of the frames that were given a placeholder [`FrameOffset`], as they are mandatory for [`SpriteType::Chara`] sprites
+    pub synthesized_frame_offsets: Vec<usize>,
+}
+
+impl ConversionReport {
+    /// Return true if no information was lost or invented by the conversion
+    pub fn is_lossless(&self) -> bool {
+        self.dropped_frame_offsets.is_empty() && self.synthesized_frame_offsets.is_empty()
+    }
+
+    /// Convert the sprite back to its previous [`SpriteType`], restoring its [`CompressionMethod`] and the dropped [`FrameOffset`]s.
+    /// The synthesized [`FrameOffset`]s are removed, and no other one is added.
+    ///
+    /// The sprite should be the one this report was returned for. Frames modified since may not get back their previous [`FrameOffset`].
+    pub fn revert(&self, wan_image: &mut WanImage) {
+        wan_image.sprite_type = self.previous_sprite_type;
+        if let Some(compression) = &self.previous_compression {
+            wan_image.compression = compression.clone();
+        }
+        for frame_id in &self.synthesized_frame_offsets {
+            if let Some(frame) = wan_image.frame_store.frames.get_mut(*frame_id) {
+                frame.frame_offset = None;
+            }
+        }
+        for (frame_id, frame_offset) in &self.dropped_frame_offsets {
+            if let Some(frame) = wan_image.frame_store.frames.get_mut(*frame_id) {
+                frame.frame_offset = Some(frame_offset.clone());
+            }
+        }
+    }
+}
+
+impl WanImage {
+    /// Change the [`SpriteType`] of this sprite, and fix up what the new type needs:
+    /// - the [`CompressionMethod`] is set to [`SpriteType::default_compression_method`], if it was different,
+    /// - the [`FrameOffset`]s are removed if the new type isn't [`SpriteType::Chara`],
+    /// - the frames without [`FrameOffset`] get one if the new type is [`SpriteType::Chara`]. All its points are at the center of the frame (or at its origin if it has no fragment).
+    ///
+    /// The returned [`ConversionReport`] contains what was lost, and allows to undo the conversion.
+    pub fn convert_to(&mut self, sprite_type: SpriteType) -> ConversionReport {
+        let mut report = ConversionReport {
+            previous_sprite_type: self.sprite_type,
+            previous_compression: None,
+            dropped_frame_offsets: Vec::new(),
+            synthesized_frame_offsets: Vec::new(),
+        };
+        self.sprite_type = sprite_type;
+
+        let compression = sprite_type.default_compression_method();
+        if self.compression != compression {
+            report.previous_compression =
+                Some(std::mem::replace(&mut self.compression, compression));
+        }
+
+        for (frame_id, frame) in self.frame_store.frames.iter_mut().enumerate() {
+            if sprite_type == SpriteType::Chara {
+                if frame.frame_offset.is_none() {
+                    let center = match frame.bounding_box() {
+                        Some((x_min, y_min, x_max, y_max)) => {
+                            (((x_min + x_max) / 2) as i16, ((y_min + y_max) / 2) as i16)
+                        }
+                        None => (0, 0),
+                    };
+                    frame.frame_offset = Some(FrameOffset {
+                        head: center,
+                        hand_left: center,
+                        hand_right: center,
+                        center,
+                    });
+                    report.synthesized_frame_offsets.push(frame_id);
+                }
+            } else if let Some(frame_offset) = frame.frame_offset.take() {
+                report.dropped_frame_offsets.push((frame_id, frame_offset));
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        CompressionMethod, Fragment, FragmentFlip, Frame, FrameOffset, OamShape, SpriteType,
+        WanImage,
+    };
+
+    #[test]
+    fn test_convert_to() {
+        let mut wan = WanImage::new(SpriteType::Chara);
+        let frame_offset = FrameOffset {
+            head: (1, 2),
+            hand_left: (3, 4),
+            hand_right: (5, 6),
+            center: (7, 8),
+        };
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![Fragment {
+                unk1: 0,
+                unk3_4: None,
+                unk5: false,
+                fragment_bytes_index: 0,
+                offset_y: -16,
+                offset_x: -8,
+                flip: FragmentFlip::standard(),
+                is_mosaic: false,
+                pal_idx: 0,
+                alloc_counter: None,
+                resolution: OamShape::new(0, 1).unwrap(),
+            }],
+            frame_offset: Some(frame_offset.clone()),
+        });
+        let original_frames = wan.frame_store.frames.clone();
+
+        let report = wan.convert_to(SpriteType::Engine3D);
+        assert_eq!(wan.sprite_type, SpriteType::Engine3D);
+        assert_eq!(wan.compression, CompressionMethod::NoCompression);
+        assert_eq!(
+            report.previous_compression,
+            Some(CompressionMethod::CompressionMethodOriginal)
+        );
+        assert_eq!(report.dropped_frame_offsets, vec![(0, frame_offset)]);
+        assert!(!report.is_lossless());
+        assert_eq!(wan.frame_store.frames[0].frame_offset, None);
+
+        report.revert(&mut wan);
+        assert_eq!(wan.sprite_type, SpriteType::Chara);
+        assert_eq!(
+            wan.compression,
+            CompressionMethod::CompressionMethodOriginal
+        );
+        assert_eq!(wan.frame_store.frames, original_frames);
+
+        // a placeholder is added when converting to Chara
+        wan.convert_to(SpriteType::PropsUI);
+        let report = wan.convert_to(SpriteType::Chara);
+        assert_eq!(report.synthesized_frame_offsets, vec![0]);
+        assert_eq!(
+            wan.frame_store.frames[0]
+                .frame_offset
+                .as_ref()
+                .unwrap()
+                .center,
+            (0, -8)
+        );
+        assert!(wan.to_bytes().is_ok());
+        report.revert(&mut wan);
+        assert_eq!(wan.frame_store.frames[0].frame_offset, None);
+
+        // reverting to Chara doesn't add a placeholder to the frames that had no FrameOffset
+        wan.sprite_type = SpriteType::Chara;
+        wan.frame_store.frames.push(Frame {
+            fragments: wan.frame_store.frames[0].fragments.clone(),
+            frame_offset: Some(FrameOffset {
+                head: (0, 0),
+                hand_left: (0, 0),
+                hand_right: (0, 0),
+                center: (0, 0),
+            }),
+        });
+        let frames = wan.frame_store.frames.clone();
+        let report = wan.convert_to(SpriteType::PropsUI);
+        assert_eq!(report.dropped_frame_offsets.len(), 1);
+        report.revert(&mut wan);
+        assert_eq!(wan.sprite_type, SpriteType::Chara);
+        assert_eq!(wan.frame_store.frames, frames);
+    }
+}
@@ -1,8 +1,7 @@
 use crate::get_bit_u16;
 use crate::FragmentFlip;
 use crate::OamShape;
-use crate::WanError;
-use anyhow::bail;
+use crate::{WanError, WanWriteError};
 use byteorder::WriteBytesExt;
 use byteorder::{ReadBytesExt, LE};
 use std::io::{Read, Write};
@@ -22,6 +21,9 @@ pub struct Fragment {
     pub flip: FragmentFlip,
     pub is_mosaic: bool,
     pub pal_idx: u16,
+    /// The 12 lower bits of the word that also contain the palette index: the position of the fragment in the allocated memory (bits 0 to 9), and two unknown bits (10 and 11, normally set).
+    /// Will be automatically computed if set to None (the default when reading, if it is equal to the computed value)
+    pub alloc_counter: Option<u16>,
     pub resolution: OamShape,
 }
 
@@ -97,6 +99,7 @@ impl Fragment {
                 flip,
                 is_mosaic,
                 pal_idx,
+                alloc_counter: Some(alloc_and_palette & 0x0FFF),
                 resolution: match OamShape::new(size_indice_y, size_indice_x) {
                     Some(r) => r,
                     None => {
@@ -111,24 +114,28 @@ impl Fragment {
         ))
     }
 
+    /// Write this fragment. `frame_id` and `fragment_id` are the position of this fragment, used in the returned errors.
     pub fn write<F: Write>(
         &self,
         file: &mut F,
         previous_fragment_bytes: Option<usize>,
         is_last: bool,
         fragment_alloc_counter: u16,
-    ) -> anyhow::Result<()> {
-        //TODO: use try_into, or maybe even directly i16
-        let fragment_bytes_index: i16 = match previous_fragment_bytes {
-            None => self.fragment_bytes_index as i16,
-            Some(value) => {
-                if self.fragment_bytes_index == value {
-                    -1
-                } else {
-                    self.fragment_bytes_index as i16
-                }
-            }
-        };
+        frame_id: usize,
+        fragment_id: usize,
+    ) -> Result<(), WanWriteError> {
+        let fragment_bytes_index: i16 =
+            if previous_fragment_bytes == Some(self.fragment_bytes_index) {
+                -1
+            } else {
+                self.fragment_bytes_index.try_into().map_err(|_| {
+                    WanWriteError::FragmentBytesIndexTooLarge {
+                        frame_id,
+                        fragment_id,
+                        fragment_bytes_index: self.fragment_bytes_index,
+                    }
+                })?
+            };
 
         file.write_i16::<LE>(fragment_bytes_index)?;
         file.write_u16::<LE>(self.unk1)?;
@@ -147,19 +154,14 @@ impl Fragment {
             + ((unk3 as u16) << 8)
             + ((self.offset_y as u16) & 0x00FF);
 
-        let written_offset_x = self.offset_x + 256;
-        if written_offset_x >= 0x200 {
-            bail!(
-                "The x coordinate of this metaframe is more than 255 (it is {})",
-                self.offset_x
-            );
-        }
-        if written_offset_x < 0 {
-            bail!(
-                "The x coordinate of this metaframe is less than 256 (it is {})",
-                self.offset_x
-            );
+        if !(-256..256).contains(&self.offset_x) {
+            return Err(WanWriteError::FragmentOffsetOutOfRange {
+                frame_id,
+                fragment_id,
+                offset_x: self.offset_x,
+            });
         }
+        let written_offset_x = self.offset_x + 256;
 
         let (v_flip, h_flip) = self.flip.to_bools();
 
@@ -172,9 +174,10 @@ impl Fragment {
 
         file.write_u16::<LE>(offset_y_data)?;
         file.write_u16::<LE>(offset_x_data)?;
-        file.write_u16::<LE>(
-            ((self.pal_idx & 0xF) << 12) + 0x0C00 + (fragment_alloc_counter & 0x3FF),
-        )?;
+        let alloc_counter = self
+            .alloc_counter
+            .unwrap_or(0x0C00 + (fragment_alloc_counter & 0x3FF));
+        file.write_u16::<LE>(((self.pal_idx & 0xF) << 12) + (alloc_counter & 0x0FFF))?;
 
         Ok(())
     }
@@ -2,10 +2,16 @@ use anyhow::{bail, Context};
 use binwrite::BinWrite;
 use byteorder::{ReadBytesExt, LE};
 use image::{ImageBuffer, Rgba};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+};
 use thiserror::Error;
 
-use crate::{CompressionMethod, GeneralResolution, Palette, WanError};
+use crate::{
+    fragment_bytes_compression::write_pixels, CompressionMethod, FragmentBytesWriteError,
+    GeneralResolution, Palette, WanError,
+};
 
 #[derive(Error, Debug)]
 pub enum FragmentBytesToImageError {
@@ -32,10 +38,17 @@ pub struct FragmentBytesAssemblyEntry {
 }
 
 impl FragmentBytesAssemblyEntry {
-    fn new_from_bytes<F: Read>(file: &mut F) -> Result<FragmentBytesAssemblyEntry, WanError> {
+    fn new_from_bytes<F: Read>(
+        file: &mut F,
+        is_256_color: bool,
+    ) -> Result<FragmentBytesAssemblyEntry, WanError> {
         let pixel_src = file.read_u32::<LE>()? as u64;
         let byte_amount = file.read_u16::<LE>()?;
-        let pixel_amount = (byte_amount as u32) * 2;
+        let pixel_amount = if is_256_color {
+            byte_amount as u32
+        } else {
+            (byte_amount as u32) * 2
+        };
         file.read_u16::<LE>()?;
         let z_index = file.read_u32::<LE>()?;
         Ok(FragmentBytesAssemblyEntry {
@@ -50,26 +63,33 @@ impl FragmentBytesAssemblyEntry {
         self.pixel_amount == 0 && self.pixel_src == 0
     }
 
-    pub fn write<F: Write>(&self, file: &mut F) -> Result<(), WanError> {
+    pub fn write<F: Write>(&self, file: &mut F) -> Result<(), FragmentBytesWriteError> {
         (self.pixel_src as u32, self.byte_amount, 0u16, self._z_index).write(file)?;
         Ok(())
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct FragmentBytes {
     pub mixed_pixels: Vec<u8>,
     pub z_index: u32,
 }
 
 impl FragmentBytes {
-    pub fn new_from_bytes<F: Read + Seek>(file: &mut F) -> Result<FragmentBytes, WanError> {
+    /// Read the [`FragmentBytes`] whose assembly table start at the cursor position.
+    /// If `is_256_color` is true, each byte is a pixel (8bpp). Otherwise, each byte contain two pixels (4bpp).
+    /// If `allow_non_contiguous` is false, the parts of the image should be stored one after the other, otherwise [`WanError::IncoherentPointerToFragmentBytesPart`] is returned.
+    pub fn new_from_bytes<F: Read + Seek>(
+        file: &mut F,
+        is_256_color: bool,
+        allow_non_contiguous: bool,
+    ) -> Result<FragmentBytes, WanError> {
         let mut fbytes_asm_table = Vec::new();
         let mut fbytes_size = 0;
 
         let mut last_pointer = None; //for check
         loop {
-            let asm_entry = FragmentBytesAssemblyEntry::new_from_bytes(file)?;
+            let asm_entry = FragmentBytesAssemblyEntry::new_from_bytes(file, is_256_color)?;
             fbytes_size += asm_entry.pixel_amount;
             if asm_entry.is_null() {
                 break;
@@ -79,7 +99,7 @@ impl FragmentBytes {
                     asm_entry.pixel_amount,
                     asm_entry.pixel_src
                 );
-                if asm_entry.pixel_src != 0 {
+                if asm_entry.pixel_src != 0 && !allow_non_contiguous {
                     match last_pointer {
                         None => {
                             last_pointer = Some(asm_entry.pixel_src + asm_entry.byte_amount as u64)
@@ -117,8 +137,12 @@ impl FragmentBytes {
                 file.seek(SeekFrom::Start(entry.pixel_src))?;
                 read_buffer.resize(entry.byte_amount as usize, 0);
                 file.read_exact(&mut read_buffer)?;
-                for pixel_pair in &read_buffer {
-                    mixed_pixels.extend([pixel_pair >> 4, pixel_pair & 0x0F]);
+                if is_256_color {
+                    mixed_pixels.extend(&read_buffer);
+                } else {
+                    for pixel_pair in &read_buffer {
+                        mixed_pixels.extend([pixel_pair >> 4, pixel_pair & 0x0F]);
+                    }
                 }
             };
             // check that all part of the image have the same z index
@@ -161,9 +185,71 @@ impl FragmentBytes {
         &self,
         file: &mut F,
         compression_method: &CompressionMethod,
-    ) -> Result<(u64, Vec<u64>), WanError> {
-        let mut assembly_table = compression_method.compress(self, &self.mixed_pixels, file)?;
+        is_256_color: bool,
+    ) -> Result<(u64, Vec<u64>), FragmentBytesWriteError> {
+        let assembly_table =
+            compression_method.compress(self, &self.mixed_pixels, file, is_256_color)?;
+        Self::write_assembly_table(file, assembly_table)
+    }
 
+    /// Same as [`FragmentBytes::write`], but 64-pixel chunks that are already stored in the file (as listed in `chunk_positions`) aren't written again.
+    /// The assembly table point to the existing data instead, so the parts of the image may not be contiguous.
+    /// Fully transparent chunks aren't stored, as with [`CompressionMethod::CompressionMethodOriginal`].
+    pub fn write_shared<'a, F: Write + Seek>(
+        &'a self,
+        file: &mut F,
+        chunk_positions: &mut HashMap<&'a [u8], u64>,
+        is_256_color: bool,
+    ) -> Result<(u64, Vec<u64>), FragmentBytesWriteError> {
+        if !self.mixed_pixels.len().is_multiple_of(64) {
+            return self.write(file, &CompressionMethod::NoCompression, is_256_color);
+        }
+        if self.mixed_pixels.is_empty() {
+            return Err(FragmentBytesWriteError::EmptyFragmentBytes);
+        }
+        let pixel_per_byte = if is_256_color { 1 } else { 2 };
+        let mut assembly_table: Vec<FragmentBytesAssemblyEntry> = Vec::new();
+        for chunk in self.mixed_pixels.chunks_exact(64) {
+            let pixel_src = if chunk.iter().all(|pixel| *pixel == 0) {
+                0
+            } else if let Some(position) = chunk_positions.get(chunk) {
+                *position
+            } else {
+                let position = file.stream_position()?;
+                write_pixels(file, chunk, is_256_color)?;
+                chunk_positions.insert(chunk, position);
+                position
+            };
+            // merge with the previous entry if it immediately precede this chunk
+            if let Some(previous) = assembly_table.last_mut() {
+                let is_following = if pixel_src == 0 {
+                    previous.pixel_src == 0
+                } else {
+                    previous.pixel_src != 0
+                        && previous.pixel_src + previous.byte_amount as u64 == pixel_src
+                };
+                if is_following {
+                    previous.pixel_amount += 64;
+                    previous.byte_amount += (64 / pixel_per_byte) as u16;
+                    continue;
+                }
+            }
+            assembly_table.push(FragmentBytesAssemblyEntry {
+                pixel_src,
+                pixel_amount: 64,
+                byte_amount: (64 / pixel_per_byte) as u16,
+                _z_index: self.z_index,
+            });
+        }
+        Self::write_assembly_table(file, assembly_table)
+    }
+
+    /// Write the given assembly table, followed by the null entry.
+    /// Return the position of the table, and the position of the pointers it contains.
+    fn write_assembly_table<F: Write + Seek>(
+        file: &mut F,
+        mut assembly_table: Vec<FragmentBytesAssemblyEntry>,
+    ) -> Result<(u64, Vec<u64>), FragmentBytesWriteError> {
         //insert empty entry
         assembly_table.push(FragmentBytesAssemblyEntry {
             pixel_src: 0,
@@ -191,6 +277,7 @@ impl FragmentBytes {
         palette: &Palette,
         resolution: GeneralResolution,
         palette_id: u16,
+        is_256_color: bool,
     ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, FragmentBytesToImageError> {
         if resolution.x == 0 || resolution.y == 0 {
             return Err(FragmentBytesToImageError::ZeroSizedImage);
@@ -199,11 +286,11 @@ impl FragmentBytes {
         let mut pixels: Vec<u8> =
             Vec::with_capacity(resolution.x as usize * resolution.y as usize * 4);
 
-        for pixel in decode_fragment_pixels(&self.mixed_pixels, resolution.clone())? {
+        for pixel in decode_fragment_pixels(&self.mixed_pixels, resolution.clone(), is_256_color)? {
             let mut color = if pixel == 0 {
                 [0, 0, 0, 0]
             } else {
-                match palette.get(pixel, palette_id) {
+                match palette.get(pixel, palette_id, is_256_color) {
                     Some(c) => c,
                     None => return Err(FragmentBytesToImageError::UnknownColor(pixel, palette_id)),
                 }
@@ -232,9 +319,11 @@ pub enum DecodeFragmentBytesError {
 }
 
 /// Take the raw encoded fragment (from an [`ImageBytes`]), and decode them into a list of pixels
+/// 4bpp pixels are stored with each pair of pixels swapped, while 8bpp (`is_256_color`) pixels are stored in order.
 pub fn decode_fragment_pixels(
     pixels: &[u8],
     resolution: GeneralResolution,
+    is_256_color: bool,
 ) -> Result<Vec<u8>, DecodeFragmentBytesError> {
     if resolution.x % 8 != 0 {
         return Err(DecodeFragmentBytesError::XResolutionNotMultipleEight(
@@ -259,12 +348,17 @@ pub fn decode_fragment_pixels(
             let line_start_offset = (chunk_y as usize * 8 + line as usize) * resolution.x as usize
                 + chunk_x as usize * 8;
             for row_pair in 0..4 {
+                let (first, second) = if is_256_color {
+                    (row_pair * 2, row_pair * 2 + 1)
+                } else {
+                    (row_pair * 2 + 1, row_pair * 2)
+                };
                 //no panic : 64 elements are guaranted, and this is looped 8*4=32 times
-                match dest.get_mut(line_start_offset + row_pair * 2 + 1) {
+                match dest.get_mut(line_start_offset + first) {
                     Some(entry) => *entry = *pixel_for_chunk.next().unwrap(),
                     None => break 'main,
                 }
-                dest[line_start_offset + row_pair * 2] = *pixel_for_chunk.next().unwrap();
+                dest[line_start_offset + second] = *pixel_for_chunk.next().unwrap();
             }
         }
         chunk_x += 1;
@@ -276,9 +370,11 @@ pub fn decode_fragment_pixels(
     Ok(dest)
 }
 
+/// The reverse of [`decode_fragment_pixels`]
 pub fn encode_fragment_pixels(
     pixels: &[u8],
     resolution: GeneralResolution,
+    is_256_color: bool,
 ) -> anyhow::Result<Vec<u8>> {
     if resolution.x % 8 != 0 || resolution.y % 8 != 0 {
         bail!(
@@ -307,6 +403,10 @@ pub fn encode_fragment_pixels(
                 if output_buffer.len() < pos_total + 8 {
                     bail!("The input buffer is too small")
                 };
+                if is_256_color {
+                    output_buffer[pos_total..pos_total + 8].copy_from_slice(chunk_row_data);
+                    continue;
+                }
                 //no panic : chunk_row_data is always of length 8
                 output_buffer[pos_total] = chunk_row_data[1];
                 output_buffer[pos_total + 1] = chunk_row_data[0];
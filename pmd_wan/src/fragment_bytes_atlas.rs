@@ -0,0 +1,310 @@
+use image::{imageops, RgbaImage};
+use std::io::Write;
+use thiserror::Error;
+
+use crate::{FragmentBytesToImageError, FragmentFlip, GeneralResolution, OamShape, WanImage};
+
+/// The width of the atlas image generated by [`WanImage::fragment_bytes_atlas`]
+const ATLAS_WIDTH: u32 = 256;
+/// The number of transparent pixels between two entries of the atlas
+const ATLAS_SPACING: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum FragmentBytesAtlasError {
+    #[error("Can't get the image of the FragmentBytes {0}")]
+    FragmentImage(usize, #[source] FragmentBytesToImageError),
+    #[error("Failed to write the manifest")]
+    IOError(#[from] std::io::Error),
+    #[cfg(feature = "atlas_export")]
+    #[error("Failed to encode the PNG")]
+    PngError(#[from] png::EncodingError),
+}
+
+/// A [`crate::Fragment`] that use a [`crate::FragmentBytes`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentBytesReference {
+    pub frame_id: usize,
+    /// The index of the [`crate::Fragment`] in its [`crate::Frame`]
+    pub fragment_id: usize,
+    pub flip: FragmentFlip,
+    pub resolution: GeneralResolution,
+    pub pal_idx: u16,
+}
+
+/// The position and usage of a [`crate::FragmentBytes`] in a [`FragmentBytesAtlas`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentBytesAtlasEntry {
+    /// The index of the [`crate::FragmentBytes`] in the [`crate::FragmentBytesStore`]
+    pub fragment_bytes_index: usize,
+    /// The xy position of the top-left of this entry in the atlas image
+    pub position: (u32, u32),
+    /// The resolution this entry is drawn with. This is the resolution of the first reference, or a guess based on the number of pixels if it is unused.
+    pub resolution: GeneralResolution,
+    /// The number of pixels stored in the [`crate::FragmentBytes`]
+    pub pixel_amount: usize,
+    pub z_index: u32,
+    /// Every [`crate::Fragment`] that use this [`crate::FragmentBytes`]. Empty if it is unused.
+    pub references: Vec<FragmentBytesReference>,
+}
+
+/// The output of [`WanImage::fragment_bytes_atlas`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentBytesAtlas {
+    /// All the [`crate::FragmentBytes`] drawn with the palette of their first reference (or the first sub-palette if unused), and not flipped.
+    pub image: RgbaImage,
+    /// One entry per [`crate::FragmentBytes`], in the same order as the [`crate::FragmentBytesStore`]
+    pub entries: Vec<FragmentBytesAtlasEntry>,
+}
+
+/// Guess the resolution of a [`crate::FragmentBytes`] that isn't referenced, from its number of pixels.
+/// Use the squarest [`OamShape`] with the same number of pixels, or a column of 8×8 tiles if there is none.
+fn guess_resolution(pixel_amount: usize) -> GeneralResolution {
+    for shape_indice in 0..3 {
+        for size_indice in 0..4 {
+            // no panic: the shape and size indices are in the valid range
+            let size = OamShape::new(shape_indice, size_indice).unwrap().size();
+            if size.nb_pixels() == pixel_amount as u64 {
+                return size;
+            }
+        }
+    }
+    GeneralResolution::new(8, (pixel_amount.div_ceil(64) as u32).max(1) * 8)
+}
+
+impl WanImage {
+    /// Draw every [`crate::FragmentBytes`] of the [`crate::FragmentBytesStore`] on a single image, and list which [`crate::Fragment`]s use each of them.
+    ///
+    /// This allow to see which [`crate::FragmentBytes`] are shared or unused. Entries are placed left to right, in rows of up to 256 pixels.
+    pub fn fragment_bytes_atlas(&self) -> Result<FragmentBytesAtlas, FragmentBytesAtlasError> {
+        let mut references: Vec<Vec<FragmentBytesReference>> =
+            vec![Vec::new(); self.fragment_bytes_store.len()];
+        for (frame_id, frame) in self.frame_store.frames.iter().enumerate() {
+            for (fragment_id, fragment) in frame.fragments.iter().enumerate() {
+                if let Some(fragment_references) = references.get_mut(fragment.fragment_bytes_index)
+                {
+                    fragment_references.push(FragmentBytesReference {
+                        frame_id,
+                        fragment_id,
+                        flip: fragment.flip,
+                        resolution: fragment.resolution.size(),
+                        pal_idx: fragment.pal_idx,
+                    });
+                }
+            }
+        }
+
+        let mut entries = Vec::with_capacity(references.len());
+        let mut images = Vec::with_capacity(references.len());
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        let mut atlas_height = 0;
+        for (fragment_bytes_index, (fragment_bytes, references)) in self
+            .fragment_bytes_store
+            .fragment_bytes
+            .iter()
+            .zip(references)
+            .enumerate()
+        {
+            let pixel_amount = fragment_bytes.mixed_pixels.len();
+            let (resolution, pal_idx) = match references.first() {
+                Some(reference) => (reference.resolution.clone(), reference.pal_idx),
+                None => (guess_resolution(pixel_amount), 0),
+            };
+            let image = fragment_bytes
+                .get_image(
+                    &self.palette,
+                    resolution.clone(),
+                    pal_idx,
+                    self.is_256_color,
+                )
+                .map_err(|err| FragmentBytesAtlasError::FragmentImage(fragment_bytes_index, err))?;
+
+            if x != 0 && x + resolution.x > ATLAS_WIDTH {
+                x = 0;
+                y += row_height + ATLAS_SPACING;
+                row_height = 0;
+            }
+            row_height = row_height.max(resolution.y);
+            atlas_height = atlas_height.max(y + resolution.y);
+
+            entries.push(FragmentBytesAtlasEntry {
+                fragment_bytes_index,
+                position: (x, y),
+                resolution: resolution.clone(),
+                pixel_amount,
+                z_index: fragment_bytes.z_index,
+                references,
+            });
+            images.push(image);
+            x += resolution.x + ATLAS_SPACING;
+        }
+
+        let atlas_width = entries
+            .iter()
+            .map(|entry| entry.position.0 + entry.resolution.x)
+            .max()
+            .unwrap_or(0);
+        let mut image = RgbaImage::new(atlas_width, atlas_height);
+        for (entry, entry_image) in entries.iter().zip(images) {
+            imageops::replace(
+                &mut image,
+                &entry_image,
+                entry.position.0 as i64,
+                entry.position.1 as i64,
+            );
+        }
+
+        Ok(FragmentBytesAtlas { image, entries })
+    }
+}
+
+impl FragmentBytesAtlas {
+    /// Write the list of entries as CSV, with one line per [`crate::FragmentBytes`].
+    ///
+    /// The references column list the [`crate::Fragment`]s that use it, separated by `;`, in the form `frame:fragment:flip:widthxheight:palette`.
+    /// The flip is `-` if there is none, or `h` and/or `v` for an horizontal and/or vertical flip.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> Result<(), FragmentBytesAtlasError> {
+        writeln!(
+            writer,
+            "index,x,y,width,height,pixel_amount,z_index,used,references"
+        )?;
+        for entry in &self.entries {
+            let references: Vec<String> = entry
+                .references
+                .iter()
+                .map(|reference| {
+                    let flip = match (reference.flip.flip_h, reference.flip.flip_v) {
+                        (false, false) => "-",
+                        (true, false) => "h",
+                        (false, true) => "v",
+                        (true, true) => "hv",
+                    };
+                    format!(
+                        "{}:{}:{}:{}x{}:{}",
+                        reference.frame_id,
+                        reference.fragment_id,
+                        flip,
+                        reference.resolution.x,
+                        reference.resolution.y,
+                        reference.pal_idx
+                    )
+                })
+                .collect();
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{}",
+                entry.fragment_bytes_index,
+                entry.position.0,
+                entry.position.1,
+                entry.resolution.x,
+                entry.resolution.y,
+                entry.pixel_amount,
+                entry.z_index,
+                !entry.references.is_empty(),
+                references.join(";")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the atlas image as a PNG
+    #[cfg(feature = "atlas_export")]
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), FragmentBytesAtlasError> {
+        let mut encoder = png::Encoder::new(writer, self.image.width(), self.image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(self.image.as_raw())?;
+        png_writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Draw the [`crate::FragmentBytes`] of the sprite in an atlas, writing the image as a PNG and the manifest as CSV.
+/// See [`WanImage::fragment_bytes_atlas`] and [`FragmentBytesAtlas::write_csv`].
+#[cfg(feature = "atlas_export")]
+pub fn export_fragment_bytes_atlas<W1: Write, W2: Write>(
+    wan_image: &WanImage,
+    png_writer: W1,
+    csv_writer: W2,
+) -> Result<(), FragmentBytesAtlasError> {
+    let atlas = wan_image.fragment_bytes_atlas()?;
+    atlas.write_png(png_writer)?;
+    atlas.write_csv(csv_writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use crate::{
+        encode_fragment_pixels, Fragment, FragmentBytes, FragmentFlip, Frame, GeneralResolution,
+        OamShape, SpriteType, WanImage,
+    };
+
+    fn fragment(flip: FragmentFlip, pal_idx: u16) -> Fragment {
+        Fragment {
+            unk1: 0,
+            unk3_4: None,
+            unk5: false,
+            fragment_bytes_index: 0,
+            offset_y: 0,
+            offset_x: 0,
+            flip,
+            is_mosaic: false,
+            pal_idx,
+            alloc_counter: None,
+            resolution: OamShape::new(0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_fragment_bytes_atlas() {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 32];
+        wan.palette.palette[1] = [255, 0, 0, 128];
+        wan.palette.palette[16 + 1] = [0, 255, 0, 128];
+        let mut pixels = vec![0; 16 * 8];
+        pixels[0] = 1;
+        for _ in 0..2 {
+            wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+                mixed_pixels: encode_fragment_pixels(&pixels, GeneralResolution::new(16, 8), false)
+                    .unwrap(),
+                z_index: 1,
+            });
+        }
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![fragment(FragmentFlip::standard(), 0)],
+            frame_offset: None,
+        });
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![fragment(
+                FragmentFlip {
+                    flip_h: true,
+                    flip_v: false,
+                },
+                1,
+            )],
+            frame_offset: None,
+        });
+
+        let atlas = wan.fragment_bytes_atlas().unwrap();
+        assert_eq!(atlas.entries.len(), 2);
+        assert_eq!(atlas.entries[0].references.len(), 2);
+        assert_eq!(atlas.entries[0].resolution, GeneralResolution::new(8, 8));
+        assert_eq!(atlas.entries[1].references.len(), 0);
+        assert_eq!(atlas.entries[1].resolution, GeneralResolution::new(16, 8));
+        assert_eq!(atlas.entries[1].position, (9, 0));
+        assert_eq!(atlas.image.dimensions(), (25, 8));
+        assert_eq!(atlas.image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(atlas.image.get_pixel(9, 0), &Rgba([255, 0, 0, 255]));
+
+        let mut csv = Vec::new();
+        atlas.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "index,x,y,width,height,pixel_amount,z_index,used,references\n\
+            0,0,0,8,8,128,1,true,0:0:-:8x8:0;1:0:h:8x8:1\n\
+            1,9,0,16,8,128,1,false,\n"
+        );
+    }
+}
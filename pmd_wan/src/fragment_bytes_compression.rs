@@ -2,7 +2,27 @@ use std::io::{Seek, SeekFrom, Write};
 
 use byteorder::WriteBytesExt;
 
-use crate::{fragment_bytes::FragmentBytesAssemblyEntry, FragmentBytes, WanError};
+use crate::{fragment_bytes::FragmentBytesAssemblyEntry, FragmentBytes, FragmentBytesWriteError};
+
+/// Write the given pixels, with two pixels per byte, or one pixel per byte if `is_256_color` is true.
+/// Return the number of written bytes.
+pub(crate) fn write_pixels<F: Write>(
+    file: &mut F,
+    pixels: &[u8],
+    is_256_color: bool,
+) -> Result<u32, FragmentBytesWriteError> {
+    if is_256_color {
+        file.write_all(pixels)?;
+        return Ok(pixels.len() as u32);
+    }
+    if let Some(pixel) = pixels.iter().find(|pixel| **pixel >= 16) {
+        return Err(FragmentBytesWriteError::PixelOutOf16ColorRange(*pixel));
+    }
+    for pixel_pair in pixels.chunks_exact(2) {
+        file.write_u8((pixel_pair[0] << 4) + pixel_pair[1])?;
+    }
+    Ok(pixels.len() as u32 / 2)
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum CompressionMethod {
@@ -10,20 +30,28 @@ pub enum CompressionMethod {
     CompressionMethodOriginal,
     /// No compression, used for other sprites in base game
     NoCompression,
-    /* /// An original optimised compression algorithm (TODO: I think it is unfinished, need testing, or maybe just fuzzing)
+    /// Store the transparent parts of the image as separate entries in the assembly table, at a finer granularity than the [`CompressionMethod::CompressionMethodOriginal`] 64-pixel chunks, and merge all the adjacent non-transparent pixels in a single entry.
+    ///
+    /// Each entry of the assembly table takes 12 bytes, so compressing a short transparent part may make the file bigger. A `min_transparent_to_compress` of 48 pixels is a good value for 16 colors sprites.
     CompressionMethodOptimised {
+        /// The transparent and non-transparent parts start and end on a multiple of this number of pixels. Should be a multiple of 2 (or of 1 for 256 colors sprites).
         multiple_of_value: usize,
+        /// The minimal number of consecutive transparent pixels to store in a separate entry
         min_transparent_to_compress: usize,
-    },*/
+    },
 }
 
 impl CompressionMethod {
+    /// Write the pixels, and return the assembly table that allow to reconstruct them.
+    /// Pixels are stored on 8 bits if `is_256_color` is true, and on 4 bits otherwise.
     pub fn compress<F: Write + Seek>(
         &self,
         fragment_bytes: &FragmentBytes,
         pixel_list: &[u8],
         file: &mut F,
-    ) -> Result<Vec<FragmentBytesAssemblyEntry>, WanError> {
+        is_256_color: bool,
+    ) -> Result<Vec<FragmentBytesAssemblyEntry>, FragmentBytesWriteError> {
+        let pixel_per_byte = if is_256_color { 1 } else { 2 };
         let compression = if pixel_list.len() % 64 != 0 {
             CompressionMethod::NoCompression
         } else {
@@ -31,7 +59,7 @@ impl CompressionMethod {
         };
 
         if pixel_list.is_empty() {
-            return Err(WanError::EmptyFragmentBytes);
+            return Err(FragmentBytesWriteError::EmptyFragmentBytes);
         }
 
         let mut assembly_table: Vec<FragmentBytesAssemblyEntry> = vec![];
@@ -52,19 +80,19 @@ impl CompressionMethod {
                         }
                     }
 
-                    fn to_assembly(&self) -> FragmentBytesAssemblyEntry {
+                    fn to_assembly(&self, pixel_per_byte: u32) -> FragmentBytesAssemblyEntry {
                         match self {
                             ActualEntry::Null(lenght, z_index) => FragmentBytesAssemblyEntry {
                                 pixel_src: 0,
                                 pixel_amount: *lenght,
-                                byte_amount: (*lenght / 2) as u16, //NOTE: lenght is always <= than 64x64
+                                byte_amount: (*lenght / pixel_per_byte) as u16, //NOTE: lenght is always <= than 64x64
                                 _z_index: *z_index,
                             },
                             ActualEntry::Some(initial_offset, lenght, z_index) => {
                                 FragmentBytesAssemblyEntry {
                                     pixel_src: *initial_offset,
                                     pixel_amount: *lenght,
-                                    byte_amount: (*lenght / 2) as u16,
+                                    byte_amount: (*lenght / pixel_per_byte) as u16,
                                     _z_index: *z_index,
                                 }
                             }
@@ -96,11 +124,7 @@ impl CompressionMethod {
 
                     let pos_before_area = file.seek(SeekFrom::Current(0))?;
                     if !is_all_black {
-                        for byte_id in 0..32 {
-                            file.write_u8(
-                                (this_area[byte_id * 2] << 4) + this_area[byte_id * 2 + 1],
-                            )?;
-                        }
+                        write_pixels(file, &this_area, is_256_color)?;
                     }
 
                     let need_to_create_new_entry = match &actual_entry {
@@ -111,7 +135,7 @@ impl CompressionMethod {
 
                     actual_entry = if need_to_create_new_entry {
                         if let Some(entry) = actual_entry {
-                            assembly_table.push(entry.to_assembly())
+                            assembly_table.push(entry.to_assembly(pixel_per_byte))
                         }
 
                         Some(ActualEntry::new(
@@ -124,103 +148,80 @@ impl CompressionMethod {
                         Some(actual_entry.unwrap().advance(64))
                     }
                 }
-                assembly_table.push(actual_entry.unwrap().to_assembly())
+                assembly_table.push(actual_entry.unwrap().to_assembly(pixel_per_byte))
             }
-            /*Self::CompressionMethodOptimised {
+            Self::CompressionMethodOptimised {
                 multiple_of_value,
                 min_transparent_to_compress,
             } => {
-                let mut number_of_byte_to_include: u16 = 0;
-                let mut byte_include_start = file.seek(SeekFrom::Current(0))?;
-
-                let mut pixel_id = 0;
-                loop {
-                    debug_assert!(pixel_id % 2 == 0);
-                    let mut should_create_new_transparent_entry = false;
-
-                    if (pixel_id % multiple_of_value == 0)
-                        && (pixel_id + min_transparent_to_compress < pixel_list.len())
-                    {
-                        let mut encontered_non_transparent = false;
-                        for l in 0..min_transparent_to_compress {
-                            if pixel_list[pixel_id + l] != 0 {
-                                encontered_non_transparent = true;
-                                break;
-                            };
+                if multiple_of_value == 0 || multiple_of_value % pixel_per_byte as usize != 0 {
+                    return Err(FragmentBytesWriteError::InvalidCompressionGranularity(
+                        multiple_of_value,
+                    ));
+                }
+                // group the units of pixels in runs of transparent and non-transparent pixels. true for transparent runs
+                let mut runs: Vec<(bool, &[u8])> = Vec::new();
+                let mut run_start = 0;
+                for (unit_nb, unit) in pixel_list.chunks(multiple_of_value).enumerate() {
+                    let is_transparent = unit.iter().all(|pixel| *pixel == 0);
+                    let unit_start = unit_nb * multiple_of_value;
+                    match runs.last_mut() {
+                        Some((last_is_transparent, run))
+                            if *last_is_transparent == is_transparent =>
+                        {
+                            *run = &pixel_list[run_start..unit_start + unit.len()];
                         }
-                        if !encontered_non_transparent {
-                            should_create_new_transparent_entry = true;
-                        };
-                    };
+                        _ => {
+                            run_start = unit_start;
+                            runs.push((is_transparent, unit));
+                        }
+                    }
+                }
 
-                    if should_create_new_transparent_entry {
-                        //push the actual content
-                        if number_of_byte_to_include > 0 {
-                            assembly_table.push(ImageAssemblyEntry {
-                                pixel_src: byte_include_start,
-                                pixel_amount: number_of_byte_to_include as u32 * 2,
-                                byte_amount: number_of_byte_to_include,
-                                _z_index: image.z_index,
+                // transparent runs that are too short are stored with the surrounding pixels
+                let mut pending_data: Option<(u64, u32)> = None; // start offset, byte lenght
+                for (is_transparent, run) in runs {
+                    if is_transparent && run.len() >= min_transparent_to_compress {
+                        if let Some((start_offset, byte_len)) = pending_data.take() {
+                            assembly_table.push(FragmentBytesAssemblyEntry {
+                                pixel_src: start_offset,
+                                pixel_amount: byte_len * pixel_per_byte,
+                                byte_amount: byte_len as u16,
+                                _z_index: fragment_bytes.z_index,
                             });
-                            number_of_byte_to_include = 0;
-                            byte_include_start = file.seek(SeekFrom::Current(0))?;
-                        };
-                        //create new entry for transparent stuff
-                        //count the number of transparent tile
-                        let mut transparent_tile_nb: u32 = 0; //TODO: somehow guarantee it never gets bigger than (16^2)*2
-                        loop {
-                            if pixel_id >= pixel_list.len() {
-                                break;
-                            };
-                            if pixel_list[pixel_id] == 0 {
-                                transparent_tile_nb += 1;
-                                pixel_id += 1;
-                            } else {
-                                break;
-                            };
                         }
-                        if pixel_id % multiple_of_value != 0 {
-                            transparent_tile_nb -= (pixel_id % multiple_of_value) as u32;
-                            pixel_id -= pixel_id % multiple_of_value;
-                        };
-                        assembly_table.push(ImageAssemblyEntry {
+                        assembly_table.push(FragmentBytesAssemblyEntry {
                             pixel_src: 0,
-                            pixel_amount: transparent_tile_nb,
-                            byte_amount: (transparent_tile_nb / 2) as u16, //TODO: take care of the tileset lenght
-                            _z_index: image.z_index,
+                            pixel_amount: run.len() as u32,
+                            byte_amount: (run.len() as u32 / pixel_per_byte) as u16,
+                            _z_index: fragment_bytes.z_index,
                         });
-
-                        continue;
-                    };
-
-                    if pixel_id >= pixel_list.len() {
-                        break;
-                    };
-                    debug_assert!(pixel_list[pixel_id] < 16);
-                    debug_assert!(pixel_list[pixel_id + 1] < 16);
-                    file.write_u8(((pixel_list[pixel_id] << 4) + pixel_list[pixel_id + 1]) as u8)?;
-                    pixel_id += 2;
-                    number_of_byte_to_include += 1;
+                    } else {
+                        let start_offset = file.stream_position()?;
+                        let byte_len = write_pixels(file, run, is_256_color)?;
+                        pending_data = Some(match pending_data {
+                            Some((previous_start, previous_len)) => {
+                                (previous_start, previous_len + byte_len)
+                            }
+                            None => (start_offset, byte_len),
+                        });
+                    }
                 }
-                if number_of_byte_to_include > 0 {
-                    assembly_table.push(ImageAssemblyEntry {
-                        pixel_src: byte_include_start,
-                        pixel_amount: number_of_byte_to_include as u32 * 2,
-                        byte_amount: number_of_byte_to_include,
-                        _z_index: image.z_index,
+                if let Some((start_offset, byte_len)) = pending_data {
+                    assembly_table.push(FragmentBytesAssemblyEntry {
+                        pixel_src: start_offset,
+                        pixel_amount: byte_len * pixel_per_byte,
+                        byte_amount: byte_len as u16,
+                        _z_index: fragment_bytes.z_index,
                     });
-                };
-            }*/
+                }
+            }
             Self::NoCompression => {
-                let mut byte_len = 0;
                 let start_offset = file.seek(SeekFrom::Current(0))?;
-                for pixels in pixel_list.chunks_exact(2) {
-                    file.write_u8((pixels[0] << 4) + pixels[1])?;
-                    byte_len += 1;
-                }
+                let byte_len = write_pixels(file, pixel_list, is_256_color)?;
                 assembly_table.push(FragmentBytesAssemblyEntry {
                     pixel_src: start_offset,
-                    pixel_amount: byte_len * 2,
+                    pixel_amount: byte_len * pixel_per_byte,
                     byte_amount: byte_len as u16,
                     _z_index: fragment_bytes.z_index,
                 })
@@ -229,3 +230,59 @@ impl CompressionMethod {
         Ok(assembly_table)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    use crate::{CompressionMethod, FragmentBytes, FragmentBytesWriteError};
+
+    #[test]
+    fn test_optimised_compression() {
+        let mut pixels = vec![0; 128];
+        pixels[0..8].copy_from_slice(&[1; 8]);
+        // 8 transparent pixels, that are too short to be compressed
+        pixels[16..24].copy_from_slice(&[2; 8]);
+        // 40 transparent pixels
+        pixels[64..80].copy_from_slice(&[3; 16]);
+        let fragment_bytes = FragmentBytes {
+            mixed_pixels: pixels.clone(),
+            z_index: 2,
+        };
+        let compression = CompressionMethod::CompressionMethodOptimised {
+            multiple_of_value: 8,
+            min_transparent_to_compress: 16,
+        };
+
+        let mut file = Cursor::new(vec![0xAA; 4]);
+        file.seek(SeekFrom::End(0)).unwrap();
+        let assembly_table = compression
+            .compress(&fragment_bytes, &pixels, &mut file, false)
+            .unwrap();
+        let entries: Vec<(u64, u32)> = assembly_table
+            .iter()
+            .map(|entry| (entry.pixel_src, entry.pixel_amount))
+            .collect();
+        assert_eq!(entries, vec![(4, 24), (0, 40), (16, 16), (0, 48)]);
+
+        let mut file = Cursor::new(vec![0xAA; 4]);
+        file.seek(SeekFrom::End(0)).unwrap();
+        let (assembly_table_offset, _) = fragment_bytes
+            .write(&mut file, &compression, false)
+            .unwrap();
+        file.seek(SeekFrom::Start(assembly_table_offset)).unwrap();
+        assert_eq!(
+            FragmentBytes::new_from_bytes(&mut file, false, false).unwrap(),
+            fragment_bytes
+        );
+
+        let invalid_compression = CompressionMethod::CompressionMethodOptimised {
+            multiple_of_value: 3,
+            min_transparent_to_compress: 16,
+        };
+        assert!(matches!(
+            invalid_compression.compress(&fragment_bytes, &pixels, &mut file, false),
+            Err(FragmentBytesWriteError::InvalidCompressionGranularity(3))
+        ));
+    }
+}
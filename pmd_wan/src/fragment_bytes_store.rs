@@ -1,6 +1,9 @@
-use crate::{CompressionMethod, FragmentBytes, WanError};
+use crate::{CompressionMethod, FragmentBytes, WanError, WanWriteError};
 use byteorder::{ReadBytesExt, LE};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+};
 
 #[derive(PartialEq, Eq, Debug, Default)]
 pub struct FragmentBytesStore {
@@ -11,6 +14,8 @@ impl FragmentBytesStore {
     pub fn new_from_bytes<F: Read + Seek>(
         file: &mut F,
         amount_fragments_bytes: u32,
+        is_256_color: bool,
+        allow_non_contiguous: bool,
     ) -> Result<FragmentBytesStore, WanError> {
         trace!("will read {} FragmentBytes", amount_fragments_bytes);
         let mut fragment_bytes_pointers: Vec<u64> = Vec::new(); //list of reference to FragmentBytes
@@ -32,7 +37,7 @@ impl FragmentBytesStore {
                 fragment_bytes_addr
             );
             file.seek(SeekFrom::Start(*fragment_bytes_addr))?;
-            let img = FragmentBytes::new_from_bytes(file)?;
+            let img = FragmentBytes::new_from_bytes(file, is_256_color, allow_non_contiguous)?;
             fragment_bytes.push(img);
         }
 
@@ -47,21 +52,30 @@ impl FragmentBytesStore {
         self.len() == 0
     }
 
+    /// Write all the [`FragmentBytes`], followed by their assembly table.
+    /// If `share_pixel_chunks` is true, identical 64-pixel chunks are only written once, and the compression method is ignored (see [`FragmentBytes::write_shared`]).
     pub fn write<F: Write + Seek>(
         &self,
         file: &mut F,
         compression: &CompressionMethod,
-    ) -> Result<(Vec<u64>, Vec<u64>), WanError> {
+        is_256_color: bool,
+        share_pixel_chunks: bool,
+    ) -> Result<(Vec<u64>, Vec<u64>), WanWriteError> {
         let mut fragment_bytes_addr = vec![];
         let mut sir0_pointer_fragments_bytes = vec![];
+        let mut chunk_positions = HashMap::new();
 
-        for fragment_bytes in &self.fragment_bytes {
+        for (fragment_bytes_id, fragment_bytes) in self.fragment_bytes.iter().enumerate() {
             trace!(
                 "fragment bytes wrote at {}",
                 file.seek(SeekFrom::Current(0))?
             );
-            let (assembly_table_offset, sir0_img_pointer) =
-                fragment_bytes.write(file, compression)?;
+            let (assembly_table_offset, sir0_img_pointer) = if share_pixel_chunks {
+                fragment_bytes.write_shared(file, &mut chunk_positions, is_256_color)
+            } else {
+                fragment_bytes.write(file, compression, is_256_color)
+            }
+            .map_err(|err| WanWriteError::FragmentBytesError(fragment_bytes_id, err))?;
             for pointer in sir0_img_pointer {
                 sir0_pointer_fragments_bytes.push(pointer)
             }
@@ -165,7 +165,7 @@ mod tests {
                 x: -7,
                 y: 3,
                 image_id: 0,
-                flip: FragmentFlip::horizontal()
+                flip: FragmentFlip::vertical()
             }));
         assert_eq!(found.collected.get(&fragment_first).unwrap().len(), 2);
     }
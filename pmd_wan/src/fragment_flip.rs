@@ -43,6 +43,7 @@ impl FragmentFlip {
     /// Flip a tile with the corresponding flip value
     /// source and target are image using an u8 per pixel, row by row, from top-left most to bottom-right.
     /// target and source should have the correct number of pixel. Otherwise, and error is returned.
+    /// A horizontal flip mirrors each row (left-right), a vertical flip reverses the order of the rows (top-bottom), like the DS does.
     pub fn apply(
         self,
         source: &[u8],
@@ -62,7 +63,7 @@ impl FragmentFlip {
                 // no panic: both have the number of pixels corresponding to the resolution
                 target.copy_from_slice(source);
             }
-            FLIP_HORIZONTAL => {
+            FLIP_VERTICAL => {
                 // no panic: checked for 0 pixel list before. To have at least one pixel, x should be at least one
                 for (source_chunk, target_chunk) in source
                     .chunks_exact(resolution.x as usize)
@@ -71,7 +72,7 @@ impl FragmentFlip {
                     target_chunk.copy_from_slice(source_chunk);
                 }
             }
-            FLIP_VERTICAL => {
+            FLIP_HORIZONTAL => {
                 // no panic: as before
                 for (source_chunk, target_chunk) in source
                     .chunks_exact(resolution.x as usize)
@@ -122,7 +123,7 @@ mod tests {
             .unwrap();
         assert_eq!(target_4x4, test_data_4x4);
 
-        (FragmentFlip::horizontal())
+        (FragmentFlip::vertical())
             .apply(&test_data_4x4, resolution.clone(), &mut target_4x4)
             .unwrap();
         assert_eq!(
@@ -130,7 +131,7 @@ mod tests {
             [4, 3, 2, 1, 8, 9, 10, 11, 4, 5, 6, 7, 0, 1, 2, 3]
         );
 
-        (FragmentFlip::vertical())
+        (FragmentFlip::horizontal())
             .apply(&test_data_4x4, resolution.clone(), &mut target_4x4)
             .unwrap();
         assert_eq!(
@@ -167,7 +168,7 @@ mod tests {
             Err(FragmentFlipError::IncoherentResolution)
         );
 
-        (FragmentFlip::vertical())
+        (FragmentFlip::horizontal())
             .apply(
                 &test_data_4x4,
                 GeneralResolution::new(2, 8),
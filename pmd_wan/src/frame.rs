@@ -1,6 +1,4 @@
-use anyhow::{bail, Context};
-
-use crate::{Fragment, FrameOffset, WanError};
+use crate::{Fragment, FrameOffset, WanError, WanWriteError};
 use std::io::{Read, Write};
 
 /// A single frame of animation
@@ -15,9 +13,15 @@ impl Frame {
     pub fn new_from_bytes<F: Read>(file: &mut F) -> Result<Frame, WanError> {
         let mut fragments = Vec::new();
         let mut previous_fragment_bytes = None;
+        let mut fragment_alloc_counter = 0;
         loop {
-            let (fragment, is_last) = Fragment::new_from_bytes(file, previous_fragment_bytes)?;
+            let (mut fragment, is_last) = Fragment::new_from_bytes(file, previous_fragment_bytes)?;
             previous_fragment_bytes = Some(fragment.fragment_bytes_index);
+            // only keep the allocation counter if it differ from the computed one
+            if fragment.alloc_counter == Some(0x0C00 + (fragment_alloc_counter & 0x3FF)) {
+                fragment.alloc_counter = None;
+            }
+            fragment_alloc_counter += fragment.resolution.chunk_to_allocate_for_fragment();
             fragments.push(fragment);
             trace!("its data: {:?}", fragments[fragments.len() - 1]);
             if is_last {
@@ -30,22 +34,24 @@ impl Frame {
         })
     }
 
+    /// Write this frame. `frame_id` is the index of this frame, used in the returned errors.
+    ///
     /// Returns: size to allocate for the fragments of this frame
-    pub fn write<F: Write>(&self, file: &mut F) -> anyhow::Result<u16> {
+    pub fn write<F: Write>(&self, file: &mut F, frame_id: usize) -> Result<u16, WanWriteError> {
         let mut previous_fragment_bytes: Option<usize> = None;
         if self.fragments.is_empty() {
-            bail!("A frame has no fragment, which can’t be encoded.");
+            return Err(WanWriteError::EmptyFrame { frame_id });
         }
         let mut fragment_alloc_counter = 0;
         for (fragment_nb, fragment) in self.fragments.iter().enumerate() {
-            fragment
-                .write(
-                    file,
-                    previous_fragment_bytes,
-                    fragment_nb + 1 == self.fragments.len(),
-                    fragment_alloc_counter,
-                )
-                .with_context(move || format!("Can't write the fragment {:?}", fragment))?;
+            fragment.write(
+                file,
+                previous_fragment_bytes,
+                fragment_nb + 1 == self.fragments.len(),
+                fragment_alloc_counter,
+                frame_id,
+                fragment_nb,
+            )?;
             fragment_alloc_counter += fragment.resolution.chunk_to_allocate_for_fragment();
             previous_fragment_bytes = Some(fragment.fragment_bytes_index);
         }
@@ -59,4 +65,24 @@ impl Frame {
             .map(|f| f.resolution.chunk_to_allocate_for_fragment())
             .sum()
     }
+
+    /// Return the smallest rectangle containing all the [`Fragment`]s of this frame, relative to the origin of the frame.
+    /// The result is `(x_min, y_min, x_max, y_max)`, with the max values being exclusive.
+    /// Return [`None`] if there are no fragment.
+    pub fn bounding_box(&self) -> Option<(i32, i32, i32, i32)> {
+        let mut result: Option<(i32, i32, i32, i32)> = None;
+        for fragment in &self.fragments {
+            let size = fragment.resolution.size();
+            let x_min = fragment.offset_x as i32;
+            let y_min = fragment.offset_y as i32;
+            //No overflow: OamShape.size() values are always <= 64
+            let x_max = x_min + size.x as i32;
+            let y_max = y_min + size.y as i32;
+            result = Some(match result {
+                None => (x_min, y_min, x_max, y_max),
+                Some((a, b, c, d)) => (a.min(x_min), b.min(y_min), c.max(x_max), d.max(y_max)),
+            });
+        }
+        result
+    }
 }
@@ -2,7 +2,7 @@ use binread::BinRead;
 use binwrite::BinWrite;
 
 /// The coordinate of some point in the Pokémon, in the form of X then Y
-#[derive(BinWrite, BinRead, Debug, PartialEq, Eq, Clone)]
+#[derive(BinWrite, BinRead, Debug, PartialEq, Eq, Clone, Hash)]
 #[binwrite(little)]
 #[br(little)]
 pub struct FrameOffset {
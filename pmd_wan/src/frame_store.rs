@@ -1,5 +1,4 @@
-use crate::{Frame, WanError};
-use anyhow::Context;
+use crate::{Frame, WanError, WanWriteError};
 use byteorder::{ReadBytesExt, LE};
 use std::io::{Read, Seek, SeekFrom, Write};
 
@@ -15,6 +14,25 @@ impl FrameStore {
         nb_frames: u64,
     ) -> Result<FrameStore, WanError> {
         let mut frames = Vec::new();
+        let fragment_reference = Self::read_frame_pointers(file, nb_frames)?;
+
+        for frame_id in 0..nb_frames {
+            trace!(
+                "parsing frame n°{} (at offset {})",
+                frame_id,
+                fragment_reference[frame_id as usize]
+            );
+            file.seek(SeekFrom::Start(fragment_reference[frame_id as usize]))?;
+            frames.push(Frame::new_from_bytes(file)?);
+        }
+        Ok(FrameStore { frames })
+    }
+
+    /// Read and check the table of pointers to each [`Frame`]. Assume the cursor is located at the start of the table.
+    pub(crate) fn read_frame_pointers<F: Read>(
+        file: &mut F,
+        nb_frames: u64,
+    ) -> Result<Vec<u64>, WanError> {
         let mut last_pointer = None;
 
         let mut fragment_reference: Vec<u64> = Vec::new();
@@ -36,29 +54,20 @@ impl FrameStore {
             };
             fragment_reference.push(actual_ptr);
         }
-
-        for frame_id in 0..nb_frames {
-            trace!(
-                "parsing frame n°{} (at offset {})",
-                frame_id,
-                fragment_reference[frame_id as usize]
-            );
-            file.seek(SeekFrom::Start(fragment_reference[frame_id as usize]))?;
-            frames.push(Frame::new_from_bytes(file)?);
-        }
-        Ok(FrameStore { frames })
+        Ok(fragment_reference)
     }
 
     //Return: (List of offset to the encoded frames, max allocation size for a frame)
-    pub fn write<F: Write + Seek>(&self, file: &mut F) -> anyhow::Result<(Vec<u32>, u16)> {
+    pub fn write<F: Write + Seek>(&self, file: &mut F) -> Result<(Vec<u32>, u16), WanWriteError> {
+        if self.frames.len() > u16::MAX as usize {
+            return Err(WanWriteError::TooManyFrames(self.frames.len()));
+        }
         let mut frame_references = vec![];
         let mut size_to_allocate = 0;
 
-        for frame in &self.frames {
+        for (frame_id, frame) in self.frames.iter().enumerate() {
             frame_references.push(file.seek(SeekFrom::Current(0))? as u32);
-            let local_size_to_allocate = frame
-                .write(file)
-                .with_context(move || format!("can't write the frame group {:?}", frame))?;
+            let local_size_to_allocate = frame.write(file, frame_id)?;
             size_to_allocate = size_to_allocate.max(local_size_to_allocate);
         }
 
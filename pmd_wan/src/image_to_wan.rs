@@ -1,34 +1,101 @@
 use crate::{
     encode_fragment_pixels, Fragment, FragmentBytes, FragmentFlip, Frame, GeneralResolution,
-    ImageBuffer, OamShape, WanImage,
+    ImageBuffer, OamShape, PaletteMapping, WanImage, WanWriteError,
 };
-use anyhow::{bail, Context};
-use std::convert::TryInto;
+use std::{collections::BTreeSet, convert::TryInto};
 
+/// Insert a new [`Frame`] with the given paletted image, centered on the origin, using the sub-palette `pal_id`.
+/// The pixel values are used as is, and thus should be less than 16.
+/// Return the id of the new frame, or [`None`] if the image has no visible pixel.
 pub fn insert_frame_in_wanimage(
     image: Vec<u8>,
     width: u16,
     height: u16,
     wanimage: &mut WanImage,
     pal_id: u16,
-) -> anyhow::Result<Option<usize>> {
-    if height >= 256 {
-        bail!("The height of the image is {}, while only image with a height inferior to 256 can be used", height);
+) -> Result<Option<usize>, WanWriteError> {
+    if !wanimage.is_256_color {
+        if let Some(position) = image.iter().position(|pixel| *pixel >= 16) {
+            return Err(WanWriteError::PixelOutOf16ColorRange {
+                x: (position % width.max(1) as usize) as u16,
+                y: (position / width.max(1) as usize) as u16,
+                pixel: image[position],
+            });
+        }
     }
-    if width >= 512 {
-        bail!(
-            "The width of the image is {}, while only image with a width less than 512 can be used",
-            width
-        );
+    insert_frame_in_wanimage_internal(image, width, height, wanimage, pal_id, None, None)
+}
+
+/// Insert a new [`Frame`] with the given paletted image, centered on the origin, using the sub-palettes of the [`WanImage`]'s [`crate::Palette`].
+///
+/// `colors` is the color of each pixel value (with the alpha ranging from 0 to 255).
+///
+/// `tile_sub_palettes` is the sub-palette of each 8×8 tile of the image, line by line, as returned by [`crate::image_tool::cluster_sub_palettes`].
+/// If it is given, the image is split in fragments that each cover tiles with a single sub-palette, which is used for them.
+/// Otherwise, each [`Fragment`] (which can be up to 64×64) use the first sub-palette that contains all of its colors.
+/// An error is returned if a fragment can't be drawn with its sub-palette. See [`PaletteMapping`].
+pub fn insert_frame_in_wanimage_with_palette(
+    image: Vec<u8>,
+    width: u16,
+    height: u16,
+    wanimage: &mut WanImage,
+    colors: &[[u8; 4]],
+    tile_sub_palettes: Option<&[u16]>,
+) -> Result<Option<usize>, WanWriteError> {
+    if let Some(tile_sub_palettes) = tile_sub_palettes {
+        let tile_amount = width.div_ceil(8) as usize * height.div_ceil(8) as usize;
+        if tile_sub_palettes.len() != tile_amount {
+            return Err(WanWriteError::TileSubPalettesMismatch {
+                width,
+                height,
+                tile_amount: tile_sub_palettes.len(),
+            });
+        }
+    }
+    let mapping = PaletteMapping::new(colors, &wanimage.palette);
+    insert_frame_in_wanimage_internal(
+        image,
+        width,
+        height,
+        wanimage,
+        0,
+        Some(&mapping),
+        tile_sub_palettes,
+    )
+}
+
+fn insert_frame_in_wanimage_internal(
+    image: Vec<u8>,
+    width: u16,
+    height: u16,
+    wanimage: &mut WanImage,
+    pal_id: u16,
+    mapping: Option<&PaletteMapping>,
+    tile_sub_palettes: Option<&[u16]>,
+) -> Result<Option<usize>, WanWriteError> {
+    if height >= 256 || width >= 512 {
+        return Err(WanWriteError::ImageTooLarge { width, height });
     }
     let position_x = -(width as i32) / 2;
     let position_y = -(height as i32) / 2;
-    let image_buffer = ImageBuffer::new_from_vec(image, width, height)
-        .context("The input image don't correspond to the dimension of it")?;
+    let pixel_amount = image.len();
+    let image_buffer = ImageBuffer::new_from_vec(image, width, height).ok_or(
+        WanWriteError::ImageSizeMismatch {
+            width,
+            height,
+            pixel_amount,
+        },
+    )?;
 
-    let fragments = if let Some(fragments) =
-        insert_fragment_pos_in_wan_image(wanimage, pal_id, &image_buffer, position_x, position_y)?
-    {
+    let fragments = if let Some(fragments) = insert_fragment_pos_in_wan_image(
+        wanimage,
+        pal_id,
+        mapping,
+        tile_sub_palettes,
+        &image_buffer,
+        position_x,
+        position_y,
+    )? {
         fragments
     } else {
         return Ok(None);
@@ -46,13 +113,46 @@ pub fn insert_frame_in_wanimage(
     })
 }
 
+/// Return the sub-palettes used by the non-transparent 8×8 tiles of the given square of the image
+fn sub_palettes_in_region(
+    image_buffer: &ImageBuffer,
+    tile_sub_palettes: &[u16],
+    region_x: u16,
+    region_y: u16,
+    region_size: u16,
+) -> BTreeSet<u16> {
+    let tiles_x = image_buffer.width().div_ceil(8);
+    let mut result = BTreeSet::new();
+    for tile_y in
+        (region_y / 8..(region_y + region_size) / 8).take_while(|y| y * 8 < image_buffer.height())
+    {
+        for tile_x in (region_x / 8..(region_x + region_size) / 8)
+            .take_while(|x| x * 8 < image_buffer.width())
+        {
+            if image_buffer
+                .get_fragment(tile_x * 8, tile_y * 8, 8, 8, 0)
+                .buffer()
+                .iter()
+                .any(|pixel| *pixel != 0)
+            {
+                result.insert(tile_sub_palettes[(tile_y * tiles_x + tile_x) as usize]);
+            }
+        }
+    }
+    result
+}
+
+/// If `mapping` is given, it is used to choose the sub-palette of each fragment instead of `pal_id`.
+/// If `tile_sub_palettes` is also given, the fragments are split so they only cover tiles with the same sub-palette.
 fn insert_fragment_pos_in_wan_image(
     wanimage: &mut WanImage,
     pal_id: u16,
+    mapping: Option<&PaletteMapping>,
+    tile_sub_palettes: Option<&[u16]>,
     image_buffer: &ImageBuffer,
     upper_image_x: i32,
     upper_image_y: i32,
-) -> anyhow::Result<Option<Vec<Fragment>>> {
+) -> Result<Option<Vec<Fragment>>, WanWriteError> {
     let mut fragments = Vec::new();
 
     // Chunk the image into 64x64 group, the max meta frame size
@@ -63,68 +163,118 @@ fn insert_fragment_pos_in_wan_image(
         for fragment_segment_y in
             0..(image_buffer.height() + MAX_META_FRAME_SIZE - 1) / MAX_META_FRAME_SIZE
         {
-            let mut fragment_x =
-                upper_image_x + MAX_META_FRAME_SIZE as i32 * fragment_segment_x as i32;
-            let mut fragment_y =
-                upper_image_y + MAX_META_FRAME_SIZE as i32 * fragment_segment_y as i32;
-
-            let mut cut_section = image_buffer.get_fragment(
+            // squares of the image to place in a fragment, split in four when they use multiple sub-palettes
+            let mut regions = vec![(
                 MAX_META_FRAME_SIZE * fragment_segment_x,
                 MAX_META_FRAME_SIZE * fragment_segment_y,
                 MAX_META_FRAME_SIZE,
-                MAX_META_FRAME_SIZE,
-                0,
-            );
-            fragment_y += cut_section.cut_top() as i32;
-            cut_section.cut_bottom();
-            fragment_x += cut_section.cut_left() as i32;
-            cut_section.cut_right();
-
-            if !cut_section.have_pixel() {
-                continue;
-            }
+            )];
+            while let Some((region_x, region_y, region_size)) = regions.pop() {
+                let region_sub_palette = match tile_sub_palettes {
+                    None => None,
+                    Some(tile_sub_palettes) => {
+                        let sub_palettes = sub_palettes_in_region(
+                            image_buffer,
+                            tile_sub_palettes,
+                            region_x,
+                            region_y,
+                            region_size,
+                        );
+                        if sub_palettes.len() > 1 {
+                            // the top-left quarter is the last pushed, to be processed first
+                            let half = region_size / 2;
+                            regions.push((region_x + half, region_y + half, half));
+                            regions.push((region_x, region_y + half, half));
+                            regions.push((region_x + half, region_y, half));
+                            regions.push((region_x, region_y, half));
+                            continue;
+                        }
+                        sub_palettes.first().copied()
+                    }
+                };
 
-            //no panic: resolution should always be less than 64x64, and be an already valid resolution, to which it can fall back if no smaller images are avalaible
-            let fragment_size = OamShape::find_smallest_containing(GeneralResolution::new(
-                cut_section.width().into(),
-                cut_section.height().into(),
-            ))
-            .unwrap();
+                let mut fragment_x = upper_image_x + region_x as i32;
+                let mut fragment_y = upper_image_y + region_y as i32;
 
-            let buffer_to_write = cut_section.get_fragment(
-                0,
-                0,
-                fragment_size.size().x as u16,
-                fragment_size.size().y as u16,
-                0,
-            );
-
-            let image_bytes_index = wanimage.fragment_bytes_store.fragment_bytes.len();
-            wanimage
-                .fragment_bytes_store
-                .fragment_bytes
-                .push(FragmentBytes {
-                    mixed_pixels: encode_fragment_pixels(
-                        buffer_to_write.buffer(),
-                        fragment_size.size(),
-                    )
-                    .context("failed to encode the input byte. This is an internal error")?,
-                    z_index: 1,
-                });
+                let mut cut_section =
+                    image_buffer.get_fragment(region_x, region_y, region_size, region_size, 0);
+                fragment_y += cut_section.cut_top() as i32;
+                cut_section.cut_bottom();
+                fragment_x += cut_section.cut_left() as i32;
+                cut_section.cut_right();
 
-            let offset_y = fragment_y.try_into().context("The image is too large")?;
-            fragments.push(Fragment {
-                unk1: 0,
-                unk3_4: None,
-                unk5: false,
-                fragment_bytes_index: image_bytes_index,
-                offset_y,
-                offset_x: fragment_x.try_into().context("The image is too high")?,
-                flip: FragmentFlip::standard(),
-                is_mosaic: false,
-                pal_idx: pal_id,
-                resolution: fragment_size,
-            });
+                if !cut_section.have_pixel() {
+                    continue;
+                }
+
+                //no panic: resolution should always be less than 64x64, and be an already valid resolution, to which it can fall back if no smaller images are avalaible
+                let fragment_size = OamShape::find_smallest_containing(GeneralResolution::new(
+                    cut_section.width().into(),
+                    cut_section.height().into(),
+                ))
+                .unwrap();
+
+                let buffer_to_write = cut_section.get_fragment(
+                    0,
+                    0,
+                    fragment_size.size().x as u16,
+                    fragment_size.size().y as u16,
+                    0,
+                );
+
+                let (pixels, pal_idx) = match mapping {
+                    None => (buffer_to_write.buffer().to_vec(), pal_id),
+                    Some(mapping) => {
+                        let no_sub_palette = || WanWriteError::NoSubPaletteForFragment {
+                            x: fragment_x,
+                            y: fragment_y,
+                        };
+                        let sub_palette = match region_sub_palette {
+                            Some(sub_palette) => sub_palette,
+                            None => mapping
+                                .find_sub_palette(buffer_to_write.buffer())
+                                .ok_or_else(no_sub_palette)?,
+                        };
+                        (
+                            mapping
+                                .remap(sub_palette, buffer_to_write.buffer())
+                                .ok_or_else(no_sub_palette)?,
+                            sub_palette,
+                        )
+                    }
+                };
+
+                let image_bytes_index = wanimage.fragment_bytes_store.fragment_bytes.len();
+                wanimage
+                    .fragment_bytes_store
+                    .fragment_bytes
+                    .push(FragmentBytes {
+                        // no panic: the buffer has the size of the resolution, which is a valid OamShape
+                        mixed_pixels: encode_fragment_pixels(
+                            &pixels,
+                            fragment_size.size(),
+                            wanimage.is_256_color,
+                        )
+                        .unwrap(),
+                        z_index: 1,
+                    });
+
+                // no panic: the size of the image was checked, so the fragment is within -128..128 in y and -256..256 in x
+                let offset_y = fragment_y.try_into().unwrap();
+                fragments.push(Fragment {
+                    unk1: 0,
+                    unk3_4: None,
+                    unk5: false,
+                    fragment_bytes_index: image_bytes_index,
+                    offset_y,
+                    offset_x: fragment_x.try_into().unwrap(),
+                    flip: FragmentFlip::standard(),
+                    is_mosaic: false,
+                    pal_idx,
+                    alloc_counter: None,
+                    resolution: fragment_size,
+                });
+            }
         }
     }
 
@@ -230,3 +380,21 @@ fn insert_empty_image_test() {
         .unwrap()
         .is_none());
 }
+
+#[test]
+fn insert_frame_with_palette_test() {
+    let mut wanimage = WanImage::new(crate::SpriteType::PropsUI);
+    wanimage.palette.palette = vec![[0, 0, 0, 0]; 32];
+    wanimage.palette.palette[16 + 3] = [255, 255, 255, 128];
+    let colors = [[0, 0, 0, 0], [0, 0, 0, 0], [255, 255, 255, 255]];
+    let frame_id =
+        insert_frame_in_wanimage_with_palette(vec![2; 36], 6, 6, &mut wanimage, &colors, None)
+            .unwrap()
+            .unwrap();
+    let fragment = &wanimage.frame_store.frames[frame_id].fragments[0];
+    assert_eq!(fragment.pal_idx, 1);
+    let (image, _) = wanimage.render_frame(frame_id).unwrap();
+    assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+    assert!(insert_frame_in_wanimage(vec![16; 36], 6, 6, &mut wanimage, 0).is_err());
+}
@@ -1,6 +1,12 @@
-use std::{collections::HashMap, convert::TryInto};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryInto,
+};
 
 use image::{GenericImageView, Rgba};
+use thiserror::Error;
+
+use crate::{GeneralResolution, Palette};
 
 pub struct ImageToPaletteBytesData {
     pub map: HashMap<[u8; 4], u8>,
@@ -29,13 +35,14 @@ impl ImageToPaletteBytesData {
         };
         self.map.insert(color.0, number);
         self.ordered.push(color.0);
-        Some(self.map.len() as u8)
+        Some(number)
     }
 }
 
 /// Transform an [`ImageBuffer`] to a list of bytes (its pixels from top left to bottom right, line by line).
 /// The [`ImageToPaletteBytesData`] can be used on multiple image to make sure the same color have the same palette id.
 /// None is returned if the palette have reach its limit of 255 different color.
+/// Pixels that are not fully opaque are made transparent. See [`quantize_images`] for images with more colors or semi-transparency.
 pub fn image_to_paletted_bytes<I: GenericImageView<Pixel = Rgba<u8>>>(
     palette_data: &mut ImageToPaletteBytesData,
     img: &I,
@@ -50,3 +57,633 @@ pub fn image_to_paletted_bytes<I: GenericImageView<Pixel = Rgba<u8>>>(
     }
     Some(result)
 }
+
+/// Convert an alpha ranging from 0 to 255 (as used in images) to the 0 to 128 range used by the DS, and by [`crate::Palette`].
+pub fn alpha_to_ds(alpha: u8) -> u8 {
+    ((alpha as u16 * 128 + 127) / 255) as u8
+}
+
+/// Convert an alpha ranging from 0 to 128 (as used by the DS, and by [`crate::Palette`]) to the 0 to 255 range used in images. The reverse of [`alpha_to_ds`].
+pub fn alpha_from_ds(alpha: u8) -> u8 {
+    ((alpha.min(128) as u16 * 255 + 64) / 128) as u8
+}
+
+/// Options for [`quantize_images`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuantizeOptions {
+    /// The maximum number of colors, not counting the transparent one. Should be between 1 and 255.
+    pub max_colors: usize,
+    /// Use Floyd–Steinberg dithering to spread the quantization error to the neighbour pixels.
+    pub dithering: bool,
+}
+
+impl Default for QuantizeOptions {
+    /// 15 colors (plus transparency, for a 16 colors palette), without dithering
+    fn default() -> Self {
+        Self {
+            max_colors: 15,
+            dithering: false,
+        }
+    }
+}
+
+/// The result of [`quantize_images`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuantizedImages {
+    /// The colors, with the first being transparent. Like [`ImageToPaletteBytesData::ordered`], the alpha range from 0 to 255, so it can be used as is with [`cluster_sub_palettes`] and the image converters.
+    /// Its alpha should be converted with [`alpha_to_ds`] to be used in a [`crate::Palette`].
+    pub palette: Vec<[u8; 4]>,
+    /// The pixels of each image (from top left to bottom right, line by line), as index in the palette.
+    pub images: Vec<Vec<u8>>,
+}
+
+/// The weight of each channel when comparing colors. The alpha range from 0 to 128, so it count double.
+const CHANNEL_WEIGHT: [i64; 4] = [1, 1, 1, 2];
+
+fn color_distance(a: [i32; 4], b: [u8; 4]) -> i64 {
+    (0..4)
+        .map(|c| {
+            let diff = (a[c] - b[c] as i32) as i64 * CHANNEL_WEIGHT[c];
+            diff * diff
+        })
+        .sum()
+}
+
+fn nearest_color(palette: &[[u8; 4]], color: [i32; 4]) -> u8 {
+    let mut best = (1, i64::MAX);
+    for (id, palette_color) in palette.iter().enumerate().skip(1) {
+        let distance = color_distance(color, *palette_color);
+        if distance < best.1 {
+            best = (id, distance);
+        }
+    }
+    best.0 as u8
+}
+
+/// Reduce the colors of a box of the median cut to a single color, by computing their weighted average
+fn average_color(colors: &[([u8; 4], u64)]) -> [u8; 4] {
+    let total: u64 = colors.iter().map(|(_, count)| count).sum();
+    let mut result = [0; 4];
+    for (c, channel) in result.iter_mut().enumerate() {
+        let sum: u64 = colors
+            .iter()
+            .map(|(color, count)| color[c] as u64 * count)
+            .sum();
+        *channel = ((sum + total / 2) / total) as u8;
+    }
+    result
+}
+
+/// Return the channel with the largest (weighted) range of the box, and this range
+fn widest_channel(colors: &[([u8; 4], u64)]) -> (usize, i64) {
+    let mut result = (0, -1);
+    for c in 0..4 {
+        let min = colors.iter().map(|(color, _)| color[c]).min().unwrap_or(0);
+        let max = colors.iter().map(|(color, _)| color[c]).max().unwrap_or(0);
+        let range = (max - min) as i64 * CHANNEL_WEIGHT[c];
+        if range > result.1 {
+            result = (c, range);
+        }
+    }
+    result
+}
+
+/// Find the palette using the median cut algorithm, on colors with an alpha in the 0 to 128 range
+fn median_cut(histogram: BTreeMap<[u8; 4], u64>, max_colors: usize) -> Vec<[u8; 4]> {
+    if histogram.len() <= max_colors {
+        return histogram.into_keys().collect();
+    }
+    let mut boxes: Vec<Vec<([u8; 4], u64)>> = vec![histogram.into_iter().collect()];
+    while boxes.len() < max_colors {
+        // split the box with the widest range
+        let mut to_split: Option<(usize, usize, i64)> = None;
+        for (box_id, colors) in boxes.iter().enumerate() {
+            if colors.len() < 2 {
+                continue;
+            }
+            let (channel, range) = widest_channel(colors);
+            if to_split.map(|(_, _, best)| range > best).unwrap_or(true) {
+                to_split = Some((box_id, channel, range));
+            }
+        }
+        let (box_id, channel, _) = match to_split {
+            Some(v) => v,
+            None => break,
+        };
+        let mut colors = boxes.swap_remove(box_id);
+        colors.sort_by_key(|(color, _)| (color[channel], *color));
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut accumulated = 0;
+        let mut split_at = colors.len() - 1;
+        for (index, (_, count)) in colors.iter().enumerate() {
+            accumulated += count;
+            if accumulated * 2 >= total {
+                split_at = index + 1;
+                break;
+            }
+        }
+        let split_at = split_at.clamp(1, colors.len() - 1);
+        let second_half = colors.split_off(split_at);
+        boxes.push(colors);
+        boxes.push(second_half);
+    }
+    let mut palette: Vec<[u8; 4]> = boxes.iter().map(|colors| average_color(colors)).collect();
+    palette.sort_unstable();
+    palette.dedup();
+    palette
+}
+
+/// Reduce the colors of the given images to a shared palette of up to [`QuantizeOptions::max_colors`] colors plus transparency.
+///
+/// The colors are compared with their alpha in the 0 to 128 range used by the DS (see [`alpha_to_ds`]), so semi-transparent pixels are kept. Pixels that end up with an alpha of 0 become transparent.
+/// The colors are chosen with the median cut algorithm. The result only depends on the input, making it reproducible.
+pub fn quantize_images<I: GenericImageView<Pixel = Rgba<u8>>>(
+    images: &[I],
+    options: &QuantizeOptions,
+) -> QuantizedImages {
+    let to_ds = |pixel: Rgba<u8>| -> Option<[u8; 4]> {
+        let alpha = alpha_to_ds(pixel.0[3]);
+        if alpha == 0 {
+            None
+        } else {
+            Some([pixel.0[0], pixel.0[1], pixel.0[2], alpha])
+        }
+    };
+
+    let mut histogram: BTreeMap<[u8; 4], u64> = BTreeMap::new();
+    for image in images {
+        for (_, _, pixel) in image.pixels() {
+            if let Some(color) = to_ds(pixel) {
+                *histogram.entry(color).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut palette = vec![[0, 0, 0, 0]];
+    palette.extend(median_cut(histogram, options.max_colors.clamp(1, 255)));
+
+    let mut result_images = Vec::with_capacity(images.len());
+    let mut nearest_cache: HashMap<[u8; 4], u8> = HashMap::new();
+    for image in images {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut errors = vec![[0i32; 4]; if options.dithering { width * height } else { 0 }];
+        for (x, y, pixel) in image.pixels() {
+            let color = match to_ds(pixel) {
+                Some(color) => color,
+                None => {
+                    pixels.push(0);
+                    continue;
+                }
+            };
+            if !options.dithering {
+                let id = *nearest_cache
+                    .entry(color)
+                    .or_insert_with(|| nearest_color(&palette, color.map(|c| c as i32)));
+                pixels.push(id);
+                continue;
+            }
+            let (x, y) = (x as usize, y as usize);
+            let error = errors[y * width + x];
+            let mut wanted = [0; 4];
+            for c in 0..4 {
+                let max = if c == 3 { 128 } else { 255 };
+                wanted[c] = (color[c] as i32 + error[c]).clamp(if c == 3 { 1 } else { 0 }, max);
+            }
+            let id = nearest_color(&palette, wanted);
+            pixels.push(id);
+            let chosen = palette[id as usize];
+            for (dx, dy, weight) in [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)] {
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx < 0 || nx as usize >= width || ny >= height {
+                    continue;
+                }
+                let neighbour = &mut errors[ny * width + nx as usize];
+                for c in 0..4 {
+                    neighbour[c] += (wanted[c] - chosen[c] as i32) * weight / 16;
+                }
+            }
+        }
+        result_images.push(pixels);
+    }
+
+    QuantizedImages {
+        palette: palette
+            .iter()
+            .map(|color| [color[0], color[1], color[2], alpha_from_ds(color[3])])
+            .collect(),
+        images: result_images,
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SubPaletteClusteringError {
+    #[error(
+        "The image {0} has {1} pixels, while it should have {2} pixels according to its resolution"
+    )]
+    InvalidResolution(usize, usize, u64),
+    #[error("The 8×8 tile at {1}, {2} of the image {0} uses {3} colors, but a sub-palette can only contain 15 colors")]
+    TileTooManyColors(usize, u32, u32, usize),
+    #[error("The tiles need more than 16 sub-palettes of 15 colors")]
+    TooManySubPalettes,
+    #[error("The images have more than 255 different colors")]
+    TooManyColors,
+}
+
+/// Return the set of colors (except the transparent one) of each 8×8 tiles of the image, line by line
+fn get_tiles_colors(pixels: &[u8], resolution: &GeneralResolution) -> Vec<BTreeSet<u8>> {
+    let tiles_x = resolution.x.div_ceil(8);
+    let tiles_y = resolution.y.div_ceil(8);
+    let mut result = vec![BTreeSet::new(); (tiles_x * tiles_y) as usize];
+    for (pixel_nb, pixel) in pixels.iter().enumerate() {
+        if *pixel == 0 {
+            continue;
+        }
+        let x = pixel_nb as u32 % resolution.x;
+        let y = pixel_nb as u32 / resolution.x;
+        result[((y / 8) * tiles_x + x / 8) as usize].insert(*pixel);
+    }
+    result
+}
+
+/// Group the colors of the 8×8 tiles of paletted images into up to 16 sub-palettes of 15 colors (plus transparency), so they can be used in a 4bpp sprite.
+///
+/// Return:
+/// 1. A [`Palette`] made of those sub-palettes, with the first color of each being transparent. Its colors have their alpha converted with [`alpha_to_ds`].
+/// 2. For each image, the sub-palette used by each of its 8×8 tiles, line by line (with the tiles aligned on the top-left of the image). Fully transparent tiles use the sub-palette 0.
+///
+/// `colors` is the color of each pixel value (with the alpha ranging from 0 to 255), the pixel value 0 being transparent, like [`ImageToPaletteBytesData::ordered`] and [`QuantizedImages::palette`].
+/// Each image can then be inserted with [`crate::insert_frame_in_wanimage_with_palette`], using its tile assignment.
+/// The images, `colors` and the palette can also be used with [`crate::create_wan_from_multiple_images_with_palette`], which choose the sub-palette of each fragment itself.
+///
+/// The tiles with the most colors are placed first, each in the sub-palette that needs the least new colors to contain it.
+pub fn cluster_sub_palettes(
+    images: &[(&[u8], GeneralResolution)],
+    colors: &[[u8; 4]],
+) -> Result<(Palette, Vec<Vec<u16>>), SubPaletteClusteringError> {
+    let mut tiles_colors = Vec::with_capacity(images.len());
+    for (image_id, (pixels, resolution)) in images.iter().enumerate() {
+        if pixels.len() as u64 != resolution.nb_pixels() {
+            return Err(SubPaletteClusteringError::InvalidResolution(
+                image_id,
+                pixels.len(),
+                resolution.nb_pixels(),
+            ));
+        }
+        let image_tiles_colors = get_tiles_colors(pixels, resolution);
+        let tiles_x = resolution.x.div_ceil(8);
+        for (tile_nb, tile_colors) in image_tiles_colors.iter().enumerate() {
+            if tile_colors.len() > 15 {
+                return Err(SubPaletteClusteringError::TileTooManyColors(
+                    image_id,
+                    (tile_nb as u32 % tiles_x) * 8,
+                    (tile_nb as u32 / tiles_x) * 8,
+                    tile_colors.len(),
+                ));
+            }
+        }
+        tiles_colors.push(image_tiles_colors);
+    }
+
+    let mut unique_sets: Vec<&BTreeSet<u8>> = tiles_colors
+        .iter()
+        .flatten()
+        .filter(|set| !set.is_empty())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    unique_sets.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+    let mut sub_palettes: Vec<BTreeSet<u8>> = Vec::new();
+    for set in unique_sets {
+        let best = sub_palettes
+            .iter()
+            .enumerate()
+            .map(|(id, sub_palette)| (id, set.difference(sub_palette).count(), sub_palette.len()))
+            .filter(|(_, new_colors, len)| len + new_colors <= 15)
+            .min_by_key(|(id, new_colors, _)| (*new_colors, *id));
+        match best {
+            Some((id, _, _)) => sub_palettes[id].extend(set),
+            None => {
+                if sub_palettes.len() >= 16 {
+                    return Err(SubPaletteClusteringError::TooManySubPalettes);
+                }
+                sub_palettes.push(set.clone());
+            }
+        }
+    }
+
+    // merge the sub-palettes that fit together
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'search: for first in 0..sub_palettes.len() {
+            for second in first + 1..sub_palettes.len() {
+                if sub_palettes[first].union(&sub_palettes[second]).count() <= 15 {
+                    let second_colors = sub_palettes.remove(second);
+                    sub_palettes[first].extend(second_colors);
+                    merged = true;
+                    break 'search;
+                }
+            }
+        }
+    }
+
+    let mut palette = Palette::new_eos();
+    for sub_palette in &sub_palettes {
+        palette.palette.push([0, 0, 0, 0]);
+        for color_id in sub_palette {
+            let color = colors
+                .get(*color_id as usize)
+                .copied()
+                .unwrap_or([0, 0, 0, 0]);
+            palette
+                .palette
+                .push([color[0], color[1], color[2], alpha_to_ds(color[3])]);
+        }
+        palette
+            .palette
+            .resize(palette.palette.len() + 15 - sub_palette.len(), [0, 0, 0, 0]);
+    }
+
+    let tile_sub_palettes = tiles_colors
+        .iter()
+        .map(|image_tiles_colors| {
+            image_tiles_colors
+                .iter()
+                .map(|tile_colors| {
+                    // sub-palettes were only merged after the tiles were placed in them, so they still contain all their colors
+                    sub_palettes
+                        .iter()
+                        .position(|sub_palette| tile_colors.is_subset(sub_palette))
+                        .unwrap_or(0) as u16
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok((palette, tile_sub_palettes))
+}
+
+/// Like [`cluster_sub_palettes`], but for RGBA images. Pixels that are not fully opaque are considered transparent, as with [`image_to_paletted_bytes`].
+/// Also return the paletted version of the images, and the color of each pixel value.
+#[allow(clippy::type_complexity)]
+pub fn cluster_sub_palettes_rgba<I: GenericImageView<Pixel = Rgba<u8>>>(
+    images: &[I],
+) -> Result<(Palette, Vec<Vec<u16>>, Vec<Vec<u8>>, Vec<[u8; 4]>), SubPaletteClusteringError> {
+    let mut palette_data = ImageToPaletteBytesData::default();
+    let mut paletted_images = Vec::with_capacity(images.len());
+    for image in images {
+        paletted_images.push(
+            image_to_paletted_bytes(&mut palette_data, image)
+                .ok_or(SubPaletteClusteringError::TooManyColors)?,
+        );
+    }
+    let paletted_with_resolution: Vec<(&[u8], GeneralResolution)> = paletted_images
+        .iter()
+        .zip(images)
+        .map(|(pixels, image)| {
+            (
+                pixels.as_slice(),
+                GeneralResolution::new(image.width(), image.height()),
+            )
+        })
+        .collect();
+    let (palette, tile_sub_palettes) =
+        cluster_sub_palettes(&paletted_with_resolution, &palette_data.ordered)?;
+    Ok((
+        palette,
+        tile_sub_palettes,
+        paletted_images,
+        palette_data.ordered,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use crate::{
+        create_wan_from_multiple_images_with_palette,
+        image_tool::{
+            alpha_from_ds, alpha_to_ds, cluster_sub_palettes, cluster_sub_palettes_rgba,
+            image_to_paletted_bytes, quantize_images, ImageToPaletteBytesData, QuantizeOptions,
+            SubPaletteClusteringError,
+        },
+        insert_frame_in_wanimage_with_palette, Animation, AnimationFrame, GeneralResolution,
+        SpriteType, WanImage, WanWriteError,
+    };
+
+    #[test]
+    fn test_image_to_paletted_bytes_id() {
+        let mut image = RgbaImage::new(3, 1);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 255, 0, 255]));
+        image.put_pixel(2, 0, Rgba([255, 0, 0, 255]));
+        let mut palette_data = ImageToPaletteBytesData::default();
+        let bytes = image_to_paletted_bytes(&mut palette_data, &image).unwrap();
+        assert_eq!(bytes, vec![1, 2, 1]);
+        assert_eq!(
+            palette_data.ordered,
+            vec![[0, 0, 0, 0], [255, 0, 0, 255], [0, 255, 0, 255]]
+        );
+    }
+
+    #[test]
+    fn test_alpha_to_ds() {
+        assert_eq!(alpha_to_ds(0), 0);
+        assert_eq!(alpha_to_ds(1), 1);
+        assert_eq!(alpha_to_ds(128), 64);
+        assert_eq!(alpha_to_ds(255), 128);
+        assert_eq!(alpha_from_ds(0), 0);
+        assert_eq!(alpha_from_ds(64), 128);
+        assert_eq!(alpha_from_ds(128), 255);
+        for alpha in 0..=128 {
+            assert_eq!(alpha_to_ds(alpha_from_ds(alpha)), alpha);
+        }
+    }
+
+    #[test]
+    fn test_quantize_few_colors() {
+        let mut image = RgbaImage::new(3, 1);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 0, 255, 128]));
+        let result = quantize_images(&[image], &QuantizeOptions::default());
+        assert_eq!(
+            result.palette,
+            vec![[0, 0, 0, 0], [0, 0, 255, 128], [255, 0, 0, 255]]
+        );
+        assert_eq!(result.images, vec![vec![2, 1, 0]]);
+    }
+
+    #[test]
+    fn test_quantize_gradient() {
+        let gradient = RgbaImage::from_fn(64, 8, |x, _| Rgba([x as u8 * 4, 0, 0, 255]));
+        let other = RgbaImage::from_pixel(2, 2, Rgba([0, 255, 0, 255]));
+        for dithering in [false, true] {
+            let options = QuantizeOptions {
+                max_colors: 15,
+                dithering,
+            };
+            let result = quantize_images(&[gradient.clone(), other.clone()], &options);
+            assert!(result.palette.len() <= 16);
+            assert_eq!(result.images[0].len(), 64 * 8);
+            assert!(result.images[0].iter().all(|p| *p != 0));
+            // the green is used by a single image, but is still kept
+            assert_eq!(
+                result.palette[result.images[1][0] as usize],
+                [0, 255, 0, 255]
+            );
+            // reproducible
+            assert_eq!(
+                quantize_images(&[gradient.clone(), other.clone()], &options),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_cluster_sub_palettes() {
+        // a 32×8 image, with each 8×8 tile using 10 colors, from a total of 40 colors
+        let image = RgbaImage::from_fn(32, 8, |x, y| {
+            let color = (x / 8) * 10 + (x + y) % 10;
+            Rgba([color as u8 * 6, 255 - color as u8, 0, 255])
+        });
+        let (palette, tile_sub_palettes, paletted, colors) =
+            cluster_sub_palettes_rgba(&[image.clone()]).unwrap();
+        assert_eq!(colors.len(), 41);
+        assert_eq!(palette.palette.len(), 4 * 16);
+        // each tile has its own sub-palette
+        let mut sorted_tile_sub_palettes = tile_sub_palettes[0].clone();
+        sorted_tile_sub_palettes.sort_unstable();
+        assert_eq!(sorted_tile_sub_palettes, vec![0, 1, 2, 3]);
+
+        let wan = create_wan_from_multiple_images_with_palette(
+            &[(&paletted[0], GeneralResolution::new(32, 8))],
+            SpriteType::PropsUI,
+            &colors,
+            &palette,
+        )
+        .unwrap();
+        let (rendered, origin) = wan.render_frame(0).unwrap();
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert_eq!(
+                rendered.get_pixel(x + origin.0 as u32, y + origin.1 as u32),
+                pixel
+            );
+        }
+    }
+
+    #[test]
+    fn test_cluster_then_insert_frame() {
+        // a 64×16 image with 40 colors, 10 for each 16×16 square, so it can't be drawn with a single fragment in 4bpp
+        let image = RgbaImage::from_fn(64, 16, |x, y| {
+            let color = (x / 16) * 10 + (x + y) % 10;
+            Rgba([color as u8 * 6, 255 - color as u8, 0, 255])
+        });
+        let (palette, tile_sub_palettes, paletted, colors) =
+            cluster_sub_palettes_rgba(&[image.clone()]).unwrap();
+        assert_eq!(colors.len(), 41);
+        assert_eq!(tile_sub_palettes[0].len(), 16);
+
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette = palette;
+        assert!(matches!(
+            insert_frame_in_wanimage_with_palette(
+                paletted[0].clone(),
+                64,
+                16,
+                &mut wan,
+                &colors,
+                None
+            ),
+            Err(WanWriteError::NoSubPaletteForFragment { .. })
+        ));
+        assert!(matches!(
+            insert_frame_in_wanimage_with_palette(
+                paletted[0].clone(),
+                64,
+                16,
+                &mut wan,
+                &colors,
+                Some(&tile_sub_palettes[0][1..])
+            ),
+            Err(WanWriteError::TileSubPalettesMismatch { .. })
+        ));
+        let frame_id = insert_frame_in_wanimage_with_palette(
+            paletted[0].clone(),
+            64,
+            16,
+            &mut wan,
+            &colors,
+            Some(&tile_sub_palettes[0]),
+        )
+        .unwrap()
+        .unwrap();
+        wan.animation_store.anim_groups.push(vec![Animation {
+            frames: vec![AnimationFrame {
+                duration: 1,
+                flag: 0,
+                frame_id: frame_id as u16,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 0,
+            }],
+        }]);
+
+        let wan = WanImage::from_bytes(&wan.to_bytes().unwrap()).unwrap();
+        assert!(!wan.is_256_color);
+        assert!(wan.frame_store.frames[frame_id]
+            .fragments
+            .iter()
+            .all(|fragment| fragment.pal_idx < 4));
+        // the image is centered on the origin, and fully covered by fragments
+        let (rendered, origin) = wan.render_frame(frame_id).unwrap();
+        assert_eq!(origin, (32, 8));
+        assert_eq!(rendered, image);
+    }
+
+    #[test]
+    fn test_quantize_then_cluster() {
+        let image = RgbaImage::from_fn(16, 8, |x, _| {
+            if x < 8 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 128])
+            }
+        });
+        let quantized = quantize_images(&[image], &QuantizeOptions::default());
+        let images = [(
+            quantized.images[0].as_slice(),
+            GeneralResolution::new(16, 8),
+        )];
+        let (palette, _) = cluster_sub_palettes(&images, &quantized.palette).unwrap();
+        let wan = create_wan_from_multiple_images_with_palette(
+            &images,
+            SpriteType::PropsUI,
+            &quantized.palette,
+            &palette,
+        )
+        .unwrap();
+        // the alpha is only converted once
+        let mut colors = wan.palette.palette[1..3].to_vec();
+        colors.sort_unstable();
+        assert_eq!(colors, vec![[0, 0, 255, 64], [255, 0, 0, 128]]);
+        assert_eq!(wan.frame_store.frames[0].fragments.len(), 2);
+    }
+
+    #[test]
+    fn test_cluster_sub_palettes_errors() {
+        let colors = vec![[255, 255, 255, 255]; 20];
+        let pixels: Vec<u8> = (0..64).map(|p| (p % 16) as u8 + 1).collect();
+        assert_eq!(
+            cluster_sub_palettes(&[(&pixels, GeneralResolution::new(8, 8))], &colors),
+            Err(SubPaletteClusteringError::TileTooManyColors(0, 0, 0, 16))
+        );
+        assert_eq!(
+            cluster_sub_palettes(&[(&pixels, GeneralResolution::new(8, 7))], &colors),
+            Err(SubPaletteClusteringError::InvalidResolution(0, 64, 56))
+        );
+    }
+}
@@ -7,9 +7,26 @@ pub mod tests;
 pub mod wan_image;
 pub use wan_image::WanImage;
 
+mod wan_decode_options;
+pub use wan_decode_options::WanDecodeOptions;
+
+mod wan_layout;
+pub use wan_layout::WanLayout;
+
+mod wan_view;
+pub use wan_view::WanView;
+
+#[cfg(feature = "pack")]
+mod pack;
+#[cfg(feature = "pack")]
+pub use pack::{write_pack, WanPack, WanPackError};
+
 mod wan_error;
 pub use wan_error::WanError;
 
+mod wan_write_error;
+pub use wan_write_error::{FragmentBytesWriteError, WanWriteError};
+
 mod frame;
 pub use frame::Frame;
 
@@ -34,6 +51,9 @@ pub use crate::fragment_bytes::{
 mod palette;
 pub use palette::Palette;
 
+mod palette_mapping;
+pub use palette_mapping::PaletteMapping;
+
 mod fragment_bytes_store;
 pub use fragment_bytes_store::FragmentBytesStore;
 
@@ -60,7 +80,7 @@ pub use fragment_finder::{
 };
 
 mod image_to_wan;
-pub use image_to_wan::insert_frame_in_wanimage;
+pub use image_to_wan::{insert_frame_in_wanimage, insert_frame_in_wanimage_with_palette};
 
 mod image_buffer;
 pub use image_buffer::ImageBuffer;
@@ -68,7 +88,9 @@ pub use image_buffer::ImageBuffer;
 pub mod image_tool;
 
 mod multi_images_to_wan;
-pub use multi_images_to_wan::create_wan_from_multiple_images;
+pub use multi_images_to_wan::{
+    create_wan_from_multiple_images, create_wan_from_multiple_images_with_palette,
+};
 
 mod normalized_bytes;
 pub use normalized_bytes::{NormalizedBytes, VariableNormalizedBytes};
@@ -76,6 +98,42 @@ pub use normalized_bytes::{NormalizedBytes, VariableNormalizedBytes};
 mod frame_offset;
 pub use frame_offset::FrameOffset;
 
+mod remove_unused;
+
+mod convert_sprite_type;
+pub use convert_sprite_type::ConversionReport;
+
+mod merge;
+pub use merge::{WanMergeError, MAX_SUB_PALETTES};
+
+mod validation;
+pub use validation::{ValidationIssue, MAX_FRAGMENTS_PER_FRAME, MAX_FRAME_ALLOCATION};
+
+mod render;
+pub use render::{RenderError, RenderedAnimation, RenderedAnimationFrame};
+
+mod fragment_bytes_atlas;
+#[cfg(feature = "atlas_export")]
+pub use fragment_bytes_atlas::export_fragment_bytes_atlas;
+pub use fragment_bytes_atlas::{
+    FragmentBytesAtlas, FragmentBytesAtlasEntry, FragmentBytesAtlasError, FragmentBytesReference,
+};
+
+#[cfg(feature = "animation_export")]
+mod animation_export;
+#[cfg(feature = "animation_export")]
+pub use animation_export::{export_animation_apng, export_animation_gif, AnimationExportError};
+
+#[cfg(feature = "spritebot")]
+mod spritebot;
+#[cfg(feature = "spritebot")]
+pub use spritebot::{
+    export_wan_to_spritebot_folder, import_wan_from_spritebot_folder, spritebot_animation_name,
+    spritebot_to_wan, wan_to_spritebot, SpriteBotError, SPRITEBOT_ANIMATION_NAMES,
+};
+#[cfg(feature = "spritebot")]
+pub use spritebot_storage;
+
 use binwrite::WriterOption;
 pub fn get_opt_le() -> WriterOption {
     binwrite::writer_option_new!(endian: binwrite::Endian::Little)
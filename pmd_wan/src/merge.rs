@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{SpriteType, ValidationIssue, WanImage};
+
+/// The maximum number of sub-palettes, as the palette index of a [`crate::Fragment`] is stored on 4 bits
+pub const MAX_SUB_PALETTES: usize = 16;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WanMergeError {
+    #[error("Can't merge a {1:?} sprite into a {0:?} sprite")]
+    SpriteTypeMismatch(SpriteType, SpriteType),
+    #[error("Can't merge a sprite with {} colors per palette into a sprite with {} colors per palette", if *.1 { 256 } else { 16 }, if *.0 { 256 } else { 16 })]
+    ColorModeMismatch(bool, bool),
+    #[error("Can't merge sprites whose palette or FragmentBytes are stored in another file")]
+    SharedData,
+    #[error("The sprite to merge has {0} animation groups, but {1} target animation group ids were given")]
+    AnimGroupIdsLength(usize, usize),
+    #[error("The animation group {0} is targeted multiple times")]
+    DuplicateAnimGroupId(usize),
+    #[error("The sprite to merge reference a non-existing element: {0}")]
+    InvalidReference(ValidationIssue),
+    #[error("The merged sprite would have {0} FragmentBytes, but there can be at most 32767")]
+    TooManyFragmentBytes(usize),
+    #[error("The merged sprite would have {0} frames, but there can be at most 65535")]
+    TooManyFrames(usize),
+    #[error("The merged sprite would have {0} sub-palettes, but there can be at most 16")]
+    TooManySubPalettes(usize),
+    #[error("The merged sprite would have {0} animation groups, but there can be at most 65535")]
+    TooManyAnimGroups(usize),
+}
+
+impl WanImage {
+    /// Merge the content of `other` into this sprite.
+    ///
+    /// The [`crate::FragmentBytes`] of `other` are appended, reusing identical ones already present. Its [`crate::Frame`]s are appended, and its sub-palettes too (reusing identical ones), with all the indices remapped.
+    /// The animation group `n` of `other` replace the animation group `anim_group_ids[n]` of this sprite, adding empty animation groups if needed.
+    ///
+    /// The sprite is left unchanged if an error is returned. Frames that are no longer used by replaced animation groups are kept, see [`WanImage::remove_unused`] to remove them.
+    pub fn merge(
+        &mut self,
+        other: &WanImage,
+        anim_group_ids: &[usize],
+    ) -> Result<(), WanMergeError> {
+        if self.sprite_type != other.sprite_type {
+            return Err(WanMergeError::SpriteTypeMismatch(
+                self.sprite_type,
+                other.sprite_type,
+            ));
+        }
+        if self.is_256_color != other.is_256_color {
+            return Err(WanMergeError::ColorModeMismatch(
+                self.is_256_color,
+                other.is_256_color,
+            ));
+        }
+        if self.shared_palette
+            || self.shared_fragment_bytes
+            || other.shared_palette
+            || other.shared_fragment_bytes
+        {
+            return Err(WanMergeError::SharedData);
+        }
+        if anim_group_ids.len() != other.animation_store.anim_groups.len() {
+            return Err(WanMergeError::AnimGroupIdsLength(
+                other.animation_store.anim_groups.len(),
+                anim_group_ids.len(),
+            ));
+        }
+        for (position, anim_group_id) in anim_group_ids.iter().enumerate() {
+            if anim_group_ids[..position].contains(anim_group_id) {
+                return Err(WanMergeError::DuplicateAnimGroupId(*anim_group_id));
+            }
+        }
+        if let Some(issue) = other.validate().into_iter().find(|issue| {
+            matches!(
+                issue,
+                ValidationIssue::FragmentBytesIndexOutOfRange { .. }
+                    | ValidationIssue::PaletteOutOfRange { .. }
+                    | ValidationIssue::AnimationFrameOutOfRange { .. }
+            )
+        }) {
+            return Err(WanMergeError::InvalidReference(issue));
+        }
+
+        // fragment bytes
+        let mut fragment_bytes_indices: HashMap<(&[u8], u32), usize> = HashMap::new();
+        for (index, fragment_bytes) in self.fragment_bytes_store.fragment_bytes.iter().enumerate() {
+            fragment_bytes_indices
+                .entry((&fragment_bytes.mixed_pixels, fragment_bytes.z_index))
+                .or_insert(index);
+        }
+        let mut new_fragment_bytes = Vec::new();
+        let mut fragment_bytes_mapping = Vec::with_capacity(other.fragment_bytes_store.len());
+        for fragment_bytes in &other.fragment_bytes_store.fragment_bytes {
+            let next_index = self.fragment_bytes_store.len() + new_fragment_bytes.len();
+            let index = *fragment_bytes_indices
+                .entry((&fragment_bytes.mixed_pixels, fragment_bytes.z_index))
+                .or_insert_with(|| {
+                    new_fragment_bytes.push(fragment_bytes.clone());
+                    next_index
+                });
+            fragment_bytes_mapping.push(index);
+        }
+        let fragment_bytes_amount = self.fragment_bytes_store.len() + new_fragment_bytes.len();
+        if fragment_bytes_amount > i16::MAX as usize {
+            return Err(WanMergeError::TooManyFragmentBytes(fragment_bytes_amount));
+        }
+
+        // sub-palettes
+        let palette_size = if self.is_256_color { 256 } else { 16 };
+        let mut sub_palettes: Vec<Vec<[u8; 4]>> = self
+            .palette
+            .palette
+            .chunks(palette_size)
+            .map(|sub_palette| {
+                let mut sub_palette = sub_palette.to_vec();
+                sub_palette.resize(palette_size, [0, 0, 0, 0]);
+                sub_palette
+            })
+            .collect();
+        let mut palette_mapping = Vec::new();
+        for sub_palette in other.palette.palette.chunks(palette_size) {
+            let mut sub_palette = sub_palette.to_vec();
+            sub_palette.resize(palette_size, [0, 0, 0, 0]);
+            let index = match sub_palettes.iter().position(|s| *s == sub_palette) {
+                Some(index) => index,
+                None => {
+                    sub_palettes.push(sub_palette);
+                    sub_palettes.len() - 1
+                }
+            };
+            palette_mapping.push(index as u16);
+        }
+        if sub_palettes.len() > MAX_SUB_PALETTES {
+            return Err(WanMergeError::TooManySubPalettes(sub_palettes.len()));
+        }
+
+        // frames
+        let frame_id_offset = self.frame_store.frames.len();
+        let frame_amount = frame_id_offset + other.frame_store.frames.len();
+        if frame_amount > u16::MAX as usize {
+            return Err(WanMergeError::TooManyFrames(frame_amount));
+        }
+
+        // animation groups
+        let anim_group_amount = anim_group_ids
+            .iter()
+            .map(|id| id + 1)
+            .chain(std::iter::once(self.animation_store.anim_groups.len()))
+            .max()
+            .unwrap_or(0);
+        if anim_group_amount > u16::MAX as usize {
+            return Err(WanMergeError::TooManyAnimGroups(anim_group_amount));
+        }
+
+        // everything has been checked, apply the changes
+        self.fragment_bytes_store
+            .fragment_bytes
+            .extend(new_fragment_bytes);
+        self.palette.palette = sub_palettes.concat();
+        for frame in &other.frame_store.frames {
+            let mut frame = frame.clone();
+            for fragment in &mut frame.fragments {
+                // no panic: the references were checked to be valid
+                fragment.fragment_bytes_index =
+                    fragment_bytes_mapping[fragment.fragment_bytes_index];
+                fragment.pal_idx = palette_mapping[fragment.pal_idx as usize];
+            }
+            self.frame_store.frames.push(frame);
+        }
+        self.animation_store
+            .anim_groups
+            .resize(anim_group_amount, Vec::new());
+        for (anim_group, anim_group_id) in
+            other.animation_store.anim_groups.iter().zip(anim_group_ids)
+        {
+            let mut anim_group = anim_group.clone();
+            for animation_frame in anim_group.iter_mut().flat_map(|a| &mut a.frames) {
+                animation_frame.frame_id += frame_id_offset as u16;
+            }
+            self.animation_store.anim_groups[*anim_group_id] = anim_group;
+        }
+        // Those preserved values are only valid for the original sprite
+        self.animation_store.copied_on_previous = None;
+        self.size_to_allocate_for_max_frame = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Animation, AnimationFrame, Fragment, FragmentBytes, FragmentFlip, Frame, OamShape,
+        SpriteType, ValidationIssue, WanImage, WanMergeError,
+    };
+
+    fn sprite(pixel: u8, color: [u8; 4]) -> WanImage {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 16];
+        wan.palette.palette[1] = color;
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: vec![pixel; 64],
+            z_index: 0,
+        });
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![Fragment {
+                unk1: 0,
+                unk3_4: None,
+                unk5: false,
+                fragment_bytes_index: 0,
+                offset_y: 0,
+                offset_x: 0,
+                flip: FragmentFlip::standard(),
+                is_mosaic: false,
+                pal_idx: 0,
+                alloc_counter: None,
+                resolution: OamShape::new(0, 0).unwrap(),
+            }],
+            frame_offset: None,
+        });
+        wan.animation_store.anim_groups.push(vec![Animation {
+            frames: vec![AnimationFrame {
+                duration: 1,
+                flag: 0,
+                frame_id: 0,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 0,
+            }],
+        }]);
+        wan
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut wan = sprite(0x11, [255, 0, 0, 128]);
+        let mut other = sprite(0x11, [0, 255, 0, 128]);
+        other
+            .fragment_bytes_store
+            .fragment_bytes
+            .push(FragmentBytes {
+                mixed_pixels: vec![0x10; 64],
+                z_index: 0,
+            });
+        other.frame_store.frames[0].fragments[0].fragment_bytes_index = 1;
+        other
+            .frame_store
+            .frames
+            .push(wan.frame_store.frames[0].clone());
+        other.animation_store.anim_groups.push(vec![Animation {
+            frames: vec![AnimationFrame {
+                frame_id: 1,
+                ..wan.animation_store.anim_groups[0][0].frames[0].clone()
+            }],
+        }]);
+
+        assert_eq!(
+            wan.merge(&other, &[2]),
+            Err(WanMergeError::AnimGroupIdsLength(2, 1))
+        );
+        assert_eq!(
+            wan.merge(&other, &[2, 2]),
+            Err(WanMergeError::DuplicateAnimGroupId(2))
+        );
+
+        wan.merge(&other, &[2, 0]).unwrap();
+        assert_eq!(wan.fragment_bytes_store.len(), 2);
+        assert_eq!(wan.palette.palette.len(), 32);
+        assert_eq!(wan.palette.palette[16 + 1], [0, 255, 0, 128]);
+        assert_eq!(wan.frame_store.frames.len(), 3);
+        assert_eq!(
+            wan.frame_store.frames[1].fragments[0].fragment_bytes_index,
+            1
+        );
+        assert_eq!(wan.frame_store.frames[1].fragments[0].pal_idx, 1);
+        assert_eq!(
+            wan.frame_store.frames[2].fragments[0].fragment_bytes_index,
+            0
+        );
+        assert_eq!(wan.animation_store.anim_groups.len(), 3);
+        assert_eq!(wan.animation_store.anim_groups[0][0].frames[0].frame_id, 2);
+        assert!(wan.animation_store.anim_groups[1].is_empty());
+        assert_eq!(wan.animation_store.anim_groups[2][0].frames[0].frame_id, 1);
+        assert_eq!(wan.validate(), Vec::new());
+
+        // merging the same sprite again reuse the sub-palettes and FragmentBytes
+        wan.merge(&other, &[3, 4]).unwrap();
+        assert_eq!(wan.fragment_bytes_store.len(), 2);
+        assert_eq!(wan.palette.palette.len(), 32);
+
+        other.frame_store.frames[0].fragments[0].pal_idx = 1;
+        assert_eq!(
+            wan.merge(&other, &[5, 6]),
+            Err(WanMergeError::InvalidReference(
+                ValidationIssue::PaletteOutOfRange {
+                    frame_id: 0,
+                    fragment_id: 0,
+                    pal_idx: 1
+                }
+            ))
+        );
+        other.frame_store.frames[0].fragments[0].pal_idx = 0;
+
+        for i in 0..16 {
+            other.palette.palette.extend([[i, 0, 0, 128]; 16]);
+        }
+        assert_eq!(
+            wan.merge(&other, &[5, 6]),
+            Err(WanMergeError::TooManySubPalettes(18))
+        );
+        assert_eq!(wan.frame_store.frames.len(), 5);
+    }
+}
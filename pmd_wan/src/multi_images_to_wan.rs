@@ -7,7 +7,8 @@ use std::{
 use crate::{
     encode_fragment_pixels, find_fragments_in_images, fragment_finder::FragmentUse,
     pad_seven_pixel, Fragment, FragmentBytes, FragmentFinderData, FragmentFlip, Frame,
-    GeneralResolution, NormalizedBytes, OamShape, SpriteType, VariableNormalizedBytes, WanImage,
+    GeneralResolution, NormalizedBytes, OamShape, Palette, PaletteMapping, SpriteType,
+    VariableNormalizedBytes, WanImage,
 };
 use anyhow::{bail, Context};
 
@@ -20,10 +21,11 @@ struct ImageStartDelta {
 impl ImageStartDelta {
     fn new(selected_x: i32, selected_y: i32) -> Self {
         fn get_appropriate_value(value: i32) -> i8 {
-            if value % 8 == 0 {
+            // rem_euclid, as the position of a fragment can be negative
+            if value.rem_euclid(8) == 0 {
                 0
             } else {
-                -8 + ((value % 8) as i8)
+                -8 + (value.rem_euclid(8) as i8)
             }
         }
         Self {
@@ -33,9 +35,52 @@ impl ImageStartDelta {
     }
 }
 
+/// Create a new [`WanImage`] with a [`Frame`] for each of the given images.
+/// The pixel values are used as is, with the first sub-palette. They thus should be less than 16.
+/// See [`create_wan_from_multiple_images_with_palette`] to use multiple sub-palettes.
 pub fn create_wan_from_multiple_images(
     images: &[(&[u8], GeneralResolution)],
     sprite_type: SpriteType,
+) -> anyhow::Result<WanImage> {
+    for (image_id, (image_bytes, _)) in images.iter().enumerate() {
+        if let Some(pixel) = image_bytes.iter().find(|pixel| **pixel >= 16) {
+            bail!(
+                "The image {} contain the pixel value {}, while only value less than 16 can be used with a single sub-palette",
+                image_id,
+                pixel
+            );
+        }
+    }
+    create_wan_from_multiple_images_internal(images, sprite_type, None)
+}
+
+/// Create a new [`WanImage`] with a [`Frame`] for each of the given images, using multiple sub-palettes.
+///
+/// `colors` is the color of each pixel value (with the alpha ranging from 0 to 255), and `palette` is the [`Palette`] of the resulting sprite, made of up to 16 sub-palettes of 15 colors.
+/// Each [`Fragment`] use the first sub-palette that contains all of its colors. An error is returned if there are none.
+/// See [`PaletteMapping`].
+pub fn create_wan_from_multiple_images_with_palette(
+    images: &[(&[u8], GeneralResolution)],
+    sprite_type: SpriteType,
+    colors: &[[u8; 4]],
+    palette: &Palette,
+) -> anyhow::Result<WanImage> {
+    if palette.palette.len() > 16 * 16 {
+        bail!(
+            "The palette has {} colors, but only up to 16 sub-palettes of 16 colors can be used",
+            palette.palette.len()
+        );
+    }
+    let mapping = PaletteMapping::new(colors, palette);
+    let mut wan = create_wan_from_multiple_images_internal(images, sprite_type, Some(&mapping))?;
+    wan.palette = palette.clone();
+    Ok(wan)
+}
+
+fn create_wan_from_multiple_images_internal(
+    images: &[(&[u8], GeneralResolution)],
+    sprite_type: SpriteType,
+    mapping: Option<&PaletteMapping>,
 ) -> anyhow::Result<WanImage> {
     //high level overview of how this work :
     //1. Get fragments (8 by 8) usage stats
@@ -51,8 +96,13 @@ pub fn create_wan_from_multiple_images(
         )
     }
     // step 1 and 2
-    let images_deltas =
+    let mut images_deltas =
         get_images_delta(images).context("while trying to get the images deltas")?;
+    if let Some(mapping) = mapping {
+        for (delta, (image_bytes, image_resolution)) in images_deltas.iter_mut().zip(images) {
+            *delta = find_delta_fitting_palette(image_bytes, image_resolution, *delta, mapping);
+        }
+    }
 
     // step 3
     let mut bigger_fragment_finder_builder = BiggerFragmentFinderBuilder::new(images.len() as u16);
@@ -73,7 +123,7 @@ pub fn create_wan_from_multiple_images(
     wan.frame_store.frames = vec![Frame::default(); images.len()];
 
     // step 4 and 5 are combined
-    bigger_fragment_finder.find_and_apply_on_wan(&mut wan);
+    bigger_fragment_finder.find_and_apply_on_wan(&mut wan, mapping)?;
 
     wan.fix_empty_frames();
     Ok(wan)
@@ -93,8 +143,7 @@ fn get_images_delta(images: &[(&[u8], GeneralResolution)]) -> anyhow::Result<Vec
             for (fragment, all_usage) in ordered.iter().map(|(x, y)| (*x, *y)) {
                 for usage in all_usage {
                     if usage.image_id == image_id
-                        && !limit
-                        && fragment.0.iter().filter(|x| **x != 0).count() > (64 / 4) * 3
+                        && (!limit || fragment.0.iter().filter(|x| **x != 0).count() > (64 / 4) * 3)
                     {
                         return Some((*fragment, *usage));
                     }
@@ -120,6 +169,76 @@ fn get_images_delta(images: &[(&[u8], GeneralResolution)]) -> anyhow::Result<Vec
     Ok(result)
 }
 
+/// Return the 8×8 tiles of the image, using the given grid alignment, with their position relative to the top-left of the image.
+/// Fully transparent tiles are skipped.
+fn get_image_tiles(
+    image_bytes: &[u8],
+    resolution: &GeneralResolution,
+    delta: ImageStartDelta,
+) -> Vec<(i32, i32, [u8; 64])> {
+    let mut result = Vec::new();
+    let (padded_image, padded_resolution) =
+        pad_seven_pixel(image_bytes, resolution.clone()).unwrap();
+    let pixel_start_in_padded_image = (delta.delta_x + 7, delta.delta_y + 7);
+    let loop_number_by_side = (
+        (-delta.delta_x as u32 + resolution.x + 7) / 8,
+        (-delta.delta_y as u32 + resolution.y + 7) / 8,
+    );
+    for global_fragment_position_y in 0..loop_number_by_side.1 {
+        let fragment_start_y =
+            global_fragment_position_y * 8 + pixel_start_in_padded_image.1 as u32;
+        for global_fragment_position_x in 0..loop_number_by_side.0 {
+            let fragment_start_x =
+                global_fragment_position_x * 8 + pixel_start_in_padded_image.0 as u32;
+            let mut fragment_buffer = [0; 64];
+            for special_line in 0..8 {
+                let pixel_base =
+                    (special_line + fragment_start_y) * padded_resolution.x + fragment_start_x;
+                fragment_buffer[special_line as usize * 8..special_line as usize * 8 + 8]
+                    .copy_from_slice(&padded_image[pixel_base as usize..pixel_base as usize + 8]);
+            }
+
+            if fragment_buffer == [0; 64] {
+                continue;
+            }
+
+            result.push((
+                fragment_start_x as i32 - 7,
+                fragment_start_y as i32 - 7,
+                fragment_buffer,
+            ));
+        }
+    }
+    result
+}
+
+/// Return the preferred grid alignment if every of its tiles fit in a sub-palette, or otherwise the first one that does.
+/// If none does, the preferred one is returned, and the error will be reported when placing the tiles.
+fn find_delta_fitting_palette(
+    image_bytes: &[u8],
+    resolution: &GeneralResolution,
+    preferred: ImageStartDelta,
+    mapping: &PaletteMapping,
+) -> ImageStartDelta {
+    let fit = |delta: ImageStartDelta| {
+        get_image_tiles(image_bytes, resolution, delta)
+            .iter()
+            .all(|(_, _, tile)| mapping.find_sub_palette(tile).is_some())
+    };
+    if fit(preferred) {
+        return preferred;
+    }
+    for delta_y in -7..=0 {
+        for delta_x in -7..=0 {
+            let delta = ImageStartDelta { delta_x, delta_y };
+            if fit(delta) {
+                return delta;
+            }
+        }
+    }
+    preferred
+}
+
 #[derive(Debug)]
 struct BiggerFragmentFinderBuilder {
     presence: HashMap<NormalizedBytes, (Vec<bool>, BTreeSet<FragmentUse>)>,
@@ -141,56 +260,18 @@ impl BiggerFragmentFinderBuilder {
         delta: ImageStartDelta,
         image_id: u16,
     ) {
-        let (padded_image, padded_resolution) =
-            pad_seven_pixel(image_bytes, resolution.clone()).unwrap();
-        let pixel_start_in_padded_image = (
-            if delta.delta_x == 0 {
-                0
-            } else {
-                delta.delta_x + 7
-            },
-            if delta.delta_y == 0 {
-                0
-            } else {
-                delta.delta_y + 7
-            },
-        );
-        let loop_number_by_side = (
-            (-delta.delta_x as u32 + resolution.x + 7) / 8,
-            (-delta.delta_y as u32 + resolution.y + 7) / 8,
-        );
-        for global_fragment_position_y in 0..loop_number_by_side.1 {
-            let fragment_start_y =
-                global_fragment_position_y * 8 + pixel_start_in_padded_image.1 as u32;
-            for global_fragment_position_x in 0..loop_number_by_side.0 {
-                let fragment_start_x =
-                    global_fragment_position_x * 8 + pixel_start_in_padded_image.0 as u32;
-                let mut fragment_buffer = [0; 64];
-                for special_line in 0..8 {
-                    let pixel_base =
-                        (special_line + fragment_start_y) * padded_resolution.x + fragment_start_x;
-                    fragment_buffer[special_line as usize * 8..special_line as usize * 8 + 8]
-                        .copy_from_slice(
-                            &padded_image[pixel_base as usize..pixel_base as usize + 8],
-                        );
-                }
-
-                if fragment_buffer == [0; 64] {
-                    continue;
-                }
-
-                let (normalized_bytes, flip) = NormalizedBytes::new(fragment_buffer);
-
-                self.add_use(
-                    normalized_bytes,
-                    FragmentUse {
-                        x: fragment_start_x as i32 - 7,
-                        y: fragment_start_y as i32 - 7,
-                        image_id,
-                        flip,
-                    },
-                );
-            }
+        for (x, y, fragment_buffer) in get_image_tiles(image_bytes, &resolution, delta) {
+            let (normalized_bytes, flip) = NormalizedBytes::new(fragment_buffer);
+
+            self.add_use(
+                normalized_bytes,
+                FragmentUse {
+                    x,
+                    y,
+                    image_id,
+                    flip,
+                },
+            );
         }
     }
 
@@ -224,10 +305,15 @@ struct BiggerFragmentFinder {
 }
 
 impl BiggerFragmentFinder {
-    fn find_and_apply_on_wan(self, wan: &mut WanImage) {
+    fn find_and_apply_on_wan(
+        self,
+        wan: &mut WanImage,
+        mapping: Option<&PaletteMapping>,
+    ) -> anyhow::Result<()> {
         for (_, group) in self.usage_by_image.into_iter() {
-            FindBiggerFragmentOnSingleGroupStruct::process(group, wan);
+            FindBiggerFragmentOnSingleGroupStruct::process(group, wan, mapping)?;
         }
+        Ok(())
     }
 }
 
@@ -253,10 +339,15 @@ struct FindBiggerFragmentOnSingleGroupStruct<'a> {
     group: HashMap<NormalizedBytes, BTreeSet<FragmentUse>>,
     lookup_by_use: HashMap<FragmentPosition, (NormalizedBytes, FragmentFlip)>,
     wan: &'a mut WanImage,
+    mapping: Option<&'a PaletteMapping>,
 }
 
 impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
-    fn process(group: HashMap<NormalizedBytes, BTreeSet<FragmentUse>>, wan: &'a mut WanImage) {
+    fn process(
+        group: HashMap<NormalizedBytes, BTreeSet<FragmentUse>>,
+        wan: &'a mut WanImage,
+        mapping: Option<&'a PaletteMapping>,
+    ) -> anyhow::Result<()> {
         let mut lookup_by_use = HashMap::new();
         for (key, value) in group.iter() {
             for usage in value {
@@ -275,6 +366,7 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
             group,
             lookup_by_use,
             wan,
+            mapping,
         };
 
         for (shape_indice, size_indice) in [
@@ -300,6 +392,26 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
 
         for (bytes, use_of_this_byte) in s.group.into_iter() {
             // TODO: this is mostly copy–pasted from the process_resolution function
+            let (pixels, pal_idx) = match s.mapping {
+                None => (bytes.0.to_vec(), 0),
+                Some(mapping) => {
+                    let sub_palette = match mapping.find_sub_palette(&bytes.0) {
+                        Some(sub_palette) => sub_palette,
+                        None => {
+                            // no panic: each bytes have at least one usage
+                            let usage = use_of_this_byte.iter().next().unwrap();
+                            bail!(
+                                "No sub-palette contains all the colors of the 8×8 fragment at {}, {} in the image {}",
+                                usage.x,
+                                usage.y,
+                                usage.image_id
+                            );
+                        }
+                    };
+                    // no panic: the sub-palette contains all those pixels
+                    (mapping.remap(sub_palette, &bytes.0).unwrap(), sub_palette)
+                }
+            };
             // add the bytes
             let image_bytes_index = s.wan.fragment_bytes_store.len();
             s.wan
@@ -307,8 +419,9 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
                 .fragment_bytes
                 .push(FragmentBytes {
                     mixed_pixels: encode_fragment_pixels(
-                        &bytes.0,
+                        &pixels,
                         OamShape::new(0, 0).unwrap().size(),
+                        s.wan.is_256_color,
                     )
                     .unwrap(),
                     z_index: 0,
@@ -325,11 +438,13 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
                     offset_x: usage.x.try_into().unwrap(),
                     flip: usage.flip,
                     is_mosaic: false,
-                    pal_idx: 0,
+                    pal_idx,
+                    alloc_counter: None,
                     resolution: OamShape::new(0, 0).unwrap(),
                 });
             }
         }
+        Ok(())
     }
 
     fn process_resolution(&mut self, resolution: OamShape) {
@@ -437,6 +552,20 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
                             continue 'skip_fragment_positon;
                         }
                     }
+                    // no panic: there is at least one usage for the fragment
+                    let base_bigger_fragment = base_bigger_fragment.unwrap();
+                    // And that all of its colors are in the same sub-palette
+                    let (pixels, pal_idx) = match self.mapping {
+                        None => (base_bigger_fragment.0, 0),
+                        Some(mapping) => match mapping.find_sub_palette(&base_bigger_fragment.0) {
+                            // no panic: the sub-palette contains all those pixels
+                            Some(sub_palette) => (
+                                mapping.remap(sub_palette, &base_bigger_fragment.0).unwrap(),
+                                sub_palette,
+                            ),
+                            None => continue 'skip_fragment_positon,
+                        },
+                    };
                     // Yay, we found a bunch of big fragment we can finally push that to Wan
                     // push the bytes
                     let image_bytes_index = self.wan.fragment_bytes_store.len();
@@ -445,8 +574,9 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
                         .fragment_bytes
                         .push(FragmentBytes {
                             mixed_pixels: encode_fragment_pixels(
-                                &base_bigger_fragment.unwrap().0,
+                                &pixels,
                                 resolution.size(),
+                                self.wan.is_256_color,
                             )
                             .unwrap(),
                             z_index: 0,
@@ -464,7 +594,8 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
                                 offset_x: position.x.try_into().unwrap(),
                                 flip,
                                 is_mosaic: false,
-                                pal_idx: 0,
+                                pal_idx,
+                                alloc_counter: None,
                                 resolution,
                             });
                     }
@@ -486,3 +617,117 @@ impl<'a> FindBiggerFragmentOnSingleGroupStruct<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        create_wan_from_multiple_images, create_wan_from_multiple_images_with_palette,
+        GeneralResolution, Palette, SpriteType,
+    };
+
+    #[test]
+    fn test_create_wan_from_multiple_images_position() {
+        for (width, height, start_x, start_y) in [(8, 8, 0, 0), (9, 9, 1, 1), (16, 16, 3, 5)] {
+            let mut pixels = vec![0; (width * height) as usize];
+            for y in start_y..start_y + 8 {
+                for x in start_x..start_x + 8 {
+                    pixels[(y * width + x) as usize] = 1;
+                }
+            }
+            let resolution = GeneralResolution::new(width, height);
+            let mut wan = create_wan_from_multiple_images(
+                &[(&pixels, resolution.clone()), (&pixels, resolution)],
+                SpriteType::Chara,
+            )
+            .unwrap();
+            wan.palette.palette = vec![[0, 0, 0, 0], [255, 0, 0, 128]];
+            for frame_id in 0..2 {
+                let (image, origin) = wan.render_frame(frame_id).unwrap();
+                let opaque: Vec<(i32, i32)> = image
+                    .enumerate_pixels()
+                    .filter(|(_, _, pixel)| pixel.0[3] != 0)
+                    .map(|(x, y, _)| (x as i32 - origin.0, y as i32 - origin.1))
+                    .collect();
+                assert_eq!(opaque.len(), 64);
+                assert_eq!(opaque[0], (start_x as i32, start_y as i32));
+            }
+        }
+    }
+
+    #[test]
+    fn test_create_wan_from_multiple_images_sparse() {
+        // Fragments covering less than 75% of their pixels should still be used
+        let points = [(2, 3), (3, 3), (5, 6)];
+        let mut pixels = vec![0; 16 * 16];
+        for (x, y) in points {
+            pixels[y * 16 + x] = 1;
+        }
+        let resolution = GeneralResolution::new(16, 16);
+        let mut wan = create_wan_from_multiple_images(
+            &[(&pixels, resolution.clone()), (&pixels, resolution)],
+            SpriteType::Chara,
+        )
+        .unwrap();
+        wan.palette.palette = vec![[0, 0, 0, 0], [255, 0, 0, 128]];
+        for frame_id in 0..2 {
+            let (image, origin) = wan.render_frame(frame_id).unwrap();
+            let opaque: Vec<(i32, i32)> = image
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel.0[3] != 0)
+                .map(|(x, y, _)| (x as i32 - origin.0, y as i32 - origin.1))
+                .collect();
+            assert_eq!(opaque, vec![(2, 3), (3, 3), (5, 6)]);
+        }
+    }
+
+    #[test]
+    fn test_create_wan_from_multiple_images_with_palette() {
+        let colors = [[0, 0, 0, 0], [255, 0, 0, 255], [0, 255, 0, 255]];
+        let mut palette = Palette::default();
+        palette.palette = vec![[0, 0, 0, 0]; 32];
+        palette.palette[1] = [255, 0, 0, 128];
+        palette.palette[16 + 5] = [0, 255, 0, 128];
+
+        // the left half is red, and the right half is green
+        let pixels: Vec<u8> = (0..16 * 8)
+            .map(|p| if p % 16 < 8 { 1 } else { 2 })
+            .collect();
+        let wan = create_wan_from_multiple_images_with_palette(
+            &[(&pixels, GeneralResolution::new(16, 8))],
+            SpriteType::PropsUI,
+            &colors,
+            &palette,
+        )
+        .unwrap();
+        assert_eq!(wan.palette, palette);
+        let mut pal_idx: Vec<u16> = wan.frame_store.frames[0]
+            .fragments
+            .iter()
+            .map(|f| f.pal_idx)
+            .collect();
+        pal_idx.sort_unstable();
+        assert_eq!(pal_idx, vec![0, 1]);
+        let (image, origin) = wan.render_frame(0).unwrap();
+        let x = origin.0 as u32;
+        let y = origin.1 as u32;
+        assert_eq!(image.get_pixel(x, y).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(x + 15, y + 7).0, [0, 255, 0, 255]);
+
+        // no sub-palette contains both red and green
+        let mixed: Vec<u8> = (0..64).map(|p| if p % 2 == 0 { 1 } else { 2 }).collect();
+        assert!(create_wan_from_multiple_images_with_palette(
+            &[(&mixed, GeneralResolution::new(8, 8))],
+            SpriteType::PropsUI,
+            &colors,
+            &palette,
+        )
+        .is_err());
+
+        // and without the palette, only the values less than 16 are accepted
+        assert!(create_wan_from_multiple_images(
+            &[(&[16; 64], GeneralResolution::new(8, 8))],
+            SpriteType::PropsUI
+        )
+        .is_err());
+    }
+}
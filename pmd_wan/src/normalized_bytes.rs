@@ -78,10 +78,10 @@ mod tests {
         base[63] = 1;
         let mut flipboth = [0; 64];
         flipboth[0] = 1;
-        let mut flipvert = [0; 64];
-        flipvert[63 - 7] = 1;
         let mut fliphor = [0; 64];
-        fliphor[7] = 1;
+        fliphor[63 - 7] = 1;
+        let mut flipvert = [0; 64];
+        flipvert[7] = 1;
         for (bytes, flip) in [
             (base, FragmentFlip::standard()),
             (flipboth, FragmentFlip::both()),
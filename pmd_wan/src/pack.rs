@@ -0,0 +1,366 @@
+use pmd_cpack::{CPack, CPackError};
+use pmd_pkdpx::{decompress_px, is_px, PXError};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Seek, Write};
+use thiserror::Error;
+
+use crate::{WanError, WanImage, WanWriteError};
+
+#[derive(Debug, Error)]
+pub enum WanPackError {
+    #[error("Failed to read the pack file")]
+    CPackError(#[from] CPackError),
+    #[error("The pack file doesn't have a file n°{0}")]
+    NoFile(usize),
+    #[error("Failed to decompress the PKDPX/AT4PX file: {0}")]
+    PXError(PXError),
+    #[error("an input/output error happened")]
+    IOError(#[from] io::Error),
+    #[error("Failed to decode the sprite")]
+    WanError(#[from] WanError),
+    #[error("Failed to encode the sprite n°{0}")]
+    WanWriteError(usize, #[source] WanWriteError),
+    #[error("The pack file would be {0} bytes long, but it can be at most 4GiB")]
+    PackTooLarge(u64),
+    #[error("The sprite n°{0} would be {1} bytes long once PKDPX compressed, but it can be at most 65535 bytes")]
+    CompressedTooLarge(usize, usize),
+}
+
+/// The size of the header of a PKDPX file
+const PKDPX_HEADER_LENGTH: usize = 20;
+/// How far back a PKDPX command can copy data from
+const PKDPX_WINDOW: usize = 0x1000;
+/// The shortest and longest copy a PKDPX command can do, as a high nibble of 0 is reserved for the control flags
+const PKDPX_MIN_COPY: usize = 4;
+const PKDPX_MAX_COPY: usize = 18;
+
+/// Compress the data in a PKDPX container.
+///
+/// All the control flags are 0, so a command with a high nibble of 0 output twice the byte made of its low nibble repeated (like `0x00 0x00` or `0x11 0x11`),
+/// and the other commands copy 4 to 18 bytes from the previous 4096 bytes. The other bytes are stored as-is.
+///
+/// Return the length of the result as an error if it is longer than 65535 bytes, the most a PKDPX container can hold.
+fn compress_pkdpx(data: &[u8]) -> Result<Vec<u8>, usize> {
+    let mut result = Vec::with_capacity(PKDPX_HEADER_LENGTH + data.len() + data.len() / 8 + 1);
+    result.extend(b"PKDPX");
+    // the container length is set at the end
+    result.extend([0; 2]);
+    // control flags
+    result.extend([0; 9]);
+    result.extend((data.len() as u32).to_le_bytes());
+
+    // the last position each 4 bytes sequence was found at, and the previous position with the same sequence for each position
+    let mut last_positions: HashMap<&[u8], usize> = HashMap::new();
+    let mut previous_positions = vec![usize::MAX; data.len()];
+
+    let mut position = 0;
+    let mut command_byte_position = 0;
+    let mut command_count = 0;
+    while position < data.len() {
+        if command_count % 8 == 0 {
+            command_byte_position = result.len();
+            result.push(0);
+        }
+        let command_bit = 0x80 >> (command_count % 8);
+        command_count += 1;
+
+        // find the longest copy. It may overlap with the data it produces.
+        let max_length = PKDPX_MAX_COPY.min(data.len() - position);
+        let mut best_copy: Option<(usize, usize)> = None;
+        if max_length >= PKDPX_MIN_COPY {
+            let mut candidate = last_positions
+                .get(&data[position..position + PKDPX_MIN_COPY])
+                .copied()
+                .unwrap_or(usize::MAX);
+            while candidate != usize::MAX && position - candidate <= PKDPX_WINDOW {
+                let length = (0..max_length)
+                    .take_while(|offset| data[candidate + offset] == data[position + offset])
+                    .count();
+                if best_copy.is_none_or(|(best_length, _)| length > best_length) {
+                    best_copy = Some((length, position - candidate));
+                }
+                if length == max_length {
+                    break;
+                }
+                candidate = previous_positions[candidate];
+            }
+        }
+        let is_pattern = data.len() - position >= 2
+            && data[position] == data[position + 1]
+            && data[position] >> 4 == data[position] & 0xF;
+
+        let consumed = match best_copy {
+            Some((length, distance))
+                if length > PKDPX_MIN_COPY || (length == PKDPX_MIN_COPY && !is_pattern) =>
+            {
+                let relative_offset = PKDPX_WINDOW - distance;
+                result.push((((length - 3) << 4) | (relative_offset >> 8)) as u8);
+                result.push((relative_offset & 0xFF) as u8);
+                length
+            }
+            _ if is_pattern => {
+                result.push(data[position] & 0xF);
+                2
+            }
+            _ => {
+                result[command_byte_position] |= command_bit;
+                result.push(data[position]);
+                1
+            }
+        };
+        for (inserted, previous_position) in previous_positions[position..position + consumed]
+            .iter_mut()
+            .enumerate()
+        {
+            let inserted = position + inserted;
+            if let Some(sequence) = data.get(inserted..inserted + PKDPX_MIN_COPY) {
+                if let Some(previous) = last_positions.insert(sequence, inserted) {
+                    *previous_position = previous;
+                }
+            }
+        }
+        position += consumed;
+    }
+
+    let container_length = result.len();
+    if container_length > u16::MAX as usize {
+        return Err(container_length);
+    }
+    result[5..7].copy_from_slice(&(container_length as u16).to_le_bytes());
+    Ok(result)
+}
+
+/// Write a pack file containing the given files.
+///
+/// The table of contents is followed by an empty entry, and padded to 64 bytes. Each file is padded to 16 bytes with 0xFF.
+pub fn write_pack<W: Write>(writer: &mut W, files: &[Vec<u8>]) -> Result<(), WanPackError> {
+    let header_lenght = (8 + (files.len() as u64 + 1) * 8).next_multiple_of(32);
+    let padded_header_lenght = header_lenght.next_multiple_of(64);
+
+    let mut offsets = Vec::with_capacity(files.len());
+    let mut offset = padded_header_lenght;
+    for file in files {
+        offsets.push(offset);
+        offset = (offset + file.len() as u64).next_multiple_of(16);
+    }
+    if offset > u32::MAX as u64 {
+        return Err(WanPackError::PackTooLarge(offset));
+    }
+
+    let mut header = Vec::with_capacity(padded_header_lenght as usize);
+    header.extend(0u32.to_le_bytes());
+    header.extend((files.len() as u32).to_le_bytes());
+    for (file, offset) in files.iter().zip(&offsets) {
+        header.extend((*offset as u32).to_le_bytes());
+        header.extend((file.len() as u32).to_le_bytes());
+    }
+    header.resize(header_lenght as usize, 0);
+    header.resize(padded_header_lenght as usize, 0xFF);
+    writer.write_all(&header)?;
+
+    for file in files {
+        writer.write_all(file)?;
+        writer.write_all(&vec![0xFF; file.len().next_multiple_of(16) - file.len()])?;
+    }
+    Ok(())
+}
+
+/// A pack file containing sprites, like `MONSTER/monster.bin`, `MONSTER/m_ground.bin` or `MONSTER/m_attack.bin`.
+///
+/// The files are decompressed if they are PKDPX or AT4PX compressed.
+pub struct WanPack<F: Read + Seek> {
+    cpack: CPack<F>,
+}
+
+impl<F: Read + Seek> WanPack<F> {
+    pub fn new(file: F) -> Result<Self, WanPackError> {
+        Ok(Self {
+            cpack: CPack::new_from_file(file)?,
+        })
+    }
+
+    /// Return the number of files in the pack
+    pub fn len(&self) -> usize {
+        self.cpack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpack.is_empty()
+    }
+
+    /// Return the content of the given file, as stored in the pack
+    pub fn get_raw_bytes(&self, index: usize) -> Result<Vec<u8>, WanPackError> {
+        if index >= self.cpack.len() {
+            return Err(WanPackError::NoFile(index));
+        }
+        let mut buffer = Vec::new();
+        self.cpack.get_file(index)?.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Return true if the given file is PKDPX or AT4PX compressed
+    pub fn is_compressed(&self, index: usize) -> Result<bool, WanPackError> {
+        if index >= self.cpack.len() {
+            return Err(WanPackError::NoFile(index));
+        }
+        is_px(&mut self.cpack.get_file(index)?).map_err(WanPackError::PXError)
+    }
+
+    /// Return the content of the given file, decompressed if needed
+    pub fn get_bytes(&self, index: usize) -> Result<Vec<u8>, WanPackError> {
+        let raw = self.get_raw_bytes(index)?;
+        if is_px(&mut Cursor::new(&raw)).map_err(WanPackError::PXError)? {
+            decompress_px(Cursor::new(raw)).map_err(WanPackError::PXError)
+        } else {
+            Ok(raw)
+        }
+    }
+
+    /// Decode the sprite stored in the given file
+    pub fn get(&self, index: usize) -> Result<WanImage, WanPackError> {
+        Ok(WanImage::from_bytes(&self.get_bytes(index)?)?)
+    }
+
+    /// Iterate over every sprite of the pack, with their index. An error for a file doesn't stop the iteration.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Result<WanImage, WanPackError>)> + '_ {
+        (0..self.len()).map(move |index| (index, self.get(index)))
+    }
+
+    /// Write a new pack file, where the files at the given indices are replaced by the given sprites. The other files are copied unchanged.
+    ///
+    /// If `compress` is true, the sprites replacing a compressed file are PKDPX compressed.
+    pub fn write_with_replacements<W: Write>(
+        &self,
+        writer: &mut W,
+        replacements: &[(usize, &WanImage)],
+        compress: bool,
+    ) -> Result<(), WanPackError> {
+        let mut files = Vec::with_capacity(self.len());
+        for index in 0..self.len() {
+            files.push(self.get_raw_bytes(index)?);
+        }
+        for (index, wan_image) in replacements {
+            if *index >= files.len() {
+                return Err(WanPackError::NoFile(*index));
+            }
+            let bytes = wan_image
+                .to_bytes()
+                .map_err(|err| WanPackError::WanWriteError(*index, err))?;
+            files[*index] = if compress && self.is_compressed(*index)? {
+                compress_pkdpx(&bytes)
+                    .map_err(|length| WanPackError::CompressedTooLarge(*index, length))?
+            } else {
+                bytes
+            };
+        }
+        write_pack(writer, &files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pmd_pkdpx::decompress_px;
+    use std::io::Cursor;
+
+    use super::compress_pkdpx;
+    use crate::{write_pack, SpriteType, WanImage, WanPack, WanPackError};
+
+    #[test]
+    fn test_wan_pack() {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 16];
+        let bytes = wan.to_bytes().unwrap();
+        let wan = WanImage::from_bytes(&bytes).unwrap();
+
+        let mut pack = Vec::new();
+        write_pack(
+            &mut pack,
+            &[bytes.clone(), compress_pkdpx(&bytes).unwrap(), vec![0; 15]],
+        )
+        .unwrap();
+        let pack = WanPack::new(Cursor::new(pack)).unwrap();
+
+        assert_eq!(pack.len(), 3);
+        assert_eq!(pack.get_bytes(0).unwrap(), bytes);
+        assert_eq!(pack.get_bytes(1).unwrap(), bytes);
+        let sprites: Vec<_> = pack.iter().collect();
+        assert_eq!(sprites.len(), 3);
+        assert_eq!(sprites[0].1.as_ref().unwrap(), &wan);
+        assert_eq!(sprites[1].1.as_ref().unwrap(), &wan);
+        assert!(matches!(sprites[2].1, Err(WanPackError::WanError(_))));
+        assert!(matches!(pack.get(3), Err(WanPackError::NoFile(3))));
+    }
+
+    #[test]
+    fn test_wan_pack_write() {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 16];
+        let bytes = wan.to_bytes().unwrap();
+        let mut original = Vec::new();
+        write_pack(
+            &mut original,
+            &[vec![1; 3], compress_pkdpx(&bytes).unwrap(), bytes.clone()],
+        )
+        .unwrap();
+        // the empty entry and padding, followed by the first file
+        assert_eq!(original[40..64], [0; 24]);
+        assert_eq!(
+            original[64..80],
+            [
+                1, 1, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+                0xFF
+            ]
+        );
+        let original = WanPack::new(Cursor::new(original)).unwrap();
+
+        wan.palette.palette[1] = [255, 0, 0, 128];
+        let mut rewritten = Vec::new();
+        original
+            .write_with_replacements(&mut rewritten, &[(1, &wan), (2, &wan)], true)
+            .unwrap();
+        let rewritten = WanPack::new(Cursor::new(rewritten)).unwrap();
+        assert_eq!(rewritten.len(), 3);
+        assert_eq!(rewritten.get_raw_bytes(0).unwrap(), vec![1; 3]);
+        assert!(rewritten.is_compressed(1).unwrap());
+        assert!(!rewritten.is_compressed(2).unwrap());
+        for index in 1..3 {
+            assert_eq!(
+                rewritten.get(index).unwrap().palette.palette[1],
+                [255, 0, 0, 128]
+            );
+        }
+
+        assert!(matches!(
+            original.write_with_replacements(&mut Vec::new(), &[(3, &wan)], true),
+            Err(WanPackError::NoFile(3))
+        ));
+    }
+
+    #[test]
+    fn test_compress_pkdpx() {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 16];
+        let bytes = wan.to_bytes().unwrap();
+        let compressed = compress_pkdpx(&bytes).unwrap();
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress_px(Cursor::new(compressed)).unwrap(), bytes);
+
+        // runs, copies overlapping what they produce, copies from the far end of the window, and bytes that can't be compressed
+        let mut data = vec![0x33, 0x33, 0x33, 0x12];
+        data.extend([7; 40]);
+        let mut state: u32 = 1;
+        let random: Vec<u8> = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        data.extend(&random);
+        data.extend(&random[1000..1100]);
+        data.extend([1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1]);
+        let compressed = compress_pkdpx(&data).unwrap();
+        assert_eq!(decompress_px(Cursor::new(compressed)).unwrap(), data);
+
+        assert!(compress_pkdpx(&random.repeat(14)[..70000]).is_err());
+    }
+}
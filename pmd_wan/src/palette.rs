@@ -1,24 +1,37 @@
-use crate::{wan_read_raw_4, WanError};
+use crate::{WanError, WanWriteError};
 use binwrite::BinWrite;
 use byteorder::{ReadBytesExt, LE};
 use std::io::{Read, Seek, SeekFrom, Write};
 
-#[derive(PartialEq, Eq, Debug, Default)]
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
 /// A palette, composed of group of 16 color when the first is transparent. Colors are RGBA.
 pub struct Palette {
     pub palette: Vec<[u8; 4]>,
+    /// The unknown u16 of the palette header. Normally 0.
+    pub unk1: u16,
+    /// The unknown u32 of the palette header. 0xFF0000 in the Explorers of Sky sprites.
+    pub unk2: u32,
 }
 
 impl Palette {
+    /// Create an empty palette, with the unknown values of the Explorers of Sky sprites
+    pub fn new_eos() -> Self {
+        Self {
+            palette: Vec::new(),
+            unk1: 0,
+            unk2: 0xFF << 16,
+        }
+    }
+
     /// load the Palette. Assume the cursor it located at the palette header
     pub fn new_from_bytes<F: Read + Seek>(file: &mut F) -> Result<Palette, WanError> {
         let mut palette = Vec::new();
         let pointer_palette_start = file.read_u32::<LE>()? as u64;
         trace!("start of palette : {}", pointer_palette_start);
 
-        file.read_u16::<LE>()?;
+        let unk1 = file.read_u16::<LE>()?;
         let nb_color = file.read_u16::<LE>()?;
-        wan_read_raw_4(file)?;
+        let unk2 = file.read_u32::<LE>()?;
         trace!(
             "palette_start: {}, nb_color: {}",
             pointer_palette_start,
@@ -35,14 +48,20 @@ impl Palette {
             let alpha = file.read_u8()?;
             palette.push([red, green, blue, alpha]);
         }
-        Ok(Palette { palette })
+        Ok(Palette {
+            palette,
+            unk1,
+            unk2,
+        })
     }
 
     /// Return the rgba color for the given color id and palette id.
+    /// Palettes are made of 16 colors, or 256 colors if `is_256_color` is true.
     /// Return [`Option::None`] if the color doesn't exist.
     /// Note that the alpha range from 0 to 128 normally, thought this is not an hard guarantee.
-    pub fn get(&self, id: u8, palette_id: u16) -> Option<[u8; 4]> {
-        let id = (id as usize).saturating_add(palette_id as usize * 16);
+    pub fn get(&self, id: u8, palette_id: u16, is_256_color: bool) -> Option<[u8; 4]> {
+        let palette_size = if is_256_color { 256 } else { 16 };
+        let id = (id as usize).saturating_add(palette_id as usize * palette_size);
         if id >= self.palette.len() {
             return None;
         };
@@ -65,7 +84,10 @@ impl Palette {
         Err(WanError::CantFindColorInPalette)
     }
 
-    pub fn write<F: Write + Seek>(&self, file: &mut F) -> Result<u64, WanError> {
+    pub fn write<F: Write + Seek>(&self, file: &mut F) -> Result<u64, WanWriteError> {
+        if self.palette.len() > u16::MAX as usize {
+            return Err(WanWriteError::TooManyColors(self.palette.len()));
+        }
         let start_offset = file.seek(SeekFrom::Current(0))?;
         for color in &self.palette {
             color.write(file)?;
@@ -74,10 +96,10 @@ impl Palette {
         let header_offset = file.seek(SeekFrom::Current(0))?;
         (
             start_offset as u32,
-            0u16, //unk
+            self.unk1,
             self.palette.len() as u16,
-            (0xFF << 16) as u32, //unk
-            0u32,                //magic
+            self.unk2,
+            0u32, //magic
         )
             .write(file)?;
 
@@ -0,0 +1,82 @@
+use crate::{image_tool::alpha_to_ds, Palette};
+
+/// Map the pixel values of paletted images (like those returned by [`crate::image_tool::image_to_paletted_bytes`]) to the sub-palettes of a [`Palette`].
+///
+/// Each sub-palette is a group of 16 colors of the [`Palette`], whose first color is transparent.
+/// The pixel value 0 is always transparent, and is kept as 0 in every sub-palette.
+#[derive(Debug, Clone)]
+pub struct PaletteMapping {
+    /// For each sub-palette, the index in this sub-palette of each pixel value, if it is present.
+    sub_palettes: Vec<[Option<u8>; 256]>,
+}
+
+impl PaletteMapping {
+    /// Create the mapping from the color of each pixel value (with the alpha ranging from 0 to 255, as in an image) and the [`Palette`] (with the alpha ranging from 0 to 128).
+    /// A color is only mapped to colors of a sub-palette with the same alpha once converted with [`alpha_to_ds`].
+    pub fn new(colors: &[[u8; 4]], palette: &Palette) -> Self {
+        let mut sub_palettes = Vec::new();
+        for sub_palette in palette.palette.chunks(16) {
+            let mut mapping = [None; 256];
+            mapping[0] = Some(0);
+            for (pixel_value, color) in colors.iter().enumerate().take(256).skip(1) {
+                mapping[pixel_value] = sub_palette
+                    .iter()
+                    .enumerate()
+                    .skip(1)
+                    .find(|(_, palette_color)| {
+                        palette_color[0..3] == color[0..3]
+                            && palette_color[3] == alpha_to_ds(color[3])
+                    })
+                    .map(|(local_id, _)| local_id as u8);
+            }
+            sub_palettes.push(mapping);
+        }
+        Self { sub_palettes }
+    }
+
+    /// Return the id of the first sub-palette that contains the colors of all of the given pixels.
+    pub fn find_sub_palette(&self, pixels: &[u8]) -> Option<u16> {
+        self.sub_palettes
+            .iter()
+            .position(|mapping| {
+                pixels
+                    .iter()
+                    .all(|pixel| mapping[*pixel as usize].is_some())
+            })
+            .map(|id| id as u16)
+    }
+
+    /// Return the pixels with their value replaced by their index in the given sub-palette.
+    /// Return [`None`] if the sub-palette doesn't exist or doesn't contain one of the colors.
+    pub fn remap(&self, sub_palette: u16, pixels: &[u8]) -> Option<Vec<u8>> {
+        let mapping = self.sub_palettes.get(sub_palette as usize)?;
+        pixels
+            .iter()
+            .map(|pixel| mapping[*pixel as usize])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Palette, PaletteMapping};
+
+    #[test]
+    fn test_palette_mapping() {
+        let mut palette = Palette::default();
+        palette.palette = vec![[0, 0, 0, 0]; 32];
+        palette.palette[3] = [255, 0, 0, 128];
+        palette.palette[16 + 1] = [0, 255, 0, 128];
+        palette.palette[16 + 2] = [255, 0, 0, 128];
+        let colors = [[0, 0, 0, 0], [255, 0, 0, 255], [0, 255, 0, 255]];
+        let mapping = PaletteMapping::new(&colors, &palette);
+
+        assert_eq!(mapping.find_sub_palette(&[0, 1, 1]), Some(0));
+        assert_eq!(mapping.find_sub_palette(&[1, 2]), Some(1));
+        assert_eq!(mapping.find_sub_palette(&[3]), None);
+        assert_eq!(mapping.remap(0, &[0, 1]), Some(vec![0, 3]));
+        assert_eq!(mapping.remap(1, &[2, 1, 0]), Some(vec![1, 2, 0]));
+        assert_eq!(mapping.remap(0, &[2]), None);
+        assert_eq!(mapping.remap(2, &[0]), None);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::WanImage;
+
+/// Return, for each element, its new index once the unused elements are removed, and the number of elements kept.
+/// An out of range index stays out of range, as it is shifted by the number of removed elements.
+fn compact_indices(used: &[bool]) -> (Vec<usize>, usize) {
+    let mut new_indices = Vec::with_capacity(used.len());
+    let mut kept = 0;
+    for is_used in used {
+        new_indices.push(kept);
+        if *is_used {
+            kept += 1;
+        }
+    }
+    (new_indices, kept)
+}
+
+fn remap_index(new_indices: &[usize], kept: usize, index: usize) -> usize {
+    match new_indices.get(index) {
+        Some(new_index) => *new_index,
+        None => index - (new_indices.len() - kept),
+    }
+}
+
+fn retain_used<T>(elements: &mut Vec<T>, used: &[bool]) {
+    let mut used = used.iter();
+    elements.retain(|_| *used.next().unwrap_or(&true));
+}
+
+impl WanImage {
+    /// Remove the [`crate::Frame`]s not used by any [`crate::AnimationFrame`], and the [`crate::FragmentBytes`] not used by any [`crate::Fragment`] of the remaining frames.
+    /// If `remove_unused_palettes` is true, the sub-palettes not used by any [`crate::Fragment`] are also removed.
+    ///
+    /// The `frame_id`, `fragment_bytes_index` and `pal_idx` are updated to point to the same element as before.
+    /// Index that were already pointing to a non-existing element keep pointing to a non-existing element.
+    pub fn remove_unused(&mut self, remove_unused_palettes: bool) {
+        // frames
+        let mut used_frames = vec![false; self.frame_store.frames.len()];
+        for animation in self.animation_store.anim_groups.iter().flatten() {
+            for animation_frame in &animation.frames {
+                if let Some(used) = used_frames.get_mut(animation_frame.frame_id as usize) {
+                    *used = true;
+                }
+            }
+        }
+        let (new_frame_ids, kept_frames) = compact_indices(&used_frames);
+        if kept_frames != used_frames.len() {
+            // The preserved value may have been computed from a removed frame
+            self.size_to_allocate_for_max_frame = None;
+        }
+        retain_used(&mut self.frame_store.frames, &used_frames);
+        for animation in self.animation_store.anim_groups.iter_mut().flatten() {
+            for animation_frame in &mut animation.frames {
+                animation_frame.frame_id = remap_index(
+                    &new_frame_ids,
+                    kept_frames,
+                    animation_frame.frame_id as usize,
+                ) as u16;
+            }
+        }
+
+        // fragment bytes
+        let mut used_fragment_bytes = vec![false; self.fragment_bytes_store.len()];
+        for fragment in self.frame_store.frames.iter().flat_map(|f| &f.fragments) {
+            if let Some(used) = used_fragment_bytes.get_mut(fragment.fragment_bytes_index) {
+                *used = true;
+            }
+        }
+        let (new_fragment_bytes_indices, kept_fragment_bytes) =
+            compact_indices(&used_fragment_bytes);
+        retain_used(
+            &mut self.fragment_bytes_store.fragment_bytes,
+            &used_fragment_bytes,
+        );
+        for fragment in self
+            .frame_store
+            .frames
+            .iter_mut()
+            .flat_map(|f| &mut f.fragments)
+        {
+            fragment.fragment_bytes_index = remap_index(
+                &new_fragment_bytes_indices,
+                kept_fragment_bytes,
+                fragment.fragment_bytes_index,
+            );
+        }
+
+        // sub-palettes
+        if remove_unused_palettes {
+            let palette_size = if self.is_256_color { 256 } else { 16 };
+            let mut used_palettes = vec![false; self.palette.palette.len().div_ceil(palette_size)];
+            for fragment in self.frame_store.frames.iter().flat_map(|f| &f.fragments) {
+                if let Some(used) = used_palettes.get_mut(fragment.pal_idx as usize) {
+                    *used = true;
+                }
+            }
+            let (new_palette_ids, kept_palettes) = compact_indices(&used_palettes);
+            let mut sub_palettes: Vec<Vec<[u8; 4]>> = self
+                .palette
+                .palette
+                .chunks(palette_size)
+                .map(|c| c.to_vec())
+                .collect();
+            retain_used(&mut sub_palettes, &used_palettes);
+            self.palette.palette = sub_palettes.concat();
+            for fragment in self
+                .frame_store
+                .frames
+                .iter_mut()
+                .flat_map(|f| &mut f.fragments)
+            {
+                fragment.pal_idx =
+                    remap_index(&new_palette_ids, kept_palettes, fragment.pal_idx as usize) as u16;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Animation, AnimationFrame, Fragment, FragmentBytes, FragmentFlip, Frame, OamShape,
+        SpriteType, WanImage,
+    };
+
+    fn fragment_with_palette(fragment_bytes_index: usize, pal_idx: u16) -> Fragment {
+        Fragment {
+            unk1: 0,
+            unk3_4: None,
+            unk5: false,
+            fragment_bytes_index,
+            offset_y: 0,
+            offset_x: 0,
+            flip: FragmentFlip::standard(),
+            is_mosaic: false,
+            pal_idx,
+            alloc_counter: None,
+            resolution: OamShape::new(0, 0).unwrap(),
+        }
+    }
+
+    fn animation_frame(frame_id: u16) -> AnimationFrame {
+        AnimationFrame {
+            duration: 1,
+            flag: 0,
+            frame_id,
+            offset_x: 0,
+            offset_y: 0,
+            shadow_offset_x: 0,
+            shadow_offset_y: 0,
+        }
+    }
+
+    #[test]
+    fn test_remove_unused() {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = (0..48).map(|i| [i, 0, 0, 128]).collect();
+        for z_index in 0..4 {
+            wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+                mixed_pixels: vec![0; 64],
+                z_index,
+            });
+        }
+        wan.frame_store.frames = vec![
+            Frame {
+                fragments: vec![fragment_with_palette(0, 0)],
+                frame_offset: None,
+            },
+            Frame {
+                fragments: vec![fragment_with_palette(3, 2), fragment_with_palette(5, 0)],
+                frame_offset: None,
+            },
+            Frame {
+                fragments: vec![fragment_with_palette(1, 1)],
+                frame_offset: None,
+            },
+        ];
+        wan.animation_store.anim_groups = vec![vec![Animation {
+            frames: vec![animation_frame(1), animation_frame(3), animation_frame(1)],
+        }]];
+
+        wan.remove_unused(false);
+        assert_eq!(wan.palette.palette.len(), 48);
+        assert_eq!(wan.frame_store.frames.len(), 1);
+        assert_eq!(
+            wan.animation_store.anim_groups[0][0]
+                .frames
+                .iter()
+                .map(|f| f.frame_id)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 0]
+        );
+        assert_eq!(wan.fragment_bytes_store.len(), 1);
+        assert_eq!(wan.fragment_bytes_store.fragment_bytes[0].z_index, 3);
+        assert_eq!(
+            wan.frame_store.frames[0].fragments[0].fragment_bytes_index,
+            0
+        );
+        assert_eq!(
+            wan.frame_store.frames[0].fragments[1].fragment_bytes_index,
+            2
+        );
+
+        wan.remove_unused(true);
+        assert_eq!(wan.palette.palette.len(), 32);
+        assert_eq!(wan.palette.palette[0], [0, 0, 0, 128]);
+        assert_eq!(wan.palette.palette[16], [32, 0, 0, 128]);
+        assert_eq!(wan.frame_store.frames[0].fragments[0].pal_idx, 1);
+        assert_eq!(wan.frame_store.frames[0].fragments[1].pal_idx, 0);
+    }
+}
@@ -0,0 +1,346 @@
+use image::{imageops, RgbaImage};
+use std::cmp::Reverse;
+use thiserror::Error;
+
+use crate::{FragmentBytesToImageError, WanImage};
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("The frame {0} doesn't exist")]
+    NoFrame(usize),
+    #[error("Can't get the image of the fragment {1} of the frame {0}")]
+    FragmentImage(usize, usize, #[source] FragmentBytesToImageError),
+    #[error("The animation group {0} doesn't exist")]
+    NoAnimationGroup(usize),
+    #[error("The animation {1} doesn't exist in the animation group {0}")]
+    NoAnimation(usize, usize),
+}
+
+/// A single frame of a [`RenderedAnimation`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedAnimationFrame {
+    pub image: RgbaImage,
+    /// How long this frame is displayed, in DS frame (1/60 of a second)
+    pub duration: u8,
+}
+
+/// The output of [`WanImage::render_animation`].
+/// All the frames have the same size, and share the same origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedAnimation {
+    pub frames: Vec<RenderedAnimationFrame>,
+    /// The xy position of the origin of the sprite, relative to the top-left of each frame image.
+    pub origin: (i32, i32),
+}
+
+impl WanImage {
+    /// Assemble all the [`crate::Fragment`] of the given [`crate::Frame`] into a single image.
+    ///
+    /// Flip, position and palette of each fragment are applied. Fragment with the greater [`crate::FragmentBytes::z_index`] are drawn behind the others.
+    /// For those with the same z index, the first fragment of the frame is drawn on top of the following ones, like the DS OAM does.
+    ///
+    /// Result:
+    /// 1. The image assembling all the fragment from the frame, cropped to the [`crate::Frame::bounding_box`]
+    /// 2. The xy position of the origin of the frame, relative to the top-left of the result image. It may be outside of the image.
+    ///
+    /// A frame without fragment result in a 0×0 image.
+    pub fn render_frame(&self, frame_id: usize) -> Result<(RgbaImage, (i32, i32)), RenderError> {
+        let frame = self
+            .frame_store
+            .frames
+            .get(frame_id)
+            .ok_or(RenderError::NoFrame(frame_id))?;
+        let (x_min, y_min, x_max, y_max) = match frame.bounding_box() {
+            Some(bounding_box) => bounding_box,
+            None => return Ok((RgbaImage::new(0, 0), (0, 0))),
+        };
+        let mut image = RgbaImage::new((x_max - x_min) as u32, (y_max - y_min) as u32);
+        self.draw_frame(frame_id, &mut image, -x_min, -y_min)?;
+        Ok((image, (-x_min, -y_min)))
+    }
+
+    /// Render all the [`crate::AnimationFrame`] of an [`crate::Animation`], in the order they are played.
+    ///
+    /// Each frame is drawn with its offset applied. The canvas is large enough to contain every frame of the animation, so they can be played one after the other without any jitter.
+    pub fn render_animation(
+        &self,
+        anim_group_id: usize,
+        animation_id: usize,
+    ) -> Result<RenderedAnimation, RenderError> {
+        let animation = self
+            .animation_store
+            .anim_groups
+            .get(anim_group_id)
+            .ok_or(RenderError::NoAnimationGroup(anim_group_id))?
+            .get(animation_id)
+            .ok_or(RenderError::NoAnimation(anim_group_id, animation_id))?;
+
+        // 1. find the size of the canvas
+        let mut canvas_box: Option<(i32, i32, i32, i32)> = None;
+        for animation_frame in &animation.frames {
+            let frame_id = animation_frame.frame_id as usize;
+            let frame = self
+                .frame_store
+                .frames
+                .get(frame_id)
+                .ok_or(RenderError::NoFrame(frame_id))?;
+            if let Some((x_min, y_min, x_max, y_max)) = frame.bounding_box() {
+                let (offset_x, offset_y) = (
+                    animation_frame.offset_x as i32,
+                    animation_frame.offset_y as i32,
+                );
+                let (x_min, y_min, x_max, y_max) = (
+                    x_min + offset_x,
+                    y_min + offset_y,
+                    x_max + offset_x,
+                    y_max + offset_y,
+                );
+                canvas_box = Some(match canvas_box {
+                    None => (x_min, y_min, x_max, y_max),
+                    Some((a, b, c, d)) => (a.min(x_min), b.min(y_min), c.max(x_max), d.max(y_max)),
+                });
+            }
+        }
+        let (x_min, y_min, x_max, y_max) = canvas_box.unwrap_or((0, 0, 0, 0));
+        let origin = (-x_min, -y_min);
+
+        // 2. draw each frame on its own canvas
+        let mut frames = Vec::with_capacity(animation.frames.len());
+        for animation_frame in &animation.frames {
+            let mut image = RgbaImage::new((x_max - x_min) as u32, (y_max - y_min) as u32);
+            self.draw_frame(
+                animation_frame.frame_id as usize,
+                &mut image,
+                origin.0 + animation_frame.offset_x as i32,
+                origin.1 + animation_frame.offset_y as i32,
+            )?;
+            frames.push(RenderedAnimationFrame {
+                image,
+                duration: animation_frame.duration,
+            });
+        }
+
+        Ok(RenderedAnimation { frames, origin })
+    }
+
+    /// Draw the given frame on top of the target image, with its origin at `origin_x`, `origin_y`.
+    /// Part of the frame outside of the target image are cut.
+    pub(crate) fn draw_frame(
+        &self,
+        frame_id: usize,
+        target: &mut RgbaImage,
+        origin_x: i32,
+        origin_y: i32,
+    ) -> Result<(), RenderError> {
+        let frame = self
+            .frame_store
+            .frames
+            .get(frame_id)
+            .ok_or(RenderError::NoFrame(frame_id))?;
+
+        let mut draw_order: Vec<usize> = (0..frame.fragments.len()).collect();
+        draw_order.sort_by_key(|fragment_id| {
+            let z_index = self
+                .fragment_bytes_store
+                .fragment_bytes
+                .get(frame.fragments[*fragment_id].fragment_bytes_index)
+                .map(|b| b.z_index)
+                .unwrap_or(0);
+            (Reverse(z_index), Reverse(*fragment_id))
+        });
+
+        for fragment_id in draw_order {
+            let fragment = &frame.fragments[fragment_id];
+            let mut fragment_image = self
+                .get_image_for_fragment(fragment)
+                .map_err(|err| RenderError::FragmentImage(frame_id, fragment_id, err))?;
+            if fragment.flip.flip_h {
+                imageops::flip_horizontal_in_place(&mut fragment_image);
+            }
+            if fragment.flip.flip_v {
+                imageops::flip_vertical_in_place(&mut fragment_image);
+            }
+            imageops::overlay(
+                target,
+                &fragment_image,
+                (origin_x + fragment.offset_x as i32) as i64,
+                (origin_y + fragment.offset_y as i32) as i64,
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        encode_fragment_pixels, Animation, AnimationFrame, Fragment, FragmentBytes, FragmentFlip,
+        Frame, OamShape, RenderError, SpriteType, WanImage,
+    };
+    use image::Rgba;
+
+    /// An 8×8 fragment
+    fn small_fragment(fragment_bytes_index: usize, offset_x: i16, offset_y: i8) -> Fragment {
+        Fragment {
+            unk1: 0,
+            unk3_4: None,
+            unk5: false,
+            fragment_bytes_index,
+            offset_y,
+            offset_x,
+            flip: FragmentFlip::standard(),
+            is_mosaic: false,
+            pal_idx: 0,
+            alloc_counter: None,
+            resolution: OamShape::new(0, 0).unwrap(),
+        }
+    }
+
+    /// A sprite with two 8×8 [`crate::FragmentBytes`]: one with only its top-left pixel set to the color 1, and one fully of the color 2
+    fn wan_with_two_fragment_bytes() -> WanImage {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 32];
+        wan.palette.palette[1] = [255, 0, 0, 128];
+        wan.palette.palette[2] = [0, 255, 0, 128];
+        wan.palette.palette[16 + 1] = [0, 0, 255, 128];
+        // top-left pixel only
+        let mut top_left = [0; 64];
+        top_left[0] = 1;
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: encode_fragment_pixels(
+                &top_left,
+                OamShape::new(0, 0).unwrap().size(),
+                false,
+            )
+            .unwrap(),
+            z_index: 0,
+        });
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: encode_fragment_pixels(
+                &[2; 64],
+                OamShape::new(0, 0).unwrap().size(),
+                false,
+            )
+            .unwrap(),
+            z_index: 0,
+        });
+        wan
+    }
+
+    #[test]
+    fn test_render_frame_position_and_flip() {
+        let mut wan = wan_with_two_fragment_bytes();
+        let mut flipped = small_fragment(0, -8, 0);
+        flipped.flip = FragmentFlip::from_bools(true, true);
+        let mut other_palette = small_fragment(0, 0, -4);
+        other_palette.pal_idx = 1;
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![flipped, other_palette],
+            frame_offset: None,
+        });
+
+        let (image, origin) = wan.render_frame(0).unwrap();
+        assert_eq!(origin, (8, 4));
+        assert_eq!(image.dimensions(), (16, 12));
+        assert_eq!(image.get_pixel(7, 11), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(0, 4), &Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(8, 0), &Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn test_render_frame_draw_order() {
+        let mut wan = wan_with_two_fragment_bytes();
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![small_fragment(0, 0, 0), small_fragment(1, 0, 0)],
+            frame_offset: None,
+        });
+        let (image, _) = wan.render_frame(0).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+
+        wan.fragment_bytes_store.fragment_bytes[0].z_index = 1;
+        let (image, _) = wan.render_frame(0).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn test_render_frame_error() {
+        let mut wan = wan_with_two_fragment_bytes();
+        assert!(wan.render_frame(0).is_err());
+        wan.frame_store.frames.push(Frame::default());
+        let (image, origin) = wan.render_frame(0).unwrap();
+        assert_eq!(image.dimensions(), (0, 0));
+        assert_eq!(origin, (0, 0));
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![small_fragment(5, 0, 0)],
+            frame_offset: None,
+        });
+        assert!(wan.render_frame(1).is_err());
+    }
+
+    fn animation_frame(
+        frame_id: u16,
+        duration: u8,
+        offset_x: i16,
+        offset_y: i16,
+    ) -> AnimationFrame {
+        AnimationFrame {
+            duration,
+            flag: 0,
+            frame_id,
+            offset_x,
+            offset_y,
+            shadow_offset_x: 0,
+            shadow_offset_y: 0,
+        }
+    }
+
+    #[test]
+    fn test_render_animation() {
+        let mut wan = wan_with_two_fragment_bytes();
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![small_fragment(0, 0, 0)],
+            frame_offset: None,
+        });
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![small_fragment(1, -4, -4)],
+            frame_offset: None,
+        });
+        wan.animation_store.anim_groups.push(vec![Animation {
+            frames: vec![animation_frame(0, 3, 10, 0), animation_frame(1, 5, 0, 0)],
+        }]);
+
+        let rendered = wan.render_animation(0, 0).unwrap();
+        assert_eq!(rendered.origin, (4, 4));
+        assert_eq!(rendered.frames.len(), 2);
+        assert_eq!(rendered.frames[0].duration, 3);
+        assert_eq!(rendered.frames[1].duration, 5);
+        for frame in &rendered.frames {
+            assert_eq!(frame.image.dimensions(), (22, 12));
+        }
+        assert_eq!(
+            rendered.frames[0].image.get_pixel(14, 4),
+            &Rgba([255, 0, 0, 255])
+        );
+        assert_eq!(
+            rendered.frames[0].image.get_pixel(0, 0),
+            &Rgba([0, 0, 0, 0])
+        );
+        assert_eq!(
+            rendered.frames[1].image.get_pixel(0, 0),
+            &Rgba([0, 255, 0, 255])
+        );
+        assert_eq!(
+            rendered.frames[1].image.get_pixel(14, 4),
+            &Rgba([0, 0, 0, 0])
+        );
+
+        assert!(matches!(
+            wan.render_animation(1, 0),
+            Err(RenderError::NoAnimationGroup(1))
+        ));
+        assert!(matches!(
+            wan.render_animation(0, 1),
+            Err(RenderError::NoAnimation(0, 1))
+        ));
+    }
+}
@@ -0,0 +1,649 @@
+use image::{GenericImageView, Rgba, RgbaImage};
+use spritebot_storage::{Sprite, SpriteBotStorageError};
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::{
+    create_wan_from_multiple_images_with_palette,
+    image_tool::{cluster_sub_palettes, ImageToPaletteBytesData, SubPaletteClusteringError},
+    Animation, AnimationFrame, FrameOffset, GeneralResolution, RenderError, SpriteType, WanImage,
+};
+
+/// The conventional name of the animation groups of the Chara sprites, as used by SpriteBot.
+/// The position in this list is the id of the animation group.
+pub const SPRITEBOT_ANIMATION_NAMES: [&str; 35] = [
+    "Walk",
+    "Attack",
+    "Kick",
+    "Shoot",
+    "Strike",
+    "Sleep",
+    "Hurt",
+    "Idle",
+    "Swing",
+    "Double",
+    "Hop",
+    "Charge",
+    "Rotate",
+    "EventSleep",
+    "Wake",
+    "Eat",
+    "Tumble",
+    "Pose",
+    "Pull",
+    "Pain",
+    "Float",
+    "DeepBreath",
+    "Nod",
+    "Sit",
+    "LookUp",
+    "Sink",
+    "Trip",
+    "Laying",
+    "LeapForth",
+    "Head",
+    "Cringe",
+    "LostBalance",
+    "TumbleBack",
+    "Faint",
+    "HitGround",
+];
+
+/// Return the SpriteBot name of the given animation group. Animation groups without a conventional name are named `Anim` followed by their id.
+pub fn spritebot_animation_name(anim_group_id: usize) -> String {
+    match SPRITEBOT_ANIMATION_NAMES.get(anim_group_id) {
+        Some(name) => name.to_string(),
+        None => format!("Anim{}", anim_group_id),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SpriteBotError {
+    #[error("Only Chara sprites can be converted from or to SpriteBot, but this sprite is {0:?}")]
+    NotChara(SpriteType),
+    #[error("The frame {0} doesn't exist")]
+    NoFrame(usize),
+    #[error("The frame {0} doesn't have a FrameOffset, which is mandatory for Chara sprites")]
+    NoFrameOffset(usize),
+    #[error("A point of the animation group {0} is too far from the others to be stored in a SpriteBot sheet")]
+    TooLargeSheet(usize),
+    #[error("Failed to render a frame of the animation group {0}")]
+    RenderError(usize, #[source] RenderError),
+    #[error("Error with the SpriteBot storage")]
+    StorageError(#[from] SpriteBotStorageError),
+    #[error(
+        "The sprite use {0} colors or more, but only 255 colors and transparency can be stored"
+    )]
+    TooManyColors(usize),
+    #[error("Failed to split the colors of the sprite in sub-palettes")]
+    SubPaletteError(#[from] SubPaletteClusteringError),
+    #[error(
+        "The frame {2} of the row {1} of the animation {0} is too large to be stored in a frame"
+    )]
+    FrameTooLarge(String, usize, usize),
+    #[error("A fragment of the frame {2} of the row {1} of the animation {0} would be placed at {3}, {4}, outside of the range of the possible positions")]
+    FragmentOutOfRange(String, usize, usize, i32, i32),
+    #[error("Failed to convert the images to a wan sprite")]
+    ConvertError(#[source] anyhow::Error),
+}
+
+/// Convert an Explorers of Sky Chara [`WanImage`] to a SpriteBot [`Sprite`].
+///
+/// Each non-empty animation group become a SpriteBot animation, with one row per [`crate::Animation`] (normally one per direction).
+/// All the frames of a group share the same size, with the origin of the sprite at their center, where [`spritebot_to_wan`] expects it. The [`crate::FrameOffset`] of each frame end up in the offsets sheet, and the shadow offset of the [`crate::AnimationFrame`] in the shadow sheet.
+/// The shadow size isn't stored in the wan file, and should be provided.
+pub fn wan_to_spritebot(wan_image: &WanImage, shadow_size: u8) -> Result<Sprite, SpriteBotError> {
+    if wan_image.sprite_type != SpriteType::Chara {
+        return Err(SpriteBotError::NotChara(wan_image.sprite_type));
+    }
+    let mut sprite = Sprite::new_empty(shadow_size);
+
+    for (anim_group_id, anim_group) in wan_image.animation_store.anim_groups.iter().enumerate() {
+        if anim_group.is_empty() {
+            continue;
+        }
+
+        // 1. find the size of the frames, shared by the whole animation group
+        let mut group_box: Option<(i32, i32, i32, i32)> = None;
+        let mut add_point = |x: i32, y: i32, x_end: i32, y_end: i32| {
+            group_box = Some(match group_box {
+                None => (x, y, x_end, y_end),
+                Some((a, b, c, d)) => (a.min(x), b.min(y), c.max(x_end), d.max(y_end)),
+            });
+        };
+        for animation in anim_group {
+            for animation_frame in &animation.frames {
+                let frame_id = animation_frame.frame_id as usize;
+                let frame = wan_image
+                    .frame_store
+                    .frames
+                    .get(frame_id)
+                    .ok_or(SpriteBotError::NoFrame(frame_id))?;
+                let frame_offset = frame
+                    .frame_offset
+                    .as_ref()
+                    .ok_or(SpriteBotError::NoFrameOffset(frame_id))?;
+                let (offset_x, offset_y) = (
+                    animation_frame.offset_x as i32,
+                    animation_frame.offset_y as i32,
+                );
+                if let Some((x_min, y_min, x_max, y_max)) = frame.bounding_box() {
+                    add_point(
+                        x_min + offset_x,
+                        y_min + offset_y,
+                        x_max + offset_x,
+                        y_max + offset_y,
+                    );
+                }
+                for point in [
+                    frame_offset.head,
+                    frame_offset.hand_left,
+                    frame_offset.hand_right,
+                    frame_offset.center,
+                ] {
+                    let x = point.0 as i32 + offset_x;
+                    let y = point.1 as i32 + offset_y;
+                    add_point(x, y, x + 1, y + 1);
+                }
+                let shadow_x = animation_frame.shadow_offset_x as i32;
+                let shadow_y = animation_frame.shadow_offset_y as i32;
+                add_point(shadow_x, shadow_y, shadow_x + 1, shadow_y + 1);
+            }
+        }
+        let (x_min, y_min, x_max, y_max) = match group_box {
+            Some(group_box) => group_box,
+            None => continue,
+        };
+        // the frames are symmetric around the origin, so it end up at their center
+        let (origin_x, origin_y) = (x_max.max(-x_min), y_max.max(-y_min));
+        let (width, height) = ((origin_x * 2) as u32, (origin_y * 2) as u32);
+        let to_sheet_position = |x: i32, y: i32| -> Result<(u16, u16), SpriteBotError> {
+            Ok((
+                (x + origin_x)
+                    .try_into()
+                    .map_err(|_| SpriteBotError::TooLargeSheet(anim_group_id))?,
+                (y + origin_y)
+                    .try_into()
+                    .map_err(|_| SpriteBotError::TooLargeSheet(anim_group_id))?,
+            ))
+        };
+
+        // 2. draw each frame
+        let mut images = Vec::new();
+        for animation in anim_group {
+            let mut row = Vec::new();
+            for animation_frame in &animation.frames {
+                let frame_id = animation_frame.frame_id as usize;
+                // no panic: checked in the previous loop
+                let frame_offset = wan_image.frame_store.frames[frame_id]
+                    .frame_offset
+                    .as_ref()
+                    .unwrap();
+                let (offset_x, offset_y) = (
+                    animation_frame.offset_x as i32,
+                    animation_frame.offset_y as i32,
+                );
+                let mut image = RgbaImage::new(width, height);
+                wan_image
+                    .draw_frame(
+                        frame_id,
+                        &mut image,
+                        origin_x + offset_x,
+                        origin_y + offset_y,
+                    )
+                    .map_err(|err| SpriteBotError::RenderError(anim_group_id, err))?;
+                let point =
+                    |p: (i16, i16)| to_sheet_position(p.0 as i32 + offset_x, p.1 as i32 + offset_y);
+                row.push(spritebot_storage::Frame {
+                    duration: animation_frame.duration,
+                    image,
+                    offsets: spritebot_storage::FrameOffset {
+                        head: point(frame_offset.head)?,
+                        hand_left: point(frame_offset.hand_left)?,
+                        hand_right: point(frame_offset.hand_right)?,
+                        center: point(frame_offset.center)?,
+                        shadow: to_sheet_position(
+                            animation_frame.shadow_offset_x as i32,
+                            animation_frame.shadow_offset_y as i32,
+                        )?,
+                    },
+                });
+            }
+            images.push(row);
+        }
+
+        sprite.animations.push(spritebot_storage::Animation {
+            name: spritebot_animation_name(anim_group_id),
+            index: anim_group_id as u32,
+            rush_frame: None,
+            hit_frame: None,
+            return_frame: None,
+            images,
+        });
+    }
+
+    Ok(sprite)
+}
+
+/// Convert an Explorers of Sky Chara [`WanImage`] to a SpriteBot folder, at the root of the given file system.
+/// See [`wan_to_spritebot`].
+pub fn export_wan_to_spritebot_folder<T: vfs::FileSystem>(
+    wan_image: &WanImage,
+    shadow_size: u8,
+    destination: &mut T,
+) -> Result<(), SpriteBotError> {
+    wan_to_spritebot(wan_image, shadow_size)?.write_to_folder(destination)?;
+    Ok(())
+}
+
+/// Convert a SpriteBot [`Sprite`] to an Explorers of Sky Chara [`WanImage`].
+///
+/// Each SpriteBot animation become the animation group with the same index, with one [`Animation`] per row. Animations with a single row are repeated for the 8 directions.
+/// The center of each frame of the sheets is used as the origin of the sprite. Semi-transparent pixels are kept, with their alpha converted to the DS range.
+/// The offsets sheet is stored in the [`FrameOffset`] of each frame, and the shadow sheet in the shadow offset of each [`AnimationFrame`].
+pub fn spritebot_to_wan(sprite: &Sprite) -> Result<WanImage, SpriteBotError> {
+    struct ImportedFrame {
+        pixels: Vec<u8>,
+        resolution: GeneralResolution,
+        crop_x: i32,
+        crop_y: i32,
+        frame_offset: FrameOffset,
+        /// The animation name, row and frame number of the first SpriteBot frame using this frame
+        source: (String, usize, usize),
+    }
+
+    let mut palette_data = ImageToPaletteBytesData::default();
+    let mut imported_frames: Vec<ImportedFrame> = Vec::new();
+    // pixels, width, height, crop position and frame offset
+    type FrameContent = (Vec<u8>, u32, u32, i32, i32, FrameOffset);
+    let mut frame_id_by_content: HashMap<FrameContent, u16> = HashMap::new();
+    let mut anim_groups: Vec<Vec<Animation>> = Vec::new();
+
+    for spritebot_animation in &sprite.animations {
+        let mut animations = Vec::new();
+        for (row_nb, row) in spritebot_animation.images.iter().enumerate() {
+            let mut animation = Animation::default();
+            for (frame_nb, spritebot_frame) in row.iter().enumerate() {
+                let too_large = || {
+                    SpriteBotError::FrameTooLarge(
+                        spritebot_animation.name.clone(),
+                        row_nb,
+                        frame_nb,
+                    )
+                };
+                let (width, height) = spritebot_frame.image.dimensions();
+                let center = ((width / 2) as i32, (height / 2) as i32);
+
+                // crop the transparent border
+                let mut crop: Option<(u32, u32, u32, u32)> = None;
+                for (x, y, pixel) in spritebot_frame.image.enumerate_pixels() {
+                    if pixel.0[3] != 0 {
+                        crop = Some(match crop {
+                            None => (x, y, x + 1, y + 1),
+                            Some((a, b, c, d)) => (a.min(x), b.min(y), c.max(x + 1), d.max(y + 1)),
+                        });
+                    }
+                }
+                let (crop_x, crop_y, crop_x_end, crop_y_end) = crop.unwrap_or((0, 0, 0, 0));
+                let cropped = spritebot_frame
+                    .image
+                    .view(crop_x, crop_y, crop_x_end - crop_x, crop_y_end - crop_y)
+                    .to_image();
+                if cropped.width() > 256 || cropped.height() > 128 {
+                    return Err(too_large());
+                }
+                // semi-transparent pixels are kept, unlike with image_to_paletted_bytes
+                let mut pixels = Vec::with_capacity(cropped.len() / 4);
+                for pixel in cropped.pixels() {
+                    let color = if pixel.0[3] == 0 {
+                        Rgba([0, 0, 0, 0])
+                    } else {
+                        *pixel
+                    };
+                    pixels.push(
+                        palette_data
+                            .get_or_insert_id_for_color(color)
+                            .ok_or(SpriteBotError::TooManyColors(256))?,
+                    );
+                }
+
+                let relative_to_center = |point: (u16, u16)| -> Result<(i16, i16), SpriteBotError> {
+                    Ok((
+                        (point.0 as i32 - center.0)
+                            .try_into()
+                            .map_err(|_| too_large())?,
+                        (point.1 as i32 - center.1)
+                            .try_into()
+                            .map_err(|_| too_large())?,
+                    ))
+                };
+                let offsets = &spritebot_frame.offsets;
+                let frame_offset = FrameOffset {
+                    head: relative_to_center(offsets.head)?,
+                    hand_left: relative_to_center(offsets.hand_left)?,
+                    hand_right: relative_to_center(offsets.hand_right)?,
+                    center: relative_to_center(offsets.center)?,
+                };
+                let shadow = relative_to_center(offsets.shadow)?;
+                let crop_x = crop_x as i32 - center.0;
+                let crop_y = crop_y as i32 - center.1;
+
+                let key = (
+                    pixels.clone(),
+                    cropped.width(),
+                    cropped.height(),
+                    crop_x,
+                    crop_y,
+                    frame_offset.clone(),
+                );
+                let frame_id = match frame_id_by_content.get(&key) {
+                    Some(frame_id) => *frame_id,
+                    None => {
+                        let frame_id: u16 =
+                            imported_frames.len().try_into().map_err(|_| too_large())?;
+                        imported_frames.push(ImportedFrame {
+                            pixels,
+                            resolution: GeneralResolution::new(cropped.width(), cropped.height()),
+                            crop_x,
+                            crop_y,
+                            frame_offset,
+                            source: (spritebot_animation.name.clone(), row_nb, frame_nb),
+                        });
+                        frame_id_by_content.insert(key, frame_id);
+                        frame_id
+                    }
+                };
+
+                animation.frames.push(AnimationFrame {
+                    duration: spritebot_frame.duration,
+                    flag: 0,
+                    frame_id,
+                    offset_x: 0,
+                    offset_y: 0,
+                    shadow_offset_x: shadow.0,
+                    shadow_offset_y: shadow.1,
+                });
+            }
+            animations.push(animation);
+        }
+        if animations.len() == 1 {
+            let animation = animations.remove(0);
+            animations = vec![animation; 8];
+        }
+
+        let index = spritebot_animation.index as usize;
+        if anim_groups.len() <= index {
+            anim_groups.resize_with(index + 1, Vec::new);
+        }
+        anim_groups[index] = animations;
+    }
+
+    let images: Vec<(&[u8], GeneralResolution)> = imported_frames
+        .iter()
+        .map(|f| (f.pixels.as_slice(), f.resolution.clone()))
+        .collect();
+    let (palette, _) = cluster_sub_palettes(&images, &palette_data.ordered)?;
+    let mut wan_image = create_wan_from_multiple_images_with_palette(
+        &images,
+        SpriteType::Chara,
+        &palette_data.ordered,
+        &palette,
+    )
+    .map_err(SpriteBotError::ConvertError)?;
+
+    for (frame, imported_frame) in wan_image.frame_store.frames.iter_mut().zip(imported_frames) {
+        for fragment in &mut frame.fragments {
+            let offset_x = fragment.offset_x as i32 + imported_frame.crop_x;
+            let offset_y = fragment.offset_y as i32 + imported_frame.crop_y;
+            if !(-256..256).contains(&offset_x) || !(-128..128).contains(&offset_y) {
+                let (name, row_nb, frame_nb) = imported_frame.source;
+                return Err(SpriteBotError::FragmentOutOfRange(
+                    name, row_nb, frame_nb, offset_x, offset_y,
+                ));
+            }
+            fragment.offset_x = offset_x as i16;
+            fragment.offset_y = offset_y as i8;
+        }
+        frame.frame_offset = Some(imported_frame.frame_offset);
+    }
+
+    wan_image.animation_store.anim_groups = anim_groups;
+
+    Ok(wan_image)
+}
+
+/// Read the SpriteBot folder at the root of the given file system, and convert it to a Chara [`WanImage`].
+/// See [`spritebot_to_wan`].
+pub fn import_wan_from_spritebot_folder<T: vfs::FileSystem>(
+    source: &T,
+) -> Result<WanImage, SpriteBotError> {
+    spritebot_to_wan(&Sprite::new(source)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use vfs::PhysicalFS;
+
+    use crate::{
+        encode_fragment_pixels, export_wan_to_spritebot_folder, import_wan_from_spritebot_folder,
+        spritebot_to_wan, wan_to_spritebot, Animation, AnimationFrame, Fragment, FragmentBytes,
+        FragmentFlip, Frame, FrameOffset, OamShape, SpriteBotError, SpriteType, WanImage,
+    };
+
+    fn test_chara_wan() -> WanImage {
+        let mut wan = WanImage::new(SpriteType::Chara);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 16];
+        wan.palette.palette[1] = [255, 0, 0, 128];
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: encode_fragment_pixels(
+                &[1; 64],
+                OamShape::new(0, 0).unwrap().size(),
+                false,
+            )
+            .unwrap(),
+            z_index: 0,
+        });
+        for offset_x in [-4, 0] {
+            wan.frame_store.frames.push(Frame {
+                fragments: vec![Fragment {
+                    unk1: 0,
+                    unk3_4: None,
+                    unk5: false,
+                    fragment_bytes_index: 0,
+                    offset_y: -8,
+                    offset_x,
+                    flip: FragmentFlip::standard(),
+                    is_mosaic: false,
+                    pal_idx: 0,
+                    alloc_counter: None,
+                    resolution: OamShape::new(0, 0).unwrap(),
+                }],
+                frame_offset: Some(FrameOffset {
+                    head: (0, -6),
+                    hand_left: (-2, -4),
+                    hand_right: (2, -4),
+                    center: (0, -2),
+                }),
+            });
+        }
+        let direction = |frame_id: u16| Animation {
+            frames: vec![AnimationFrame {
+                duration: 4,
+                flag: 0,
+                frame_id,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 2,
+            }],
+        };
+        wan.animation_store.anim_groups = vec![
+            vec![direction(0), direction(1)],
+            Vec::new(),
+            vec![direction(1)],
+        ];
+        wan
+    }
+
+    #[test]
+    fn test_wan_to_spritebot() {
+        let wan = test_chara_wan();
+        let sprite = wan_to_spritebot(&wan, 1).unwrap();
+        assert_eq!(sprite.shadow_size, 1);
+        assert_eq!(sprite.animations.len(), 2);
+        let walk = &sprite.animations[0];
+        assert_eq!(walk.name, "Walk");
+        assert_eq!(walk.index, 0);
+        assert_eq!(walk.images.len(), 2);
+        // the content goes from -4 to 8 on x, -8 to 3 on y, so the origin is at (8, 8)
+        let frame = &walk.images[0][0];
+        assert_eq!(frame.image.dimensions(), (16, 16));
+        assert_eq!(frame.duration, 4);
+        assert_eq!(frame.offsets.center, (8, 6));
+        assert_eq!(frame.offsets.head, (8, 2));
+        assert_eq!(frame.offsets.hand_left, (6, 4));
+        assert_eq!(frame.offsets.hand_right, (10, 4));
+        assert_eq!(frame.offsets.shadow, (8, 10));
+        assert_eq!(frame.image.get_pixel(3, 0).0, [0, 0, 0, 0]);
+        assert_eq!(frame.image.get_pixel(4, 0).0, [255, 0, 0, 255]);
+        assert_eq!(frame.image.get_pixel(12, 0).0, [0, 0, 0, 0]);
+        assert_eq!(walk.images[1][0].image.get_pixel(7, 0).0, [0, 0, 0, 0]);
+        assert_eq!(walk.images[1][0].image.get_pixel(15, 0).0, [255, 0, 0, 255]);
+        assert_eq!(sprite.animations[1].name, "Kick");
+        assert_eq!(sprite.animations[1].index, 2);
+    }
+
+    #[test]
+    fn test_export_spritebot_folder() {
+        let wan = test_chara_wan();
+        let folder = std::env::temp_dir().join("pmd_wan_test_export_spritebot_folder");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        export_wan_to_spritebot_folder(&wan, 1, &mut PhysicalFS::new(&folder)).unwrap();
+        for file in [
+            "AnimData.xml",
+            "Walk-Anim.png",
+            "Walk-Offsets.png",
+            "Walk-Shadow.png",
+            "Kick-Anim.png",
+        ] {
+            assert!(folder.join(file).exists(), "{} is missing", file);
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn test_wan_to_spritebot_errors() {
+        let mut wan = test_chara_wan();
+        wan.frame_store.frames[1].frame_offset = None;
+        assert!(matches!(
+            wan_to_spritebot(&wan, 1),
+            Err(SpriteBotError::NoFrameOffset(1))
+        ));
+        wan.sprite_type = SpriteType::PropsUI;
+        assert!(matches!(
+            wan_to_spritebot(&wan, 1),
+            Err(SpriteBotError::NotChara(SpriteType::PropsUI))
+        ));
+    }
+
+    fn check_imported_wan(imported: &WanImage) {
+        assert_eq!(imported.sprite_type, SpriteType::Chara);
+        assert_eq!(imported.animation_store.anim_groups.len(), 3);
+        assert_eq!(imported.animation_store.anim_groups[0].len(), 2);
+        assert!(imported.animation_store.anim_groups[1].is_empty());
+        assert_eq!(imported.animation_store.anim_groups[2].len(), 8);
+        // the two frames of the original, the second one being shared by both groups
+        assert_eq!(imported.frame_store.frames.len(), 2);
+
+        let original = test_chara_wan();
+        for (anim_group_id, animation_id) in [(0, 0), (0, 1), (2, 0)] {
+            let original_animation_frame =
+                &original.animation_store.anim_groups[anim_group_id][animation_id].frames[0];
+            let original_frame =
+                &original.frame_store.frames[original_animation_frame.frame_id as usize];
+            let animation_frame =
+                &imported.animation_store.anim_groups[anim_group_id][animation_id].frames[0];
+            assert_eq!(animation_frame.duration, 4);
+            assert_eq!(
+                (
+                    animation_frame.shadow_offset_x,
+                    animation_frame.shadow_offset_y
+                ),
+                (0, 2)
+            );
+            let frame = &imported.frame_store.frames[animation_frame.frame_id as usize];
+            // the sprite shouldn't move relative to its origin
+            assert_eq!(frame.frame_offset, original_frame.frame_offset);
+
+            let (image, origin) = imported
+                .render_frame(animation_frame.frame_id as usize)
+                .unwrap();
+            let opaque: Vec<(i32, i32)> = image
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel.0[3] != 0)
+                .map(|(x, y, pixel)| {
+                    assert_eq!(pixel.0, [255, 0, 0, 255]);
+                    (x as i32 - origin.0, y as i32 - origin.1)
+                })
+                .collect();
+            assert_eq!(opaque.len(), 64);
+            let original_fragment = &original_frame.fragments[0];
+            assert_eq!(
+                opaque[0],
+                (
+                    original_fragment.offset_x as i32,
+                    original_fragment.offset_y as i32
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_spritebot_to_wan() {
+        let sprite = wan_to_spritebot(&test_chara_wan(), 1).unwrap();
+        let imported = spritebot_to_wan(&sprite).unwrap();
+        check_imported_wan(&imported);
+    }
+
+    #[test]
+    fn test_spritebot_to_wan_semi_transparent() {
+        let mut wan = test_chara_wan();
+        wan.palette.palette[1] = [255, 0, 0, 64];
+        let sprite = wan_to_spritebot(&wan, 1).unwrap();
+        let imported = spritebot_to_wan(&sprite).unwrap();
+        let (image, _) = imported.render_frame(0).unwrap();
+        assert_eq!(
+            image
+                .pixels()
+                .filter(|pixel| pixel.0 == [255, 0, 0, 128])
+                .count(),
+            64
+        );
+    }
+
+    #[test]
+    fn test_spritebot_to_wan_fragment_out_of_range() {
+        let mut sprite = wan_to_spritebot(&test_chara_wan(), 1).unwrap();
+        let mut image = RgbaImage::new(600, 16);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        sprite.animations[0].images[0][0].image = image;
+        assert!(matches!(
+            spritebot_to_wan(&sprite),
+            Err(SpriteBotError::FragmentOutOfRange(name, 0, 0, offset_x, _)) if name == "Walk" && offset_x < -256
+        ));
+    }
+
+    #[test]
+    fn test_import_spritebot_folder() {
+        let folder = std::env::temp_dir().join("pmd_wan_test_import_spritebot_folder");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let mut fs = PhysicalFS::new(&folder);
+        export_wan_to_spritebot_folder(&test_chara_wan(), 1, &mut fs).unwrap();
+        let imported = import_wan_from_spritebot_folder(&fs).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        check_imported_wan(&imported);
+    }
+}
@@ -1,10 +1,13 @@
 mod tests {
-    use image::{ImageReader, ImageFormat};
+    use image::{ImageFormat, ImageReader};
     use std::io::Cursor;
 
     use crate::{
+        encode_fragment_pixels,
         image_tool::{image_to_paletted_bytes, ImageToPaletteBytesData},
-        insert_frame_in_wanimage, Animation, AnimationFrame, WanImage,
+        insert_frame_in_wanimage, Animation, AnimationFrame, CompressionMethod, Fragment,
+        FragmentBytes, FragmentBytesWriteError, FragmentFlip, Frame, OamShape, Palette, SpriteType,
+        WanDecodeOptions, WanError, WanImage, WanWriteError,
     };
 
     #[test]
@@ -59,4 +62,305 @@ mod tests {
             3
         );
     }
+
+    fn single_fragment_wan(pixels: &[u8], is_256_color: bool) -> WanImage {
+        let resolution = OamShape::new(0, 1).unwrap();
+        let mut wanimage = WanImage::new(crate::SpriteType::PropsUI);
+        wanimage.is_256_color = is_256_color;
+        wanimage
+            .fragment_bytes_store
+            .fragment_bytes
+            .push(FragmentBytes {
+                mixed_pixels: encode_fragment_pixels(pixels, resolution.size(), is_256_color)
+                    .unwrap(),
+                z_index: 0,
+            });
+        wanimage.frame_store.frames.push(Frame {
+            fragments: vec![Fragment {
+                unk1: 0,
+                unk3_4: None,
+                unk5: false,
+                fragment_bytes_index: 0,
+                offset_y: 0,
+                offset_x: 0,
+                flip: FragmentFlip::standard(),
+                is_mosaic: false,
+                pal_idx: 0,
+                alloc_counter: None,
+                resolution,
+            }],
+            frame_offset: None,
+        });
+        wanimage.animation_store.anim_groups.push(vec![Animation {
+            frames: vec![AnimationFrame {
+                duration: 1,
+                flag: 0,
+                frame_id: 0,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 0,
+            }],
+        }]);
+        wanimage
+    }
+
+    #[test]
+    fn encode_and_decode_256_color_wan() {
+        // a 16×16 fragment using every colors, except for the transparent top-left 8×8 chunk
+        let pixels: Vec<u8> = (0..=255)
+            .map(|p: u8| if p % 16 < 8 && p < 128 { 0 } else { p })
+            .collect();
+        for compression in [
+            CompressionMethod::NoCompression,
+            CompressionMethod::CompressionMethodOriginal,
+            CompressionMethod::CompressionMethodOptimised {
+                multiple_of_value: 8,
+                min_transparent_to_compress: 32,
+            },
+        ] {
+            let mut wanimage = single_fragment_wan(&pixels, true);
+            wanimage.compression = compression;
+            wanimage.palette.palette = (0..=255).map(|c| [c, 255 - c, 0, 128]).collect();
+
+            let mut wan_cursor = Cursor::new(Vec::new());
+            wanimage.create_wan(&mut wan_cursor).unwrap();
+            let decoded_wanimage = WanImage::decode_wan(&mut wan_cursor).unwrap();
+
+            assert!(decoded_wanimage.is_256_color);
+            assert_eq!(
+                decoded_wanimage.fragment_bytes_store,
+                wanimage.fragment_bytes_store
+            );
+            let (image, _) = decoded_wanimage.render_frame(0).unwrap();
+            assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+            assert_eq!(image.get_pixel(9, 0).0, [9, 246, 0, 255]);
+            assert_eq!(image.get_pixel(15, 15).0, [255, 0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn encode_16_color_wan_with_too_large_pixel() {
+        let mut wanimage = single_fragment_wan(&[16; 256], false);
+        wanimage.palette.palette = vec![[0, 0, 0, 0]; 32];
+        assert!(matches!(
+            wanimage.create_wan(&mut Cursor::new(Vec::new())),
+            Err(WanWriteError::FragmentBytesError(
+                0,
+                FragmentBytesWriteError::PixelOutOf16ColorRange(16)
+            ))
+        ));
+
+        let mut image = vec![1; 16];
+        image[8 + 3] = 17;
+        assert!(matches!(
+            insert_frame_in_wanimage(image, 8, 2, &mut wanimage, 0),
+            Err(WanWriteError::PixelOutOf16ColorRange {
+                x: 3,
+                y: 1,
+                pixel: 17
+            })
+        ));
+    }
+
+    #[test]
+    fn encode_invalid_wan() {
+        let mut wanimage = single_fragment_wan(&[1; 256], false);
+        wanimage.palette.palette = vec![[0, 0, 0, 0]; 16];
+        wanimage
+            .frame_store
+            .frames
+            .push(wanimage.frame_store.frames[0].clone());
+        wanimage.frame_store.frames[1].fragments[0].offset_x = -257;
+        assert!(matches!(
+            wanimage.to_bytes(),
+            Err(WanWriteError::FragmentOffsetOutOfRange {
+                frame_id: 1,
+                fragment_id: 0,
+                offset_x: -257
+            })
+        ));
+
+        wanimage.frame_store.frames[1].fragments.clear();
+        assert!(matches!(
+            wanimage.to_bytes(),
+            Err(WanWriteError::EmptyFrame { frame_id: 1 })
+        ));
+
+        wanimage.frame_store.frames.pop();
+        wanimage.sprite_type = SpriteType::Chara;
+        assert!(matches!(
+            wanimage.to_bytes(),
+            Err(WanWriteError::MissingFrameOffset { frame_id: 0 })
+        ));
+
+        wanimage.sprite_type = SpriteType::PropsUI;
+        wanimage
+            .fragment_bytes_store
+            .fragment_bytes
+            .push(FragmentBytes {
+                mixed_pixels: Vec::new(),
+                z_index: 0,
+            });
+        assert!(matches!(
+            wanimage.to_bytes(),
+            Err(WanWriteError::FragmentBytesError(
+                1,
+                FragmentBytesWriteError::EmptyFragmentBytes
+            ))
+        ));
+    }
+
+    #[test]
+    fn encode_and_decode_bytes() {
+        let mut wanimage = single_fragment_wan(&[1; 256], false);
+        wanimage.palette.palette = vec![[0, 0, 0, 0]; 16];
+        let bytes = wanimage.to_bytes().unwrap();
+        let decoded_wanimage = WanImage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded_wanimage.frame_store, wanimage.frame_store);
+        assert_eq!(decoded_wanimage.to_bytes().unwrap(), bytes);
+
+        // the pointers don't depend on the position in the output
+        let mut written = vec![0xFF; 3];
+        wanimage.write_wan(&mut written).unwrap();
+        assert_eq!(written[3..], bytes);
+
+        let mut wan_cursor = Cursor::new(vec![0xFF; 3]);
+        wan_cursor.set_position(3);
+        wanimage.create_wan(&mut wan_cursor).unwrap();
+        assert_eq!(wan_cursor.position(), 3);
+        assert_eq!(wan_cursor.get_ref()[3..], bytes);
+    }
+
+    #[test]
+    fn encode_and_decode_unknown_values() {
+        let mut wanimage = single_fragment_wan(&[1; 256], false);
+        wanimage.palette.palette = vec![[0, 0, 0, 0]; 16];
+        let mut wan_cursor = Cursor::new(Vec::new());
+        wanimage.create_wan(&mut wan_cursor).unwrap();
+        let decoded_wanimage = WanImage::decode_wan(&mut wan_cursor).unwrap();
+        assert_eq!(decoded_wanimage.frame_store, wanimage.frame_store);
+        assert_eq!(decoded_wanimage.palette, wanimage.palette);
+        assert_eq!(decoded_wanimage.unk1, 0);
+        assert_eq!(decoded_wanimage.size_to_allocate_for_max_frame, None);
+
+        let mut wanimage = decoded_wanimage;
+        wanimage.unk1 = 1;
+        wanimage.unk_header = 2;
+        wanimage.size_to_allocate_for_max_frame = Some(3);
+        wanimage.palette.unk1 = 4;
+        wanimage.palette.unk2 = 5;
+        wanimage.frame_store.frames[0].fragments[0].alloc_counter = Some(0x0806);
+        wanimage.frame_store.frames[0].fragments[0].unk3_4 = Some((true, true));
+        let mut wan_cursor = Cursor::new(Vec::new());
+        wanimage.create_wan(&mut wan_cursor).unwrap();
+        let decoded_wanimage = WanImage::decode_wan(&mut wan_cursor).unwrap();
+        assert_eq!(decoded_wanimage, wanimage);
+
+        wanimage.reset_unknown_values();
+        let mut wan_cursor = Cursor::new(Vec::new());
+        wanimage.create_wan(&mut wan_cursor).unwrap();
+        let decoded_wanimage = WanImage::decode_wan(&mut wan_cursor).unwrap();
+        assert_eq!(decoded_wanimage, wanimage);
+        assert_eq!(decoded_wanimage.size_to_allocate_for_max_frame, None);
+        assert_eq!(
+            decoded_wanimage.frame_store.frames[0].fragments[0].alloc_counter,
+            None
+        );
+        assert_eq!(
+            decoded_wanimage.frame_store.frames[0].fragments[0].unk3_4,
+            Some((true, true))
+        );
+        assert_eq!(decoded_wanimage.palette.unk2, Palette::new_eos().unk2);
+    }
+
+    #[test]
+    fn encode_and_decode_shared_pixel_chunks() {
+        let chunk_a = [1; 64];
+        let chunk_b = [2; 64];
+        let mut wanimage = single_fragment_wan(&[1; 256], false);
+        wanimage.palette.palette = vec![[0, 0, 0, 0]; 16];
+        // the second chunk of the second FragmentBytes is the same as the first chunk of the first one
+        for chunks in [
+            [chunk_a, chunk_b, [0; 64], chunk_b],
+            [chunk_b, chunk_a, [0; 64], chunk_b],
+        ] {
+            wanimage
+                .fragment_bytes_store
+                .fragment_bytes
+                .push(FragmentBytes {
+                    mixed_pixels: chunks.concat(),
+                    z_index: 0,
+                });
+        }
+
+        let mut unshared = Cursor::new(Vec::new());
+        wanimage.create_wan(&mut unshared).unwrap();
+        wanimage.share_pixel_chunks = true;
+        let mut shared = Cursor::new(Vec::new());
+        wanimage.create_wan(&mut shared).unwrap();
+        assert!(shared.get_ref().len() < unshared.get_ref().len());
+
+        assert!(matches!(
+            WanImage::decode_wan(&mut shared),
+            Err(WanError::IncoherentPointerToFragmentBytesPart)
+        ));
+        let decoded_wanimage = WanImage::decode_wan_with_options(
+            &mut shared,
+            &WanDecodeOptions {
+                allow_non_contiguous_fragment_bytes: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            decoded_wanimage.fragment_bytes_store,
+            wanimage.fragment_bytes_store
+        );
+    }
+
+    #[test]
+    fn encode_and_decode_shared_data() {
+        let pixels: Vec<u8> = (0..=255).collect();
+        let mut source = single_fragment_wan(&pixels, true);
+        source.sprite_type = SpriteType::Unk2;
+        source.palette.palette = (0..=255).map(|c| [c, 255 - c, 0, 128]).collect();
+
+        for sprite_type in [SpriteType::Unk2, SpriteType::Engine3D] {
+            let mut wanimage = single_fragment_wan(&pixels, true);
+            wanimage.sprite_type = sprite_type;
+            wanimage.compression = sprite_type.default_compression_method();
+            wanimage.fragment_bytes_store.fragment_bytes.clear();
+            wanimage.shared_palette = true;
+            wanimage.shared_fragment_bytes = true;
+
+            let bytes = wanimage.to_bytes().unwrap();
+            let decoded_wanimage = WanImage::from_bytes(&bytes).unwrap();
+            assert!(decoded_wanimage.shared_palette && decoded_wanimage.shared_fragment_bytes);
+            assert_eq!(decoded_wanimage.sprite_type, sprite_type);
+            assert_eq!(decoded_wanimage.frame_store, wanimage.frame_store);
+            assert_eq!(decoded_wanimage.to_bytes().unwrap(), bytes);
+            assert_eq!(wanimage.validate(), Vec::new());
+
+            let (decoded_wanimage, layout) =
+                WanImage::decode_wan_with_layout(Cursor::new(&bytes)).unwrap();
+            let mut rewritten = Cursor::new(Vec::new());
+            decoded_wanimage
+                .create_wan_with_layout(&mut rewritten, &layout)
+                .unwrap();
+            assert_eq!(rewritten.into_inner(), bytes);
+
+            // the data of the other file are now part of the sprite
+            let mut resolved = decoded_wanimage;
+            resolved.resolve_shared_data(&source);
+            assert!(!resolved.shared_palette && !resolved.shared_fragment_bytes);
+            let mut rewritten = Cursor::new(Vec::new());
+            resolved
+                .create_wan_with_layout(&mut rewritten, &layout)
+                .unwrap();
+            let resolved = WanImage::from_bytes(rewritten.get_ref()).unwrap();
+            assert_eq!(resolved.palette.palette, source.palette.palette);
+            let (image, _) = resolved.render_frame(0).unwrap();
+            assert_eq!(image.get_pixel(9, 0).0, [9, 246, 0, 255]);
+        }
+    }
 }
@@ -0,0 +1,277 @@
+use thiserror::Error;
+
+use crate::{SpriteType, WanImage};
+
+/// The maximum number of [`crate::Fragment`] in a [`crate::Frame`], as the DS OAM only has 128 entries
+pub const MAX_FRAGMENTS_PER_FRAME: usize = 128;
+/// The maximum allocation (in blocks of 256 pixels) for all the fragments of a [`crate::Frame`], as the allocation counter of each fragment is stored on 10 bits
+pub const MAX_FRAME_ALLOCATION: u16 = 0x400;
+
+/// A problem that would prevent a [`WanImage`] to be written, or to be displayed correctly by the game.
+/// Returned by [`WanImage::validate`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    #[error("The fragment {fragment_id} of the frame {frame_id} use the FragmentBytes {fragment_bytes_index}, which doesn't exist")]
+    FragmentBytesIndexOutOfRange {
+        frame_id: usize,
+        fragment_id: usize,
+        fragment_bytes_index: usize,
+    },
+    #[error("The fragment {fragment_id} of the frame {frame_id} use the palette {pal_idx}, which doesn't exist")]
+    PaletteOutOfRange {
+        frame_id: usize,
+        fragment_id: usize,
+        pal_idx: u16,
+    },
+    #[error("The fragment {fragment_id} of the frame {frame_id} has an x offset of {offset_x}, but it should be between -256 and 255")]
+    FragmentOffsetOutOfRange {
+        frame_id: usize,
+        fragment_id: usize,
+        offset_x: i16,
+    },
+    #[error("The frame {frame_id} has no fragment")]
+    EmptyFrame { frame_id: usize },
+    #[error("The frame {frame_id} has {fragment_count} fragments, but there can be at most 128")]
+    TooManyFragments {
+        frame_id: usize,
+        fragment_count: usize,
+    },
+    #[error("The fragments of the frame {frame_id} need {allocation} blocks of memory, but at most 1024 can be allocated")]
+    FrameAllocationTooLarge { frame_id: usize, allocation: u32 },
+    #[error(
+        "The frame {frame_id} doesn't have a FrameOffset, which is mandatory for Chara sprites"
+    )]
+    MissingFrameOffset { frame_id: usize },
+    #[error("The frame {animation_frame_id} of the animation {animation_id} of the animation group {anim_group_id} use the frame {frame_id}, which doesn't exist")]
+    AnimationFrameOutOfRange {
+        anim_group_id: usize,
+        animation_id: usize,
+        animation_frame_id: usize,
+        frame_id: u16,
+    },
+    #[error("There are {0} FragmentBytes, but there can be at most 32767")]
+    TooManyFragmentBytes(usize),
+}
+
+impl WanImage {
+    /// Check the sprite for every problem that would prevent it to be written, or to be displayed correctly by the game.
+    /// Return an empty list if no problem was found.
+    ///
+    /// The references to the palette and to the [`crate::FragmentBytes`] are not checked if they are stored in another file (see [`WanImage::shared_palette`] and [`WanImage::shared_fragment_bytes`]).
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+
+        let fragment_bytes_amount = self.fragment_bytes_store.len();
+        if fragment_bytes_amount > i16::MAX as usize {
+            issues.push(ValidationIssue::TooManyFragmentBytes(fragment_bytes_amount));
+        }
+
+        let palette_size = if self.is_256_color { 256 } else { 16 };
+        let palette_amount = self.palette.palette.len().div_ceil(palette_size);
+
+        for (frame_id, frame) in self.frame_store.frames.iter().enumerate() {
+            if frame.fragments.is_empty() {
+                issues.push(ValidationIssue::EmptyFrame { frame_id });
+            }
+            if frame.fragments.len() > MAX_FRAGMENTS_PER_FRAME {
+                issues.push(ValidationIssue::TooManyFragments {
+                    frame_id,
+                    fragment_count: frame.fragments.len(),
+                });
+            }
+            // summed in an u32, as an invalid frame may need more than u16::MAX blocks
+            let allocation: u32 = frame
+                .fragments
+                .iter()
+                .map(|fragment| u32::from(fragment.resolution.chunk_to_allocate_for_fragment()))
+                .sum();
+            if allocation > u32::from(MAX_FRAME_ALLOCATION) {
+                issues.push(ValidationIssue::FrameAllocationTooLarge {
+                    frame_id,
+                    allocation,
+                });
+            }
+            if self.sprite_type == SpriteType::Chara && frame.frame_offset.is_none() {
+                issues.push(ValidationIssue::MissingFrameOffset { frame_id });
+            }
+
+            for (fragment_id, fragment) in frame.fragments.iter().enumerate() {
+                if !self.shared_fragment_bytes
+                    && fragment.fragment_bytes_index >= fragment_bytes_amount
+                {
+                    issues.push(ValidationIssue::FragmentBytesIndexOutOfRange {
+                        frame_id,
+                        fragment_id,
+                        fragment_bytes_index: fragment.fragment_bytes_index,
+                    });
+                }
+                if !self.shared_palette && fragment.pal_idx as usize >= palette_amount {
+                    issues.push(ValidationIssue::PaletteOutOfRange {
+                        frame_id,
+                        fragment_id,
+                        pal_idx: fragment.pal_idx,
+                    });
+                }
+                if !(-256..256).contains(&fragment.offset_x) {
+                    issues.push(ValidationIssue::FragmentOffsetOutOfRange {
+                        frame_id,
+                        fragment_id,
+                        offset_x: fragment.offset_x,
+                    });
+                }
+            }
+        }
+
+        for (anim_group_id, anim_group) in self.animation_store.anim_groups.iter().enumerate() {
+            for (animation_id, animation) in anim_group.iter().enumerate() {
+                for (animation_frame_id, animation_frame) in animation.frames.iter().enumerate() {
+                    if animation_frame.frame_id as usize >= self.frame_store.frames.len() {
+                        issues.push(ValidationIssue::AnimationFrameOutOfRange {
+                            anim_group_id,
+                            animation_id,
+                            animation_frame_id,
+                            frame_id: animation_frame.frame_id,
+                        });
+                    }
+                }
+            }
+        }
+
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Animation, AnimationFrame, Fragment, FragmentBytes, FragmentFlip, Frame, FrameOffset,
+        OamShape, SpriteType, ValidationIssue, WanImage,
+    };
+
+    fn frame_offset() -> FrameOffset {
+        FrameOffset {
+            head: (0, 0),
+            hand_left: (0, 0),
+            hand_right: (0, 0),
+            center: (0, 0),
+        }
+    }
+
+    /// A 64×64 fragment
+    fn big_fragment(fragment_bytes_index: usize, offset_x: i16, pal_idx: u16) -> Fragment {
+        Fragment {
+            unk1: 0,
+            unk3_4: None,
+            unk5: false,
+            fragment_bytes_index,
+            offset_y: 0,
+            offset_x,
+            flip: FragmentFlip::standard(),
+            is_mosaic: false,
+            pal_idx,
+            alloc_counter: None,
+            resolution: OamShape::new(0, 3).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut wan = WanImage::new(SpriteType::Chara);
+        wan.palette.palette = vec![[0, 0, 0, 0]; 32];
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: vec![0; 64 * 64],
+            z_index: 0,
+        });
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![big_fragment(0, -256, 1)],
+            frame_offset: Some(frame_offset()),
+        });
+        wan.animation_store.anim_groups.push(vec![Animation {
+            frames: vec![AnimationFrame {
+                duration: 1,
+                flag: 0,
+                frame_id: 0,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 0,
+            }],
+        }]);
+        assert_eq!(wan.validate(), Vec::new());
+
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![big_fragment(1, 256, 2)],
+            frame_offset: None,
+        });
+        wan.frame_store.frames.push(Frame::default());
+        // 65 fragments of 64×64 pixels need 16 blocks each
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![big_fragment(0, 0, 0); 65],
+            frame_offset: Some(frame_offset()),
+        });
+        wan.animation_store.anim_groups[0][0].frames[0].frame_id = 4;
+
+        assert_eq!(
+            wan.validate(),
+            vec![
+                ValidationIssue::MissingFrameOffset { frame_id: 1 },
+                ValidationIssue::FragmentBytesIndexOutOfRange {
+                    frame_id: 1,
+                    fragment_id: 0,
+                    fragment_bytes_index: 1
+                },
+                ValidationIssue::PaletteOutOfRange {
+                    frame_id: 1,
+                    fragment_id: 0,
+                    pal_idx: 2
+                },
+                ValidationIssue::FragmentOffsetOutOfRange {
+                    frame_id: 1,
+                    fragment_id: 0,
+                    offset_x: 256
+                },
+                ValidationIssue::EmptyFrame { frame_id: 2 },
+                ValidationIssue::MissingFrameOffset { frame_id: 2 },
+                ValidationIssue::FrameAllocationTooLarge {
+                    frame_id: 3,
+                    allocation: 65 * 16
+                },
+                ValidationIssue::AnimationFrameOutOfRange {
+                    anim_group_id: 0,
+                    animation_id: 0,
+                    animation_frame_id: 0,
+                    frame_id: 4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_allocation_overflowing_u16() {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: vec![0; 64 * 64],
+            z_index: 0,
+        });
+        // 4097 fragments of 16 blocks need more than u16::MAX blocks
+        wan.frame_store.frames.push(Frame {
+            fragments: vec![big_fragment(0, 0, 0); 4097],
+            frame_offset: None,
+        });
+        wan.palette.palette = vec![[0, 0, 0, 0]; 16];
+
+        assert_eq!(
+            wan.validate(),
+            vec![
+                ValidationIssue::TooManyFragments {
+                    frame_id: 0,
+                    fragment_count: 4097
+                },
+                ValidationIssue::FrameAllocationTooLarge {
+                    frame_id: 0,
+                    allocation: 4097 * 16
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+/// Options for [`crate::WanImage::decode_wan_with_options`]. The default is the same as [`crate::WanImage::decode_wan`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WanDecodeOptions {
+    /// Accept [`crate::FragmentBytes`] whose parts aren't stored one after the other, like those written with [`crate::WanImage::share_pixel_chunks`].
+    /// Otherwise, [`crate::WanError::IncoherentPointerToFragmentBytesPart`] is returned for them.
+    pub allow_non_contiguous_fragment_bytes: bool,
+}
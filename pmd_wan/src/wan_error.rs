@@ -38,7 +38,7 @@ pub enum WanError {
     InvalidSir0([u8; 4]),
     #[error("the end of the sir0 header should be four 0, found {0:?}")]
     InvalidEndOfSir0Header([u8; 4]),
-    #[error("the type of sprite is unknown (found the sprite type id {0}, but this program only known sprite for [0, 1, 3])")]
+    #[error("the type of sprite is unknown (found the sprite type id {0}, but this program only known sprite for [0, 1, 2, 3])")]
     TypeOfSpriteUnknown(u16),
     #[error("the 2 byte that indicate the number of color is invalid (found {0}, expected 0 or 1")]
     InvalidColorNumber(u16),
@@ -60,6 +60,13 @@ pub enum WanError {
     ExistenceFrameOffsetForNonChara,
     #[error("There is no reference to a frame offset table in a Chara sprite")]
     NonExistenceFrameOffsetForChara,
+    #[error("The {0} and the {1} overlap in the file")]
+    OverlappingParts(String, String),
+    #[error("The {0} extend after the end of the file")]
+    PartAfterEndOfFile(String),
+    #[deprecated(
+        note = "never returned: a missing frame offset is reported when writing, as WanWriteError::MissingFrameOffset"
+    )]
     #[error("There is a frame that doesn’t have a frame offset in a Chara sprite")]
     NoOffsetDataForFrame,
 }
@@ -2,15 +2,16 @@ use crate::{
     encode_fragment_pixels, get_opt_le, wan_read_raw_4, AnimationStore, CompressionMethod,
     Fragment, FragmentBytes, FragmentBytesToImageError, FragmentFlip, Frame, OamShape,
 };
-use crate::{FragmentBytesStore, FrameStore, Palette, SpriteType, WanError};
+use crate::{
+    FragmentBytesStore, FrameStore, Palette, SpriteType, WanDecodeOptions, WanError, WanWriteError,
+};
 
-use anyhow::Context;
 use binread::BinReaderExt;
 use binwrite::BinWrite;
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use image::{ImageBuffer, Rgba};
 use pmd_sir0::write_sir0_footer;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct WanImage {
@@ -21,9 +22,25 @@ pub struct WanImage {
     /// true if the picture have 256 color, false if it only have 16
     pub is_256_color: bool,
     pub sprite_type: SpriteType,
+    /// The unknown u16 before the color number in the image data info. Normally 0.
+    pub unk1: u16,
+    /// The unknown u16 between the color depth and the number of [`FragmentBytes`] in the image data info. Normally 0.
     pub unk2: u16,
+    /// The two unknown bytes after the sprite type in the wan header. Normally 0.
+    pub unk_header: u16,
+    /// The memory to allocate for the biggest frame. Will be automatically computed if None (the default when reading, if it is equal to the computed value)
+    pub size_to_allocate_for_max_frame: Option<u32>,
     /// How the imagebytes should be compressed, only affect writing
     pub compression: CompressionMethod,
+    /// If true, identical 64-pixel chunks of all the [`FragmentBytes`] are only stored once, and [`WanImage::compression`] is ignored. Only affect writing.
+    /// The resulting file need to be read with [`WanDecodeOptions::allow_non_contiguous_fragment_bytes`].
+    pub share_pixel_chunks: bool,
+    /// true if the palette isn't stored in this file, but in another one (like some sprites of `EFFECT/effect.bin`). Its pointer is then null.
+    /// [`WanImage::palette`] is empty when reading, and ignored when writing. See [`WanImage::resolve_shared_data`].
+    pub shared_palette: bool,
+    /// true if the [`FragmentBytes`] aren't stored in this file, but in another one (like some sprites of `EFFECT/effect.bin`). Their pointer table is then null.
+    /// [`WanImage::fragment_bytes_store`] is empty when reading, and ignored when writing. See [`WanImage::resolve_shared_data`].
+    pub shared_fragment_bytes: bool,
 }
 
 impl WanImage {
@@ -33,151 +50,105 @@ impl WanImage {
             fragment_bytes_store: FragmentBytesStore::default(),
             frame_store: FrameStore::default(),
             animation_store: AnimationStore::default(),
-            palette: Palette::default(),
+            palette: Palette::new_eos(),
             is_256_color: false,
             sprite_type,
+            unk1: 0,
             unk2: 0,
+            unk_header: 0,
+            size_to_allocate_for_max_frame: None,
             compression: sprite_type.default_compression_method(),
+            share_pixel_chunks: false,
+            shared_palette: false,
+            shared_fragment_bytes: false,
         }
     }
 
     /// parse an image in the wan/wat format stored in the input file
     /// It assume that the file is decompressed
-    pub fn decode_wan<F: Read + Seek>(mut file: F) -> Result<WanImage, WanError> {
-        let source_file_lenght = file.seek(SeekFrom::End(0))?;
-        file.seek(SeekFrom::Start(0))?;
-        debug!("start to decode a wan image");
-
-        // first step: decode the sir0 header
-        trace!("decoding the sir0 header");
-        let sir0_header = wan_read_raw_4(&mut file)?;
-        if sir0_header != [0x53, 0x49, 0x52, 0x30] {
-            return Err(WanError::InvalidSir0(sir0_header));
-        };
-        let sir0_pointer_header = file.read_u32::<LE>()? as u64;
-        let _sir0_pointer_offset = file.read_u32::<LE>()? as u64;
-
-        let sir0_header_end = wan_read_raw_4(&mut file)?;
-        if sir0_header_end != [0, 0, 0, 0] {
-            return Err(WanError::InvalidEndOfSir0Header(sir0_header_end));
-        };
-
-        // second step: decode the wan header
-        trace!("reading the wan header");
-        file.seek(SeekFrom::Start(sir0_pointer_header))?;
-        let pointer_to_anim_info = file.read_u32::<LE>()? as u64;
-        let pointer_to_image_data_info = file.read_u32::<LE>()? as u64;
-
-        let sprite_type_id = file.read_u16::<LE>()?;
-        let sprite_type = SpriteType::from_id(sprite_type_id)
-            .map_or_else(|| Err(WanError::TypeOfSpriteUnknown(sprite_type_id)), Ok)?;
-        //unk #12
-
-        // third step: decode animation info block
-        trace!("reading the animation info block");
-        file.seek(SeekFrom::Start(pointer_to_anim_info))?;
-        let pointer_frames_table = file.read_u32::<LE>()? as u64;
-        if pointer_frames_table > source_file_lenght {
-            return Err(WanError::PostFilePointer("meta frame reference table"));
-        }
-        let frame_offset_table = file.read_u32::<LE>()? as u64;
-        if frame_offset_table > source_file_lenght {
-            return Err(WanError::PostFilePointer("particule offset table"));
-        };
-        #[allow(unused_parens)]
-        if sprite_type == SpriteType::Chara && frame_offset_table == 0 {
-            return Err(WanError::NonExistenceFrameOffsetForChara);
-        } else if sprite_type != SpriteType::Chara && frame_offset_table != 0 {
-            return Err(WanError::ExistenceFrameOffsetForNonChara);
-        };
-        let pointer_animation_table = file.read_u32::<LE>()? as u64;
-        if pointer_animation_table > source_file_lenght {
-            return Err(WanError::PostFilePointer("animation groups table"));
-        }
-        let amount_animation_group = file.read_u16::<LE>()?;
-
-        let _size_to_allocate_for_max_frame = file.read_u32::<LE>()?;
+    pub fn decode_wan<F: Read + Seek>(file: F) -> Result<WanImage, WanError> {
+        Self::decode_wan_with_options(file, &WanDecodeOptions::default())
+    }
 
-        // fourth: decode image data info
-        trace!("reading the image data info");
-        file.seek(SeekFrom::Start(pointer_to_image_data_info))?;
-        let pointer_image_data_pointer_table = file.read_u32::<LE>()? as u64;
-        let pointer_palette = file.read_u32::<LE>()? as u64;
-        file.read_u16::<LE>()?; //unk
-        let is_256_color = match file.read_u16::<LE>()? {
-            0 => false,
-            1 => true,
-            color_id => return Err(WanError::InvalidColorNumber(color_id)),
+    /// Same as [`WanImage::decode_wan`], with the given [`WanDecodeOptions`]
+    pub fn decode_wan_with_options<F: Read + Seek>(
+        mut file: F,
+        options: &WanDecodeOptions,
+    ) -> Result<WanImage, WanError> {
+        let header = WanHeader::read(&mut file)?;
+
+        let shared_palette = header.pointer_palette == 0;
+        let palette = if shared_palette {
+            trace!("the palette is stored in another file");
+            Palette::default()
+        } else {
+            trace!("parsing the palette");
+            file.seek(SeekFrom::Start(header.pointer_palette))?;
+            Palette::new_from_bytes(&mut file)?
         };
-        let unk2 = file.read_u16::<LE>()?;
-        let amount_fragments = file.read_u16::<LE>()?;
-
-        trace!("parsing the palette");
-
-        file.seek(SeekFrom::Start(pointer_palette))?;
-        let palette = Palette::new_from_bytes(&mut file)?;
 
         // decode fragments
         trace!("decoding meta-frame");
-        let frames_end_pointer: u64 = match frame_offset_table {
-            0 => match WanImage::find_first_non_null_animation_seq_entry(
-                &mut file,
-                pointer_animation_table,
-            ) {
-                Some(v) => v,
-                // Fall back to animation group offset
-                None => pointer_animation_table,
-            },
-            value => value,
-        };
-
-        let space_frame_raw = WanError::checked_sub(
-            frames_end_pointer,
-            pointer_frames_table,
-            "frames end pointer",
-            "pointer frames table",
-        )?;
-
-        let nb_frames = space_frame_raw / 4;
-
-        file.seek(SeekFrom::Start(pointer_frames_table))?;
-        let mut frames_store = FrameStore::new_from_bytes(&mut file, nb_frames)?;
+        file.seek(SeekFrom::Start(header.pointer_frames_table))?;
+        let mut frames_store = FrameStore::new_from_bytes(&mut file, header.nb_frames)?;
 
         // decode image
-        trace!("reading the image data pointer table");
-        file.seek(SeekFrom::Start(pointer_image_data_pointer_table))?;
-        trace!(
-            "start of the image part (source) : {}",
-            pointer_image_data_pointer_table
-        );
-        let fragment_store =
-            FragmentBytesStore::new_from_bytes(&mut file, amount_fragments as u32)?;
+        let shared_fragment_bytes = header.pointer_image_data_pointer_table == 0;
+        let fragment_store = if shared_fragment_bytes {
+            trace!("the FragmentBytes are stored in another file");
+            FragmentBytesStore::default()
+        } else {
+            trace!("reading the image data pointer table");
+            file.seek(SeekFrom::Start(header.pointer_image_data_pointer_table))?;
+            trace!(
+                "start of the image part (source) : {}",
+                header.pointer_image_data_pointer_table
+            );
+            FragmentBytesStore::new_from_bytes(
+                &mut file,
+                header.amount_fragments as u32,
+                header.is_256_color,
+                options.allow_non_contiguous_fragment_bytes,
+            )?
+        };
 
         // decode animation
-        let (anim_store, particule_table_end) =
-            AnimationStore::new(&mut file, pointer_animation_table, amount_animation_group)?;
+        let (anim_store, particule_table_end) = AnimationStore::new(
+            &mut file,
+            header.pointer_animation_table,
+            header.amount_animation_group,
+        )?;
 
         // decode the frame offsets table
-        if frame_offset_table != 0 {
-            trace!("decoding frames offset at {:?}", frame_offset_table);
-            file.seek(SeekFrom::Start(frame_offset_table))?;
+        if header.frame_offset_table != 0 {
+            trace!("decoding frames offset at {:?}", header.frame_offset_table);
+            file.seek(SeekFrom::Start(header.frame_offset_table))?;
             for frame in &mut frames_store.frames {
                 frame.frame_offset = Some(file.read_le()?);
             }
-            if particule_table_end > source_file_lenght {
+            if particule_table_end > header.source_file_lenght {
                 return Err(WanError::PostFilePointer("particle table end"));
             };
         }
 
+        let size_to_allocate_for_max_frame = Some(header.size_to_allocate_for_max_frame)
+            .filter(|size| *size != frames_store.compute_fragment_alloc_counter() as u32);
+
         Ok(WanImage {
             fragment_bytes_store: fragment_store,
             frame_store: frames_store,
             animation_store: anim_store,
             palette,
-            is_256_color,
-            sprite_type,
-            unk2,
-            compression: sprite_type.default_compression_method(),
+            is_256_color: header.is_256_color,
+            sprite_type: header.sprite_type,
+            unk1: header.unk1,
+            unk2: header.unk2,
+            unk_header: header.unk_header,
+            size_to_allocate_for_max_frame,
+            compression: header.sprite_type.default_compression_method(),
+            share_pixel_chunks: false,
+            shared_palette,
+            shared_fragment_bytes,
         })
     }
 
@@ -197,7 +168,48 @@ impl WanImage {
         None
     }
 
-    pub fn create_wan<F: Write + Seek>(&self, file: &mut F) -> anyhow::Result<()> {
+    /// Write the sprite in the wan format in the output file, starting at its current position.
+    /// The cursor is moved back to the start of the written sprite at the end.
+    ///
+    /// See [`WanImage::write_wan`] to write to an output that doesn't implement [`Seek`].
+    pub fn create_wan<F: Write + Seek>(&self, file: &mut F) -> Result<(), WanWriteError> {
+        let start_position = file.stream_position()?;
+        self.write_wan(file)?;
+        file.seek(SeekFrom::Start(start_position))?;
+        Ok(())
+    }
+
+    /// Write the sprite in the wan format in the output.
+    /// The file is first built in memory, so the output doesn't need to implement [`Seek`].
+    pub fn write_wan<W: Write>(&self, writer: &mut W) -> Result<(), WanWriteError> {
+        writer.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Return the sprite encoded in the wan format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WanWriteError> {
+        let mut buffer = Cursor::new(Vec::new());
+        self.write_wan_in_memory(&mut buffer)?;
+        Ok(buffer.into_inner())
+    }
+
+    /// Parse a sprite in the wan format, like [`WanImage::decode_wan`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<WanImage, WanError> {
+        Self::decode_wan(Cursor::new(bytes))
+    }
+
+    /// Write the sprite in the buffer. All the pointers are relative to the start of the buffer, which should be empty.
+    fn write_wan_in_memory(&self, file: &mut Cursor<Vec<u8>>) -> Result<(), WanWriteError> {
+        if self.fragment_bytes_store.len() > i16::MAX as usize {
+            return Err(WanWriteError::TooManyFragmentBytes(
+                self.fragment_bytes_store.len(),
+            ));
+        }
+        if self.animation_store.anim_groups.len() > u16::MAX as usize {
+            return Err(WanWriteError::TooManyAnimGroups(
+                self.animation_store.anim_groups.len(),
+            ));
+        }
         let opt_le = get_opt_le();
         debug!("start creating a wan image");
 
@@ -237,21 +249,29 @@ impl WanImage {
             file.seek(SeekFrom::Current(0))?
         );
 
-        let (image_offset, sir0_pointer_images) =
-            self.fragment_bytes_store.write(file, &self.compression)?;
-
-        for pointer in sir0_pointer_images {
-            sir0_offsets.push(pointer as u32);
-        }
-
-        trace!("start of the palette: {}", file.seek(SeekFrom::Current(0))?);
-        let pointer_palette = self
-            .palette
-            .write(file)
-            .context("Failed to write the palette")?;
-        //sir0_offsets.push(pointer_palette);
+        let image_offset = if self.shared_fragment_bytes {
+            Vec::new()
+        } else {
+            let (image_offset, sir0_pointer_images) = self.fragment_bytes_store.write(
+                file,
+                &self.compression,
+                self.is_256_color,
+                self.share_pixel_chunks,
+            )?;
+            for pointer in sir0_pointer_images {
+                sir0_offsets.push(pointer as u32);
+            }
+            image_offset
+        };
 
-        sir0_offsets.push(pointer_palette as u32);
+        let pointer_palette = if self.shared_palette {
+            0
+        } else {
+            trace!("start of the palette: {}", file.seek(SeekFrom::Current(0))?);
+            let pointer_palette = self.palette.write(file)?;
+            sir0_offsets.push(pointer_palette as u32);
+            pointer_palette
+        };
 
         trace!(
             "start of the fragment reference offset: {}",
@@ -269,13 +289,11 @@ impl WanImage {
                 "start of the frame offsets: {}",
                 file.seek(SeekFrom::Current(0))?
             );
-            for frame in &self.frame_store.frames {
+            for (frame_id, frame) in self.frame_store.frames.iter().enumerate() {
                 if let Some(frame_offset) = frame.frame_offset.as_ref() {
-                    frame_offset
-                        .write(file)
-                        .context("Writing a frame offset data")?;
+                    frame_offset.write(file)?;
                 } else {
-                    return Err(WanError::NoOffsetDataForFrame)?;
+                    return Err(WanWriteError::MissingFrameOffset { frame_id });
                 }
             }
             sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
@@ -290,8 +308,7 @@ impl WanImage {
         );
         let (animation_group_reference_offset, sir0_animation_pointer) = self
             .animation_store
-            .write_animation_group(file, &animations_pointer)
-            .context("failed to write animations groups")?;
+            .write_animation_group(file, &animations_pointer)?;
         for pointer in sir0_animation_pointer {
             sir0_offsets.push(pointer as u32);
         }
@@ -302,9 +319,9 @@ impl WanImage {
             "start of the image offset: {}",
             file.seek(SeekFrom::Current(0))?
         );
-        for offset in image_offset {
+        for offset in &image_offset {
             sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
-            file.write_u32::<LE>(offset as u32)?;
+            file.write_u32::<LE>(*offset as u32)?;
         }
 
         // animation header
@@ -328,7 +345,10 @@ impl WanImage {
 
         file.write_u16::<LE>(self.animation_store.anim_groups.len() as u16)?;
 
-        file.write_u32::<LE>(size_to_allocate_for_max_frame as u32)?;
+        file.write_u32::<LE>(
+            self.size_to_allocate_for_max_frame
+                .unwrap_or(size_to_allocate_for_max_frame as u32),
+        )?;
         file.write_all(&[0; 6])?;
 
         // images header
@@ -337,16 +357,22 @@ impl WanImage {
             file.seek(SeekFrom::Current(0))?
         );
         let image_info_offset = file.seek(SeekFrom::Current(0))?;
-        sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
-        file.write_u32::<LE>(pointer_image_data_pointer_table as u32)?;
+        if self.shared_fragment_bytes {
+            file.write_u32::<LE>(0)?;
+        } else {
+            sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
+            file.write_u32::<LE>(pointer_image_data_pointer_table as u32)?;
+        }
 
-        sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
+        if !self.shared_palette {
+            sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
+        }
         (
             pointer_palette as u32,
-            0u16,
+            self.unk1,
             u16::from(self.is_256_color),
             self.unk2,
-            self.fragment_bytes_store.len() as u16,
+            image_offset.len() as u16,
         )
             .write_options(file, &opt_le)?;
 
@@ -357,8 +383,7 @@ impl WanImage {
         sir0_offsets.push(file.seek(SeekFrom::Current(0))? as u32);
         file.write_u32::<LE>(image_info_offset as u32)?;
         file.write_u16::<LE>(self.sprite_type.get_id() as u16)?;
-
-        file.write_all(&[0, 0])?;
+        file.write_u16::<LE>(self.unk_header)?;
 
         while file.seek(SeekFrom::Current(0))? % 16 != 0 {
             file.write_all(&[0xAA])?;
@@ -371,7 +396,7 @@ impl WanImage {
             "start of the sir0 list: {}",
             file.seek(SeekFrom::Current(0))?
         );
-        write_sir0_footer(file, &sir0_offsets).context("failed to write the Sir0 footer")?;
+        write_sir0_footer(file, &sir0_offsets)?;
 
         //padding
         file.write_all(&[0x00])?;
@@ -386,12 +411,45 @@ impl WanImage {
         file.seek(SeekFrom::Start(sir0_pointer_offset))?;
         file.write_u32::<LE>(sir0_offset_pos as u32)?;
 
-        file.seek(SeekFrom::Start(0))?;
         Ok(())
     }
 
+    /// Set the preserved unknown values to their usual value, and the preserved computed values to None, so they are recomputed when writing.
+    /// [`Fragment::unk3_4`] is kept, as it is part of the fragment rather than a preserved value.
+    ///
+    /// This should be done after adding, removing or resizing [`Fragment`]s, as the preserved allocation counters and size to allocate are likely no longer valid.
+    pub fn reset_unknown_values(&mut self) {
+        self.unk1 = 0;
+        self.unk2 = 0;
+        self.unk_header = 0;
+        self.size_to_allocate_for_max_frame = None;
+        self.palette.unk1 = 0;
+        self.palette.unk2 = Palette::new_eos().unk2;
+        for frame in &mut self.frame_store.frames {
+            for fragment in &mut frame.fragments {
+                fragment.alloc_counter = None;
+            }
+        }
+    }
+
+    /// Copy the palette and/or the [`FragmentBytes`] that are stored in another file from `source`, as indicated by [`WanImage::shared_palette`] and [`WanImage::shared_fragment_bytes`].
+    ///
+    /// The copied data are then part of this sprite, and will be written with it. This allows to render or convert a sprite that use the data of another entry of `EFFECT/effect.bin`.
+    pub fn resolve_shared_data(&mut self, source: &WanImage) {
+        if self.shared_palette {
+            self.palette = source.palette.clone();
+            self.shared_palette = false;
+        }
+        if self.shared_fragment_bytes {
+            self.fragment_bytes_store.fragment_bytes =
+                source.fragment_bytes_store.fragment_bytes.clone();
+            self.shared_fragment_bytes = false;
+        }
+    }
+
     /// Return the image corresponding to the resolution and the palette of given meta-frame.
     /// Doesn't perform flipping or any other transformation other than the resolution and the palette.
+    /// See [`WanImage::render_frame`] to get the image of a whole [`Frame`].
     pub fn get_image_for_fragment(
         &self,
         fragment: &Fragment,
@@ -409,7 +467,12 @@ impl WanImage {
             }
         };
 
-        image_bytes.get_image(&self.palette, fragment.resolution.size(), fragment.pal_idx)
+        image_bytes.get_image(
+            &self.palette,
+            fragment.resolution.size(),
+            fragment.pal_idx,
+            self.is_256_color,
+        )
     }
 
     pub fn fix_empty_frames(&mut self) {
@@ -428,7 +491,12 @@ impl WanImage {
             .fragment_bytes
             .push(FragmentBytes {
                 // no panic: We guarantee input parameters are valid
-                mixed_pixels: encode_fragment_pixels(&[0; 256], resolution.size()).unwrap(),
+                mixed_pixels: encode_fragment_pixels(
+                    &[0; 256],
+                    resolution.size(),
+                    self.is_256_color,
+                )
+                .unwrap(),
                 z_index: 0,
             });
         for empty_frame in collected {
@@ -442,8 +510,141 @@ impl WanImage {
                 flip: FragmentFlip::standard(),
                 is_mosaic: false,
                 pal_idx: 0,
+                alloc_counter: None,
                 resolution,
             })
         }
     }
 }
+
+/// The content of the headers of a wan file, with the pointers to each of its parts
+pub(crate) struct WanHeader {
+    pub source_file_lenght: u64,
+    pub sprite_type: SpriteType,
+    pub unk_header: u16,
+    pub pointer_frames_table: u64,
+    /// 0 if there is none
+    pub frame_offset_table: u64,
+    pub pointer_animation_table: u64,
+    pub amount_animation_group: u16,
+    pub size_to_allocate_for_max_frame: u32,
+    pub pointer_image_data_pointer_table: u64,
+    pub pointer_palette: u64,
+    pub unk1: u16,
+    pub is_256_color: bool,
+    pub unk2: u16,
+    pub amount_fragments: u16,
+    pub nb_frames: u64,
+}
+
+impl WanHeader {
+    /// Read and check the sir0 header, the wan header, the animation info and the image data info.
+    pub fn read<F: Read + Seek>(mut file: F) -> Result<WanHeader, WanError> {
+        let source_file_lenght = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        debug!("start to decode a wan image");
+
+        // first step: decode the sir0 header
+        trace!("decoding the sir0 header");
+        let sir0_header = wan_read_raw_4(&mut file)?;
+        if sir0_header != [0x53, 0x49, 0x52, 0x30] {
+            return Err(WanError::InvalidSir0(sir0_header));
+        };
+        let sir0_pointer_header = file.read_u32::<LE>()? as u64;
+        let _sir0_pointer_offset = file.read_u32::<LE>()? as u64;
+
+        let sir0_header_end = wan_read_raw_4(&mut file)?;
+        if sir0_header_end != [0, 0, 0, 0] {
+            return Err(WanError::InvalidEndOfSir0Header(sir0_header_end));
+        };
+
+        // second step: decode the wan header
+        trace!("reading the wan header");
+        file.seek(SeekFrom::Start(sir0_pointer_header))?;
+        let pointer_to_anim_info = file.read_u32::<LE>()? as u64;
+        let pointer_to_image_data_info = file.read_u32::<LE>()? as u64;
+
+        let sprite_type_id = file.read_u16::<LE>()?;
+        let sprite_type = SpriteType::from_id(sprite_type_id)
+            .map_or_else(|| Err(WanError::TypeOfSpriteUnknown(sprite_type_id)), Ok)?;
+        let unk_header = file.read_u16::<LE>()?;
+
+        // third step: decode animation info block
+        trace!("reading the animation info block");
+        file.seek(SeekFrom::Start(pointer_to_anim_info))?;
+        let pointer_frames_table = file.read_u32::<LE>()? as u64;
+        if pointer_frames_table > source_file_lenght {
+            return Err(WanError::PostFilePointer("meta frame reference table"));
+        }
+        let frame_offset_table = file.read_u32::<LE>()? as u64;
+        if frame_offset_table > source_file_lenght {
+            return Err(WanError::PostFilePointer("particule offset table"));
+        };
+        #[allow(unused_parens)]
+        if sprite_type == SpriteType::Chara && frame_offset_table == 0 {
+            return Err(WanError::NonExistenceFrameOffsetForChara);
+        } else if sprite_type != SpriteType::Chara && frame_offset_table != 0 {
+            return Err(WanError::ExistenceFrameOffsetForNonChara);
+        };
+        let pointer_animation_table = file.read_u32::<LE>()? as u64;
+        if pointer_animation_table > source_file_lenght {
+            return Err(WanError::PostFilePointer("animation groups table"));
+        }
+        let amount_animation_group = file.read_u16::<LE>()?;
+
+        let size_to_allocate_for_max_frame = file.read_u32::<LE>()?;
+
+        // fourth: decode image data info
+        trace!("reading the image data info");
+        file.seek(SeekFrom::Start(pointer_to_image_data_info))?;
+        let pointer_image_data_pointer_table = file.read_u32::<LE>()? as u64;
+        let pointer_palette = file.read_u32::<LE>()? as u64;
+        let unk1 = file.read_u16::<LE>()?;
+        let is_256_color = match file.read_u16::<LE>()? {
+            0 => false,
+            1 => true,
+            color_id => return Err(WanError::InvalidColorNumber(color_id)),
+        };
+        let unk2 = file.read_u16::<LE>()?;
+        let amount_fragments = file.read_u16::<LE>()?;
+
+        let frames_end_pointer: u64 = match frame_offset_table {
+            0 => match WanImage::find_first_non_null_animation_seq_entry(
+                &mut file,
+                pointer_animation_table,
+            ) {
+                Some(v) => v,
+                // Fall back to animation group offset
+                None => pointer_animation_table,
+            },
+            value => value,
+        };
+
+        let space_frame_raw = WanError::checked_sub(
+            frames_end_pointer,
+            pointer_frames_table,
+            "frames end pointer",
+            "pointer frames table",
+        )?;
+
+        let nb_frames = space_frame_raw / 4;
+
+        Ok(WanHeader {
+            source_file_lenght,
+            sprite_type,
+            unk_header,
+            pointer_frames_table,
+            frame_offset_table,
+            pointer_animation_table,
+            amount_animation_group,
+            size_to_allocate_for_max_frame,
+            pointer_image_data_pointer_table,
+            pointer_palette,
+            unk1,
+            is_256_color,
+            unk2,
+            amount_fragments,
+            nb_frames,
+        })
+    }
+}
@@ -0,0 +1,1247 @@
+use binwrite::BinWrite;
+use byteorder::{ReadBytesExt, LE};
+use pmd_sir0::write_sir0_footer;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    fragment_bytes_compression::write_pixels, Animation, SpriteType, WanError, WanImage,
+    WanWriteError,
+};
+
+/// The byte used to pad the parts of a wan file
+const PADDING_BYTE: u8 = 0xAA;
+/// The largest alignment recognised in the padding of a file
+const MAX_ALIGNMENT: u64 = 16;
+
+/// A part of a wan file, whose position is kept by a [`WanLayout`].
+/// The data that may be shared (frames, animations and pixels) are numbered by slot, in the order they are first referenced in the original file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Part {
+    Sir0Header,
+    /// The fragments of one or more identical frames
+    Frame(usize),
+    /// The frames of one or more identical animations
+    Animation(usize),
+    /// Pixels referenced by one or more entries of the assembly tables
+    Pixels(usize),
+    /// The assembly table of the [`crate::FragmentBytes`] with this index
+    AssemblyTable(usize),
+    PaletteColors,
+    PaletteHeader,
+    FramesTable,
+    FrameOffsets,
+    /// The list of the animations of the animation group with this index
+    AnimGroupList(usize),
+    AnimGroupTable,
+    FragmentBytesTable,
+    AnimInfo,
+    ImageInfo,
+    WanHeader,
+    Sir0Footer,
+}
+
+impl Part {
+    /// The position of this kind of part in the files written by [`WanImage::create_wan`]
+    fn rank(self) -> u8 {
+        match self {
+            Self::Sir0Header => 0,
+            Self::Frame(_) => 1,
+            Self::Animation(_) => 2,
+            Self::Pixels(_) => 3,
+            Self::AssemblyTable(_) => 4,
+            Self::PaletteColors => 5,
+            Self::PaletteHeader => 6,
+            Self::FramesTable => 7,
+            Self::FrameOffsets => 8,
+            Self::AnimGroupList(_) => 9,
+            Self::AnimGroupTable => 10,
+            Self::FragmentBytesTable => 11,
+            Self::AnimInfo => 12,
+            Self::ImageInfo => 13,
+            Self::WanHeader => 14,
+            Self::Sir0Footer => 15,
+        }
+    }
+
+    /// The index of this part among the parts of the same kind
+    fn index(self) -> usize {
+        match self {
+            Self::Frame(index)
+            | Self::Animation(index)
+            | Self::Pixels(index)
+            | Self::AssemblyTable(index)
+            | Self::AnimGroupList(index) => index,
+            _ => 0,
+        }
+    }
+
+    /// The alignment of this part, when it isn't known from the original file.
+    /// Frames are never padded, as they all need to be at a multiple of 10 bytes from the first one.
+    fn alignment(self) -> u64 {
+        match self {
+            Self::Sir0Header | Self::Frame(_) => 1,
+            Self::Animation(_) => 2,
+            Self::Sir0Footer => MAX_ALIGNMENT,
+            _ => 4,
+        }
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sir0Header => write!(f, "sir0 header"),
+            Self::Frame(slot) => write!(f, "frame data n°{}", slot),
+            Self::Animation(slot) => write!(f, "animation data n°{}", slot),
+            Self::Pixels(slot) => write!(f, "pixel data n°{}", slot),
+            Self::AssemblyTable(index) => {
+                write!(f, "assembly table of the FragmentBytes {}", index)
+            }
+            Self::PaletteColors => write!(f, "palette"),
+            Self::PaletteHeader => write!(f, "palette header"),
+            Self::FramesTable => write!(f, "frames table"),
+            Self::FrameOffsets => write!(f, "frame offsets table"),
+            Self::AnimGroupList(index) => {
+                write!(f, "animation list of the animation group {}", index)
+            }
+            Self::AnimGroupTable => write!(f, "animation groups table"),
+            Self::FragmentBytesTable => write!(f, "FragmentBytes table"),
+            Self::AnimInfo => write!(f, "animation info"),
+            Self::ImageInfo => write!(f, "image data info"),
+            Self::WanHeader => write!(f, "wan header"),
+            Self::Sir0Footer => write!(f, "sir0 footer"),
+        }
+    }
+}
+
+/// The bytes before a part of the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Padding {
+    /// 0xAA until the position is a multiple of the alignment
+    Align(u64),
+    /// Always the same bytes, wherever the part is
+    Bytes(Vec<u8>),
+}
+
+impl Padding {
+    /// Find the padding that produce the bytes `gap`, when it end at `end`.
+    /// Padding with 0xAA is recognised as an alignment of at least `min_alignment`. Other bytes are kept as-is.
+    fn infer(gap: &[u8], end: u64, min_alignment: u64) -> Self {
+        if gap.iter().all(|byte| *byte == PADDING_BYTE) {
+            let mut alignment = min_alignment;
+            while alignment <= MAX_ALIGNMENT {
+                if end.is_multiple_of(alignment) && (gap.len() as u64) < alignment {
+                    return Self::Align(alignment);
+                }
+                alignment *= 2;
+            }
+        }
+        Self::Bytes(gap.to_vec())
+    }
+
+    fn bytes(&self, position: u64) -> Vec<u8> {
+        match self {
+            Self::Align(alignment) => {
+                vec![PADDING_BYTE; ((alignment - position % alignment) % alignment) as usize]
+            }
+            Self::Bytes(bytes) => bytes.clone(),
+        }
+    }
+
+    fn len(&self, position: u64) -> u64 {
+        match self {
+            Self::Align(alignment) => (alignment - position % alignment) % alignment,
+            Self::Bytes(bytes) => bytes.len() as u64,
+        }
+    }
+}
+
+/// An entry of the assembly table of a [`crate::FragmentBytes`], as stored in the original file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AssemblyEntryLayout {
+    byte_amount: u16,
+    /// The slot of the pixels, or None if they are all transparent
+    pixels: Option<usize>,
+    /// The unknown u16 after the number of bytes
+    unknown: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AssemblyTableLayout {
+    entries: Vec<AssemblyEntryLayout>,
+    /// The last 6 bytes of the null entry that end the table
+    end: [u8; 6],
+}
+
+impl AssemblyTableLayout {
+    /// Return true if the given pixels can be stored with the entries of this table: they should have the same size, and be transparent where the original pixels weren't stored.
+    fn fits(&self, pixels: &[u8], pixel_per_byte: usize) -> bool {
+        let mut remaining_pixels = pixels;
+        for entry in &self.entries {
+            let pixel_amount = entry.byte_amount as usize * pixel_per_byte;
+            if pixel_amount > remaining_pixels.len() {
+                return false;
+            }
+            let (entry_pixels, next_pixels) = remaining_pixels.split_at(pixel_amount);
+            if entry.pixels.is_none() && entry_pixels.iter().any(|pixel| *pixel != 0) {
+                return false;
+            }
+            remaining_pixels = next_pixels;
+        }
+        remaining_pixels.is_empty()
+    }
+}
+
+/// How each part of a wan file was stored, as read by [`WanImage::decode_wan_with_layout`].
+///
+/// It allows [`WanImage::create_wan_with_layout`] to write a sprite with the same layout as the original file: block order, padding, shared frames, animation sequences and pixels, assembly tables, the unknown constants of the headers and the content of the sir0 footer are kept.
+/// An unmodified sprite is then written identically to the original file, and a modified one only differ where it changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WanLayout {
+    /// The parts of the file in the order they are stored, with the padding before each of them
+    parts: Vec<(Padding, Part)>,
+    /// The padding after the last part
+    end_padding: Padding,
+    /// The frame slot of each frame
+    frames: Vec<usize>,
+    /// The animation slot of each animation, for each animation group
+    animations: Vec<Vec<usize>>,
+    /// The assembly table of each [`crate::FragmentBytes`]
+    assembly_tables: Vec<AssemblyTableLayout>,
+    /// The last 6 bytes of the animation info
+    anim_info_end: [u8; 6],
+    /// The positions listed in the sir0 footer that aren't a pointer of the sprite, relative to the part they are in
+    extra_sir0_pointers: Vec<(Part, u64)>,
+    /// The pointers of the sprite that aren't listed in the sir0 footer, relative to the part they are in
+    missing_sir0_pointers: Vec<(Part, u64)>,
+}
+
+impl WanLayout {
+    /// Read the layout of the given wan file, previously decoded as `wan_image`.
+    fn new_from_bytes(original: &[u8], wan_image: &WanImage) -> Result<Self, WanError> {
+        let mut file = Cursor::new(original);
+        // the position, size and kind of each part
+        let mut parts: Vec<(u64, u64, Part)> = vec![(0, 16, Part::Sir0Header)];
+        // the position of the pointers of the sprite, in the part they are in
+        let mut pointers: Vec<(Part, u64)> = vec![(Part::Sir0Header, 4), (Part::Sir0Header, 8)];
+
+        file.seek(SeekFrom::Start(4))?;
+        let wan_header = file.read_u32::<LE>()? as u64;
+        let sir0_footer = file.read_u32::<LE>()? as u64;
+
+        parts.push((wan_header, 12, Part::WanHeader));
+        pointers.extend([(Part::WanHeader, 0), (Part::WanHeader, 4)]);
+        file.seek(SeekFrom::Start(wan_header))?;
+        let anim_info = file.read_u32::<LE>()? as u64;
+        let image_info = file.read_u32::<LE>()? as u64;
+
+        parts.push((image_info, 16, Part::ImageInfo));
+
+        // frames
+        parts.push((anim_info, 24, Part::AnimInfo));
+        pointers.extend([(Part::AnimInfo, 0), (Part::AnimInfo, 8)]);
+        file.seek(SeekFrom::Start(anim_info))?;
+        let frames_table = file.read_u32::<LE>()? as u64;
+        let frame_offsets = file.read_u32::<LE>()? as u64;
+        let anim_group_table = file.read_u32::<LE>()? as u64;
+        file.seek(SeekFrom::Start(anim_info + 18))?;
+        let mut anim_info_end = [0; 6];
+        file.read_exact(&mut anim_info_end)?;
+
+        let frames = &wan_image.frame_store.frames;
+        parts.push((frames_table, 4 * frames.len() as u64, Part::FramesTable));
+        file.seek(SeekFrom::Start(frames_table))?;
+        let mut frame_slots: HashMap<u64, usize> = HashMap::new();
+        let mut frames_layout = Vec::with_capacity(frames.len());
+        for (frame_id, frame) in frames.iter().enumerate() {
+            pointers.push((Part::FramesTable, frame_id as u64 * 4));
+            let position = file.read_u32::<LE>()? as u64;
+            let next_slot = frame_slots.len();
+            let slot = *frame_slots.entry(position).or_insert_with(|| {
+                let size = 10 * frame.fragments.len() as u64;
+                parts.push((position, size, Part::Frame(next_slot)));
+                next_slot
+            });
+            frames_layout.push(slot);
+        }
+        if frame_offsets != 0 {
+            pointers.push((Part::AnimInfo, 4));
+            parts.push((frame_offsets, 16 * frames.len() as u64, Part::FrameOffsets));
+        }
+
+        // animations
+        let anim_groups = &wan_image.animation_store.anim_groups;
+        parts.push((
+            anim_group_table,
+            8 * anim_groups.len() as u64,
+            Part::AnimGroupTable,
+        ));
+        let mut animation_slots: HashMap<u64, usize> = HashMap::new();
+        let mut animations = Vec::with_capacity(anim_groups.len());
+        for (anim_group_id, anim_group) in anim_groups.iter().enumerate() {
+            file.seek(SeekFrom::Start(anim_group_table + anim_group_id as u64 * 8))?;
+            let list = file.read_u32::<LE>()? as u64;
+            let length = file.read_u32::<LE>()?;
+            let mut group_layout = Vec::with_capacity(anim_group.len());
+            if list != 0 && length != 0 {
+                pointers.push((Part::AnimGroupTable, anim_group_id as u64 * 8));
+                let list_part = Part::AnimGroupList(anim_group_id);
+                parts.push((list, 4 * anim_group.len() as u64, list_part));
+                file.seek(SeekFrom::Start(list))?;
+                for (animation_id, animation) in anim_group.iter().enumerate() {
+                    pointers.push((list_part, animation_id as u64 * 4));
+                    let position = file.read_u32::<LE>()? as u64;
+                    let next_slot = animation_slots.len();
+                    let slot = *animation_slots.entry(position).or_insert_with(|| {
+                        let size = 12 * (animation.frames.len() as u64 + 1);
+                        parts.push((position, size, Part::Animation(next_slot)));
+                        next_slot
+                    });
+                    group_layout.push(slot);
+                }
+            }
+            animations.push(group_layout);
+        }
+
+        // palette
+        file.seek(SeekFrom::Start(image_info))?;
+        let fragment_bytes_table = file.read_u32::<LE>()? as u64;
+        let palette_header = file.read_u32::<LE>()? as u64;
+        if palette_header != 0 {
+            pointers.extend([(Part::ImageInfo, 4), (Part::PaletteHeader, 0)]);
+            parts.push((palette_header, 16, Part::PaletteHeader));
+            file.seek(SeekFrom::Start(palette_header))?;
+            let colors = file.read_u32::<LE>()? as u64;
+            let size = 4 * wan_image.palette.palette.len() as u64;
+            parts.push((colors, size, Part::PaletteColors));
+        }
+
+        // fragment bytes
+        let mut assembly_tables = Vec::new();
+        if fragment_bytes_table != 0 {
+            pointers.push((Part::ImageInfo, 0));
+            let fragment_bytes_amount = wan_image.fragment_bytes_store.len();
+            parts.push((
+                fragment_bytes_table,
+                4 * fragment_bytes_amount as u64,
+                Part::FragmentBytesTable,
+            ));
+            let mut pixel_slots: HashMap<(u64, u16), usize> = HashMap::new();
+            for fragment_bytes_id in 0..fragment_bytes_amount {
+                pointers.push((Part::FragmentBytesTable, fragment_bytes_id as u64 * 4));
+                file.seek(SeekFrom::Start(
+                    fragment_bytes_table + fragment_bytes_id as u64 * 4,
+                ))?;
+                let assembly_table = file.read_u32::<LE>()? as u64;
+                let table_part = Part::AssemblyTable(fragment_bytes_id);
+                file.seek(SeekFrom::Start(assembly_table))?;
+                let mut entries = Vec::new();
+                let end = loop {
+                    let pixel_src = file.read_u32::<LE>()? as u64;
+                    let byte_amount = file.read_u16::<LE>()?;
+                    let mut rest = [0; 6];
+                    file.read_exact(&mut rest)?;
+                    if pixel_src == 0 && byte_amount == 0 {
+                        break rest;
+                    }
+                    let pixels =
+                        if pixel_src == 0 {
+                            None
+                        } else {
+                            pointers.push((table_part, entries.len() as u64 * 12));
+                            let next_slot = pixel_slots.len();
+                            Some(*pixel_slots.entry((pixel_src, byte_amount)).or_insert_with(
+                                || {
+                                    parts.push((
+                                        pixel_src,
+                                        byte_amount as u64,
+                                        Part::Pixels(next_slot),
+                                    ));
+                                    next_slot
+                                },
+                            ))
+                        };
+                    entries.push(AssemblyEntryLayout {
+                        byte_amount,
+                        pixels,
+                        unknown: u16::from_le_bytes([rest[0], rest[1]]),
+                    });
+                };
+                parts.push((assembly_table, 12 * (entries.len() as u64 + 1), table_part));
+                assembly_tables.push(AssemblyTableLayout { entries, end });
+            }
+        }
+
+        // sir0 footer
+        file.seek(SeekFrom::Start(sir0_footer))?;
+        let mut sir0_pointers = Vec::new();
+        let mut last_pointer = 0u64;
+        loop {
+            let mut byte = file.read_u8()?;
+            if byte == 0 {
+                break;
+            }
+            let mut offset = 0u64;
+            loop {
+                offset = (offset << 7) | u64::from(byte & 0x7F);
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                byte = file.read_u8()?;
+            }
+            last_pointer = last_pointer.saturating_add(offset);
+            sir0_pointers.push(last_pointer);
+        }
+        parts.push((sir0_footer, file.position() - sir0_footer, Part::Sir0Footer));
+
+        // the empty parts are placed before the part that start at the same position
+        parts.sort_by_key(|(start, size, part)| (*start, *size != 0, part.rank(), part.index()));
+        let mut layout_parts = Vec::with_capacity(parts.len());
+        let mut previous: Option<(u64, Part)> = None;
+        for (start, size, part) in &parts {
+            let gap_start = match previous {
+                Some((previous_end, previous_part)) => {
+                    if *start < previous_end {
+                        return Err(WanError::OverlappingParts(
+                            previous_part.to_string(),
+                            part.to_string(),
+                        ));
+                    }
+                    previous_end
+                }
+                None => 0,
+            };
+            let end = start + size;
+            if end > original.len() as u64 {
+                return Err(WanError::PartAfterEndOfFile(part.to_string()));
+            }
+            let gap = &original[gap_start as usize..*start as usize];
+            layout_parts.push((Padding::infer(gap, *start, part.alignment()), *part));
+            previous = Some((end, *part));
+        }
+        let last_end = previous.map_or(0, |(end, _)| end);
+        let end_padding = Padding::infer(
+            &original[last_end as usize..],
+            original.len() as u64,
+            MAX_ALIGNMENT,
+        );
+
+        let starts: HashMap<Part, u64> = parts
+            .iter()
+            .map(|(start, _, part)| (*part, *start))
+            .collect();
+        let pointer_positions: HashSet<u64> = pointers
+            .iter()
+            .map(|(part, offset)| starts[part] + offset)
+            .collect();
+        let listed_pointers: HashSet<u64> = sir0_pointers.iter().copied().collect();
+        let missing_sir0_pointers = pointers
+            .into_iter()
+            .filter(|(part, offset)| !listed_pointers.contains(&(starts[part] + offset)))
+            .collect();
+        let extra_sir0_pointers = sir0_pointers
+            .iter()
+            .filter(|position| !pointer_positions.contains(position))
+            .filter_map(|position| {
+                // the part that contain this position, or that precede it
+                parts
+                    .iter()
+                    .rev()
+                    .find(|(start, _, _)| start <= position)
+                    .map(|(start, _, part)| (*part, position - start))
+            })
+            .collect();
+
+        Ok(Self {
+            parts: layout_parts,
+            end_padding,
+            frames: frames_layout,
+            animations,
+            assembly_tables,
+            anim_info_end,
+            extra_sir0_pointers,
+            missing_sir0_pointers,
+        })
+    }
+}
+
+/// The content of a part, before the position of the other parts is known
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PartContent {
+    bytes: Vec<u8>,
+    /// The position of the pointers in the content, with the part they point to. They are filled once every part is placed.
+    pointers: Vec<(usize, Part)>,
+}
+
+impl PartContent {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            pointers: Vec::new(),
+        }
+    }
+
+    fn push_pointer(&mut self, target: Part) {
+        self.pointers.push((self.bytes.len(), target));
+        self.bytes.extend([0; 4]);
+    }
+
+    fn push_u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+}
+
+/// The parts of a sprite being written with a [`WanLayout`]
+struct PartEncoder<'a> {
+    layout: &'a WanLayout,
+    layout_parts: HashSet<Part>,
+    contents: HashMap<Part, PartContent>,
+    /// The parts that aren't in the layout, in the order they were added, with the part they should follow if any
+    new_parts: Vec<(Part, Option<Part>)>,
+    /// The pixel slots of each [`crate::FragmentBytes`], whose content should be stored one after the other
+    pixel_runs: Vec<Vec<usize>>,
+    /// The part that should directly follow the frames table, as its end is used to find the number of frames
+    frames_table_follower: Option<Part>,
+}
+
+impl<'a> PartEncoder<'a> {
+    fn new(layout: &'a WanLayout) -> Self {
+        Self {
+            layout,
+            layout_parts: layout.parts.iter().map(|(_, part)| *part).collect(),
+            contents: HashMap::new(),
+            new_parts: Vec::new(),
+            pixel_runs: Vec::new(),
+            frames_table_follower: None,
+        }
+    }
+
+    /// Add a part. If it isn't in the layout, it is placed after `anchor`, or after the parts of the same kind.
+    fn add(&mut self, part: Part, content: PartContent, anchor: Option<Part>) {
+        if !self.layout_parts.contains(&part) {
+            self.new_parts.push((part, anchor));
+        }
+        self.contents.insert(part, content);
+    }
+
+    fn is_free(&self, part: Part) -> bool {
+        !self.contents.contains_key(&part)
+    }
+
+    /// Add some data that was stored in the given slot in the original file, and return the slot it is stored in.
+    /// The original slot is used if it is still free, or if it contains the same data. Otherwise, the data is stored in a new slot following it.
+    fn add_shared(
+        &mut self,
+        kind: fn(usize) -> Part,
+        original_slot: Option<usize>,
+        next_slot: &mut usize,
+        content: PartContent,
+    ) -> usize {
+        if let Some(slot) = original_slot {
+            match self.contents.get(&kind(slot)) {
+                None => {
+                    self.add(kind(slot), content, None);
+                    return slot;
+                }
+                Some(existing) if *existing == content => return slot,
+                Some(_) => {}
+            }
+        }
+        let slot = *next_slot;
+        *next_slot += 1;
+        self.add(kind(slot), content, original_slot.map(kind));
+        slot
+    }
+
+    /// The index in `order` where a new part should be placed, when it doesn't follow a specific part:
+    /// before the part of the same kind with a higher index, after the last part of the same kind, or after the last kind of part that precede it in [`WanImage::create_wan`].
+    fn default_position(order: &[(Padding, Part)], part: Part) -> usize {
+        let same_kind = |other: &Part| other.rank() == part.rank();
+        if let Some(index) = order
+            .iter()
+            .position(|(_, other)| same_kind(other) && other.index() > part.index())
+        {
+            return index;
+        }
+        if let Some(index) = order.iter().rposition(|(_, other)| same_kind(other)) {
+            return index + 1;
+        }
+        order
+            .iter()
+            .rposition(|(_, other)| other.rank() < part.rank())
+            .map_or(0, |index| index + 1)
+    }
+
+    /// The order of the parts to write: the order of the layout, without the unused parts, and with the new ones.
+    fn arrange(&self) -> Vec<(Padding, Part)> {
+        let mut order: Vec<(Padding, Part)> = self
+            .layout
+            .parts
+            .iter()
+            .filter(|(_, part)| self.contents.contains_key(part))
+            .cloned()
+            .collect();
+        for (part, anchor) in &self.new_parts {
+            let index = anchor
+                .and_then(|anchor| order.iter().position(|(_, other)| *other == anchor))
+                .map(|index| index + 1)
+                .unwrap_or_else(|| Self::default_position(&order, *part));
+            order.insert(index, (Padding::Align(part.alignment()), *part));
+        }
+
+        // the pixels of a FragmentBytes can't be separated by padding
+        for run in &self.pixel_runs {
+            for slots in run.windows(2) {
+                if let Some(index) = order
+                    .iter()
+                    .position(|(_, part)| *part == Part::Pixels(slots[1]))
+                {
+                    if index > 0 && order[index - 1].1 == Part::Pixels(slots[0]) {
+                        order[index].0 = Padding::Bytes(Vec::new());
+                    }
+                }
+            }
+        }
+
+        if let Some(follower) = self.frames_table_follower {
+            if let Some(follower_index) = order.iter().position(|(_, part)| *part == follower) {
+                order.remove(follower_index);
+                if let Some(frames_table_index) = order
+                    .iter()
+                    .position(|(_, part)| *part == Part::FramesTable)
+                {
+                    order.insert(
+                        frames_table_index + 1,
+                        (Padding::Bytes(Vec::new()), follower),
+                    );
+                } else {
+                    order.insert(
+                        follower_index,
+                        (Padding::Align(follower.alignment()), follower),
+                    );
+                }
+            }
+        }
+        order
+    }
+}
+
+/// Return the number of slots used in the given list
+fn slot_amount<'a>(slots: impl Iterator<Item = &'a usize>) -> usize {
+    slots.max().map_or(0, |slot| slot + 1)
+}
+
+impl WanImage {
+    /// Same as [`WanImage::decode_wan`], but also return the [`WanLayout`] of the file, so it can be written back identically with [`WanImage::create_wan_with_layout`].
+    /// The sprite is read from the current position to the end of the file.
+    pub fn decode_wan_with_layout<F: Read>(mut file: F) -> Result<(WanImage, WanLayout), WanError> {
+        let mut original = Vec::new();
+        file.read_to_end(&mut original)?;
+        let wan_image = WanImage::decode_wan(Cursor::new(&original))?;
+        let layout = WanLayout::new_from_bytes(&original, &wan_image)?;
+        Ok((wan_image, layout))
+    }
+
+    /// Same as [`WanImage::create_wan`], but the sprite is encoded with the layout of the file it was decoded from. The cursor is moved back to the start of the written sprite at the end.
+    /// If the sprite wasn't modified, the output is identical to the original file.
+    ///
+    /// The parts of the file are written in the same order and with the same padding as in the original file. New parts (like an added frame) are placed after the other parts of the same kind, and unused parts are removed.
+    /// Frames, animations and pixels that were stored once for several users stay shared as long as they are identical.
+    /// The pixels of a [`crate::FragmentBytes`] are stored with its original assembly table when they still fit in it, and are compressed with [`WanImage::compression`] otherwise. [`WanImage::share_pixel_chunks`] is ignored.
+    pub fn create_wan_with_layout<F: Write + Seek>(
+        &self,
+        file: &mut F,
+        layout: &WanLayout,
+    ) -> Result<(), WanWriteError> {
+        let start_position = file.stream_position()?;
+        file.write_all(&self.to_bytes_with_layout(layout)?)?;
+        file.seek(SeekFrom::Start(start_position))?;
+        Ok(())
+    }
+
+    /// Return the sprite encoded in the wan format, with the given layout
+    fn to_bytes_with_layout(&self, layout: &WanLayout) -> Result<Vec<u8>, WanWriteError> {
+        let mut encoder = self.encode_parts(layout)?;
+        let order = encoder.arrange();
+
+        // the size of the footer depends on the position of the pointers, and may move the parts after it
+        let mut positions: HashMap<Part, u64>;
+        loop {
+            positions = HashMap::new();
+            let mut position = 0;
+            for (padding, part) in &order {
+                position += padding.len(position);
+                positions.insert(*part, position);
+                position += encoder.contents[part].bytes.len() as u64;
+            }
+
+            let missing_pointers: HashSet<&(Part, u64)> =
+                layout.missing_sir0_pointers.iter().collect();
+            let mut sir0_pointers: Vec<u32> = encoder
+                .contents
+                .iter()
+                .flat_map(|(part, content)| {
+                    content
+                        .pointers
+                        .iter()
+                        .map(move |(offset, _)| (*part, *offset as u64))
+                })
+                .filter(|pointer| !missing_pointers.contains(pointer))
+                .chain(layout.extra_sir0_pointers.iter().copied())
+                .filter_map(|(part, offset)| positions.get(&part).map(|start| start + offset))
+                .map(|position| position as u32)
+                .collect();
+            sir0_pointers.sort_unstable();
+            sir0_pointers.dedup();
+
+            let mut footer = Vec::new();
+            write_sir0_footer(&mut footer, &sir0_pointers)?;
+            footer.push(0);
+            let footer_content = encoder.contents.entry(Part::Sir0Footer).or_default();
+            let is_stable = footer_content.bytes.len() == footer.len();
+            footer_content.bytes = footer;
+            if is_stable {
+                break;
+            }
+        }
+
+        let mut output = Vec::new();
+        for (padding, part) in &order {
+            output.extend(padding.bytes(output.len() as u64));
+            let content = &encoder.contents[part];
+            let start = output.len();
+            output.extend_from_slice(&content.bytes);
+            for (offset, target) in &content.pointers {
+                output[start + offset..start + offset + 4]
+                    .copy_from_slice(&(positions[target] as u32).to_le_bytes());
+            }
+        }
+        output.extend(layout.end_padding.bytes(output.len() as u64));
+        Ok(output)
+    }
+
+    /// Encode each part of the sprite, using the slots and assembly tables of the layout where possible
+    fn encode_parts<'a>(&self, layout: &'a WanLayout) -> Result<PartEncoder<'a>, WanWriteError> {
+        if self.fragment_bytes_store.len() > i16::MAX as usize {
+            return Err(WanWriteError::TooManyFragmentBytes(
+                self.fragment_bytes_store.len(),
+            ));
+        }
+        if self.animation_store.anim_groups.len() > u16::MAX as usize {
+            return Err(WanWriteError::TooManyAnimGroups(
+                self.animation_store.anim_groups.len(),
+            ));
+        }
+        if self.frame_store.frames.len() > u16::MAX as usize {
+            return Err(WanWriteError::TooManyFrames(self.frame_store.frames.len()));
+        }
+        let mut encoder = PartEncoder::new(layout);
+
+        // frames
+        let mut next_frame_slot = slot_amount(layout.frames.iter());
+        let mut frames_table = PartContent::default();
+        let mut size_to_allocate_for_max_frame = 0;
+        for (frame_id, frame) in self.frame_store.frames.iter().enumerate() {
+            let mut content = PartContent::default();
+            let size_to_allocate = frame.write(&mut content.bytes, frame_id)?;
+            size_to_allocate_for_max_frame = size_to_allocate_for_max_frame.max(size_to_allocate);
+            let original_slot = layout.frames.get(frame_id).copied();
+            let slot =
+                encoder.add_shared(Part::Frame, original_slot, &mut next_frame_slot, content);
+            frames_table.push_pointer(Part::Frame(slot));
+        }
+        encoder.add(Part::FramesTable, frames_table, None);
+
+        let has_frame_offsets = self.sprite_type == SpriteType::Chara;
+        if has_frame_offsets {
+            let mut frame_offsets = PartContent::default();
+            for (frame_id, frame) in self.frame_store.frames.iter().enumerate() {
+                frame
+                    .frame_offset
+                    .as_ref()
+                    .ok_or(WanWriteError::MissingFrameOffset { frame_id })?
+                    .write(&mut frame_offsets.bytes)?;
+            }
+            encoder.add(Part::FrameOffsets, frame_offsets, None);
+            encoder.frames_table_follower = Some(Part::FrameOffsets);
+        }
+
+        // animations
+        let mut next_animation_slot = slot_amount(layout.animations.iter().flatten());
+        let mut anim_group_table = PartContent::default();
+        for (anim_group_id, anim_group) in self.animation_store.anim_groups.iter().enumerate() {
+            if anim_group.is_empty() {
+                anim_group_table.push_u32(0);
+                anim_group_table.push_u32(0);
+                continue;
+            }
+            let mut list = PartContent::default();
+            for (animation_id, animation) in anim_group.iter().enumerate() {
+                let mut content = PartContent::default();
+                Animation::write(&mut content.bytes, animation)?;
+                let original_slot = layout
+                    .animations
+                    .get(anim_group_id)
+                    .and_then(|group| group.get(animation_id))
+                    .copied();
+                let slot = encoder.add_shared(
+                    Part::Animation,
+                    original_slot,
+                    &mut next_animation_slot,
+                    content,
+                );
+                list.push_pointer(Part::Animation(slot));
+            }
+            let list_part = Part::AnimGroupList(anim_group_id);
+            encoder.add(list_part, list, None);
+            if encoder.frames_table_follower.is_none() {
+                encoder.frames_table_follower = Some(list_part);
+            }
+            anim_group_table.push_pointer(list_part);
+            anim_group_table.push_u32(anim_group.len() as u32);
+        }
+        encoder.add(Part::AnimGroupTable, anim_group_table, None);
+
+        // fragment bytes
+        let fragment_bytes: &[_] = if self.shared_fragment_bytes {
+            &[]
+        } else {
+            &self.fragment_bytes_store.fragment_bytes
+        };
+        let pixel_per_byte = if self.is_256_color { 1 } else { 2 };
+        let mut next_pixel_slot = slot_amount(
+            layout
+                .assembly_tables
+                .iter()
+                .flat_map(|table| table.entries.iter())
+                .filter_map(|entry| entry.pixels.as_ref()),
+        );
+        let mut fragment_bytes_table = PartContent::default();
+        for (fragment_bytes_id, fragment_bytes) in fragment_bytes.iter().enumerate() {
+            let table_layout = layout.assembly_tables.get(fragment_bytes_id);
+            // the byte amount, pixel slot and unknown value of each entry
+            let mut entries: Vec<(u16, Option<usize>, u16)> = Vec::new();
+            let mut table_anchor = None;
+            let end = match table_layout
+                .filter(|table| table.fits(&fragment_bytes.mixed_pixels, pixel_per_byte))
+            {
+                Some(table_layout) => {
+                    let mut remaining_pixels = fragment_bytes.mixed_pixels.as_slice();
+                    for entry in &table_layout.entries {
+                        let (pixels, next_pixels) =
+                            remaining_pixels.split_at(entry.byte_amount as usize * pixel_per_byte);
+                        remaining_pixels = next_pixels;
+                        let slot = match entry.pixels {
+                            Some(original_slot) => {
+                                let mut content = PartContent::default();
+                                write_pixels(&mut content.bytes, pixels, self.is_256_color)
+                                    .map_err(|err| {
+                                        WanWriteError::FragmentBytesError(fragment_bytes_id, err)
+                                    })?;
+                                Some(encoder.add_shared(
+                                    Part::Pixels,
+                                    Some(original_slot),
+                                    &mut next_pixel_slot,
+                                    content,
+                                ))
+                            }
+                            None => None,
+                        };
+                        entries.push((entry.byte_amount, slot, entry.unknown));
+                    }
+                    table_layout.end
+                }
+                None => {
+                    // the data start after 4 bytes, so no pixel is at the null position
+                    let mut buffer = Cursor::new(vec![0; 4]);
+                    buffer.set_position(4);
+                    let assembly_table = self
+                        .compression
+                        .compress(
+                            fragment_bytes,
+                            &fragment_bytes.mixed_pixels,
+                            &mut buffer,
+                            self.is_256_color,
+                        )
+                        .map_err(|err| WanWriteError::FragmentBytesError(fragment_bytes_id, err))?;
+                    let buffer = buffer.into_inner();
+
+                    // the pixels take the place of the original ones, and the remaining ones follow them
+                    let original_slots: Vec<usize> = table_layout
+                        .map(|table| {
+                            table
+                                .entries
+                                .iter()
+                                .filter_map(|entry| entry.pixels)
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    let mut anchor = match table_layout {
+                        Some(_) => original_slots.last().copied().map(Part::Pixels),
+                        None => fragment_bytes_id.checked_sub(1).map(Part::AssemblyTable),
+                    };
+                    let mut original_slots = original_slots.into_iter();
+                    for entry in assembly_table {
+                        let slot = if entry.pixel_src == 0 {
+                            None
+                        } else {
+                            let start = entry.pixel_src as usize;
+                            let content = PartContent::new(
+                                buffer[start..start + entry.byte_amount as usize].to_vec(),
+                            );
+                            let slot = match original_slots.next() {
+                                Some(slot) if encoder.is_free(Part::Pixels(slot)) => {
+                                    encoder.add(Part::Pixels(slot), content, None);
+                                    slot
+                                }
+                                _ => {
+                                    let slot = next_pixel_slot;
+                                    next_pixel_slot += 1;
+                                    encoder.add(Part::Pixels(slot), content, anchor);
+                                    slot
+                                }
+                            };
+                            anchor = Some(Part::Pixels(slot));
+                            Some(slot)
+                        };
+                        entries.push((entry.byte_amount, slot, 0));
+                    }
+                    table_anchor = anchor;
+                    [0; 6]
+                }
+            };
+
+            let mut assembly_table = PartContent::default();
+            for (byte_amount, slot, unknown) in &entries {
+                match slot {
+                    Some(slot) => assembly_table.push_pointer(Part::Pixels(*slot)),
+                    None => assembly_table.push_u32(0),
+                }
+                assembly_table.push_u16(*byte_amount);
+                assembly_table.push_u16(*unknown);
+                assembly_table.push_u32(fragment_bytes.z_index);
+            }
+            assembly_table.push_u32(0);
+            assembly_table.push_u16(0);
+            assembly_table.bytes.extend(end);
+            let table_part = Part::AssemblyTable(fragment_bytes_id);
+            encoder.add(table_part, assembly_table, table_anchor);
+            encoder
+                .pixel_runs
+                .push(entries.iter().filter_map(|(_, slot, _)| *slot).collect());
+            fragment_bytes_table.push_pointer(table_part);
+        }
+        if !self.shared_fragment_bytes {
+            encoder.add(Part::FragmentBytesTable, fragment_bytes_table, None);
+        }
+
+        // palette
+        if !self.shared_palette {
+            if self.palette.palette.len() > u16::MAX as usize {
+                return Err(WanWriteError::TooManyColors(self.palette.palette.len()));
+            }
+            let colors = PartContent::new(self.palette.palette.concat());
+            encoder.add(Part::PaletteColors, colors, None);
+            let mut palette_header = PartContent::default();
+            palette_header.push_pointer(Part::PaletteColors);
+            palette_header.push_u16(self.palette.unk1);
+            palette_header.push_u16(self.palette.palette.len() as u16);
+            palette_header.push_u32(self.palette.unk2);
+            palette_header.push_u32(0);
+            encoder.add(Part::PaletteHeader, palette_header, None);
+        }
+
+        // headers
+        let mut anim_info = PartContent::default();
+        anim_info.push_pointer(Part::FramesTable);
+        if has_frame_offsets {
+            anim_info.push_pointer(Part::FrameOffsets);
+        } else {
+            anim_info.push_u32(0);
+        }
+        anim_info.push_pointer(Part::AnimGroupTable);
+        anim_info.push_u16(self.animation_store.anim_groups.len() as u16);
+        anim_info.push_u32(
+            self.size_to_allocate_for_max_frame
+                .unwrap_or(size_to_allocate_for_max_frame as u32),
+        );
+        anim_info.bytes.extend(layout.anim_info_end);
+        encoder.add(Part::AnimInfo, anim_info, None);
+
+        let mut image_info = PartContent::default();
+        if self.shared_fragment_bytes {
+            image_info.push_u32(0);
+        } else {
+            image_info.push_pointer(Part::FragmentBytesTable);
+        }
+        if self.shared_palette {
+            image_info.push_u32(0);
+        } else {
+            image_info.push_pointer(Part::PaletteHeader);
+        }
+        image_info.push_u16(self.unk1);
+        image_info.push_u16(u16::from(self.is_256_color));
+        image_info.push_u16(self.unk2);
+        image_info.push_u16(fragment_bytes.len() as u16);
+        encoder.add(Part::ImageInfo, image_info, None);
+
+        let mut wan_header = PartContent::default();
+        wan_header.push_pointer(Part::AnimInfo);
+        wan_header.push_pointer(Part::ImageInfo);
+        wan_header.push_u16(self.sprite_type.get_id() as u16);
+        wan_header.push_u16(self.unk_header);
+        encoder.add(Part::WanHeader, wan_header, None);
+
+        let mut sir0_header = PartContent::new(b"SIR0".to_vec());
+        sir0_header.push_pointer(Part::WanHeader);
+        sir0_header.push_pointer(Part::Sir0Footer);
+        sir0_header.push_u32(0);
+        encoder.add(Part::Sir0Header, sir0_header, None);
+        // filled once the position of the pointers is known
+        encoder.add(Part::Sir0Footer, PartContent::default(), None);
+
+        Ok(encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{Padding, Part, WanLayout};
+    use crate::{
+        encode_fragment_pixels, Animation, AnimationFrame, CompressionMethod, Fragment,
+        FragmentBytes, FragmentFlip, Frame, OamShape, SpriteType, WanImage,
+    };
+
+    /// A sprite with two frames using the same [`crate::FragmentBytes`], whose first two animations share their data
+    fn wan_with_shared_animations() -> WanImage {
+        let mut wan = WanImage::new(SpriteType::PropsUI);
+        wan.compression = CompressionMethod::CompressionMethodOriginal;
+        wan.palette.palette = vec![[0, 0, 0, 0], [255, 0, 0, 128], [0, 255, 0, 128]];
+        wan.palette.palette.resize(16, [0, 0, 0, 0]);
+        let resolution = OamShape::new(0, 1).unwrap();
+        // the top-left 8×8 chunk is transparent
+        let pixels: Vec<u8> = (0..256)
+            .map(|p| if p % 16 < 8 && p < 128 { 0 } else { 1 })
+            .collect();
+        wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+            mixed_pixels: encode_fragment_pixels(&pixels, resolution.size(), false).unwrap(),
+            z_index: 0,
+        });
+        for offset_x in [0, 8] {
+            wan.frame_store.frames.push(Frame {
+                fragments: vec![Fragment {
+                    unk1: 0,
+                    unk3_4: None,
+                    unk5: false,
+                    fragment_bytes_index: 0,
+                    offset_y: 0,
+                    offset_x,
+                    flip: FragmentFlip::standard(),
+                    is_mosaic: false,
+                    pal_idx: 0,
+                    alloc_counter: None,
+                    resolution,
+                }],
+                frame_offset: None,
+            });
+        }
+        let animation = Animation {
+            frames: vec![AnimationFrame {
+                duration: 4,
+                flag: 0,
+                frame_id: 0,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 0,
+            }],
+        };
+        // the two first animations share their data
+        wan.animation_store.anim_groups = vec![
+            vec![animation.clone(), animation.clone()],
+            Vec::new(),
+            vec![Animation {
+                frames: vec![AnimationFrame {
+                    duration: 5,
+                    ..animation.frames[0].clone()
+                }],
+            }],
+        ];
+        wan
+    }
+
+    #[test]
+    fn test_write_unmodified_with_layout() {
+        let mut original = Cursor::new(Vec::new());
+        wan_with_shared_animations()
+            .create_wan(&mut original)
+            .unwrap();
+        let original = original.into_inner();
+
+        let (wan, layout) = WanImage::decode_wan_with_layout(Cursor::new(&original)).unwrap();
+        let mut rewritten = Cursor::new(Vec::new());
+        wan.create_wan_with_layout(&mut rewritten, &layout).unwrap();
+        assert_eq!(rewritten.into_inner(), original);
+    }
+
+    #[test]
+    fn test_layout_not_at_start_of_file() {
+        let mut original = Cursor::new(vec![0xFF; 16]);
+        original.set_position(16);
+        wan_with_shared_animations()
+            .create_wan(&mut original)
+            .unwrap();
+        assert_eq!(original.position(), 16);
+        let original = original.into_inner();
+
+        let mut input = Cursor::new(&original);
+        input.set_position(16);
+        let (wan, layout) = WanImage::decode_wan_with_layout(&mut input).unwrap();
+        assert_eq!(
+            wan,
+            WanImage::decode_wan(Cursor::new(&original[16..])).unwrap()
+        );
+
+        let mut rewritten = Cursor::new(vec![0xFF; 16]);
+        rewritten.set_position(16);
+        wan.create_wan_with_layout(&mut rewritten, &layout).unwrap();
+        assert_eq!(rewritten.position(), 16);
+        assert_eq!(rewritten.into_inner(), original);
+    }
+
+    fn write_with_layout(wan: &WanImage, layout: &WanLayout) -> Vec<u8> {
+        let mut output = Cursor::new(Vec::new());
+        wan.create_wan_with_layout(&mut output, layout).unwrap();
+        output.into_inner()
+    }
+
+    /// The kind of each part of the layout, in order, with the consecutive parts of the same kind merged
+    fn part_kinds(layout: &WanLayout) -> Vec<u8> {
+        let mut kinds: Vec<u8> = layout.parts.iter().map(|(_, part)| part.rank()).collect();
+        kinds.dedup();
+        kinds
+    }
+
+    #[test]
+    fn test_write_modified_with_layout() {
+        let mut original = Cursor::new(Vec::new());
+        wan_with_shared_animations()
+            .create_wan(&mut original)
+            .unwrap();
+        let original = original.into_inner();
+        let (mut wan, layout) = WanImage::decode_wan_with_layout(Cursor::new(&original)).unwrap();
+
+        wan.palette.palette[2] = [0, 0, 255, 128];
+        wan.animation_store.anim_groups[2][0].frames[0].duration = 8;
+        let rewritten = write_with_layout(&wan, &layout);
+        assert_eq!(rewritten.len(), original.len());
+        assert_eq!(
+            rewritten
+                .iter()
+                .zip(&original)
+                .filter(|(a, b)| a != b)
+                .count(),
+            3
+        );
+        assert_eq!(WanImage::decode_wan(Cursor::new(rewritten)).unwrap(), wan);
+
+        let check_rewrite = |modified_wan: &WanImage, expected_animation_slots: usize| {
+            let rewritten = write_with_layout(modified_wan, &layout);
+            let (decoded_wan, new_layout) =
+                WanImage::decode_wan_with_layout(Cursor::new(&rewritten)).unwrap();
+            assert_eq!(decoded_wan.frame_store, modified_wan.frame_store);
+            assert_eq!(
+                decoded_wan.animation_store.anim_groups,
+                modified_wan.animation_store.anim_groups
+            );
+            assert_eq!(
+                decoded_wan.fragment_bytes_store,
+                modified_wan.fragment_bytes_store
+            );
+            assert_eq!(part_kinds(&new_layout), part_kinds(&layout));
+            assert_eq!(
+                new_layout
+                    .parts
+                    .iter()
+                    .filter(|(_, part)| matches!(part, Part::Animation(_)))
+                    .count(),
+                expected_animation_slots
+            );
+        };
+
+        // a shared animation is stored separately once it is different
+        let mut modified_wan = WanImage::decode_wan(Cursor::new(&original)).unwrap();
+        check_rewrite(&modified_wan, 2);
+        modified_wan.animation_store.anim_groups[0][1].frames[0].duration = 8;
+        check_rewrite(&modified_wan, 3);
+
+        // pixels added in the transparent part are compressed again
+        let mut modified_wan = WanImage::decode_wan(Cursor::new(&original)).unwrap();
+        modified_wan.fragment_bytes_store.fragment_bytes[0].mixed_pixels[0] = 1;
+        check_rewrite(&modified_wan, 2);
+
+        // a frame and an animation group are added
+        let mut modified_wan = WanImage::decode_wan(Cursor::new(&original)).unwrap();
+        modified_wan
+            .frame_store
+            .frames
+            .push(modified_wan.frame_store.frames[0].clone());
+        let mut animation = modified_wan.animation_store.anim_groups[0][0].clone();
+        animation.frames[0].frame_id = 2;
+        modified_wan
+            .animation_store
+            .anim_groups
+            .push(vec![animation]);
+        check_rewrite(&modified_wan, 3);
+    }
+
+    #[test]
+    fn test_write_with_other_layout() {
+        let mut original = Cursor::new(Vec::new());
+        wan_with_shared_animations()
+            .create_wan(&mut original)
+            .unwrap();
+        let (wan, mut layout) = WanImage::decode_wan_with_layout(original).unwrap();
+
+        // the palette is moved to the start of the file, and the wan header is aligned on 16 bytes
+        let palette: Vec<(Padding, Part)> = layout
+            .parts
+            .iter()
+            .filter(|(_, part)| matches!(part, Part::PaletteColors | Part::PaletteHeader))
+            .cloned()
+            .collect();
+        layout
+            .parts
+            .retain(|(_, part)| !matches!(part, Part::PaletteColors | Part::PaletteHeader));
+        layout.parts.splice(1..1, palette);
+        for (padding, part) in &mut layout.parts {
+            if *part == Part::WanHeader {
+                *padding = Padding::Align(16);
+            }
+        }
+
+        let rewritten = write_with_layout(&wan, &layout);
+        let (decoded_wan, decoded_layout) =
+            WanImage::decode_wan_with_layout(Cursor::new(&rewritten)).unwrap();
+        assert_eq!(decoded_wan, wan);
+        assert_eq!(decoded_layout.parts[1].1, Part::PaletteColors);
+        assert_eq!(write_with_layout(&decoded_wan, &decoded_layout), rewritten);
+
+        let mut modified_wan = decoded_wan;
+        modified_wan.palette.palette.resize(32, [0, 0, 255, 128]);
+        let rewritten = write_with_layout(&modified_wan, &decoded_layout);
+        let (decoded_wan, new_layout) =
+            WanImage::decode_wan_with_layout(Cursor::new(&rewritten)).unwrap();
+        assert_eq!(decoded_wan, modified_wan);
+        assert_eq!(part_kinds(&new_layout), part_kinds(&decoded_layout));
+        assert_eq!(new_layout.parts, decoded_layout.parts);
+    }
+}
@@ -0,0 +1,281 @@
+use binread::BinReaderExt;
+use byteorder::{ReadBytesExt, LE};
+use std::io::{Cursor, Seek, SeekFrom};
+
+use crate::{
+    wan_image::WanHeader, Animation, Fragment, FragmentBytes, Frame, FrameOffset, FrameStore,
+    Palette, SpriteType, WanDecodeOptions, WanError,
+};
+
+/// A read-only view of a wan file stored in memory. Only the headers are read on creation, each part of the sprite is decoded when it is accessed.
+///
+/// This is faster than [`crate::WanImage::decode_wan`] when only a few parts of the sprite are needed, like when scanning a lot of sprites.
+/// The same checks as [`crate::WanImage::decode_wan`] are performed, but only on the accessed parts.
+///
+/// The accessors return [`None`] if the element doesn't exist.
+#[derive(Debug, Clone)]
+pub struct WanView<'a> {
+    data: &'a [u8],
+    allow_non_contiguous_fragment_bytes: bool,
+    sprite_type: SpriteType,
+    is_256_color: bool,
+    pointer_frames_table: u64,
+    frame_offset_table: u64,
+    frame_count: usize,
+    pointer_animation_table: u64,
+    anim_group_count: usize,
+    pointer_fragment_bytes_table: u64,
+    fragment_bytes_count: usize,
+    pointer_palette: u64,
+}
+
+impl<'a> WanView<'a> {
+    /// Read the headers of the wan file contained in `data`. It assume that the file is decompressed.
+    pub fn new(data: &'a [u8]) -> Result<Self, WanError> {
+        Self::new_with_options(data, &WanDecodeOptions::default())
+    }
+
+    /// Same as [`WanView::new`], with the given [`WanDecodeOptions`]
+    pub fn new_with_options(data: &'a [u8], options: &WanDecodeOptions) -> Result<Self, WanError> {
+        let mut file = Cursor::new(data);
+        let header = WanHeader::read(&mut file)?;
+
+        file.seek(SeekFrom::Start(header.pointer_frames_table))?;
+        FrameStore::read_frame_pointers(&mut file, header.nb_frames)?;
+
+        Ok(Self {
+            data,
+            allow_non_contiguous_fragment_bytes: options.allow_non_contiguous_fragment_bytes,
+            sprite_type: header.sprite_type,
+            is_256_color: header.is_256_color,
+            pointer_frames_table: header.pointer_frames_table,
+            frame_offset_table: header.frame_offset_table,
+            frame_count: header.nb_frames as usize,
+            pointer_animation_table: header.pointer_animation_table,
+            anim_group_count: header.amount_animation_group as usize,
+            pointer_fragment_bytes_table: header.pointer_image_data_pointer_table,
+            // a null pointer means the FragmentBytes are stored in another file
+            fragment_bytes_count: if header.pointer_image_data_pointer_table == 0 {
+                0
+            } else {
+                header.amount_fragments as usize
+            },
+            pointer_palette: header.pointer_palette,
+        })
+    }
+
+    pub fn sprite_type(&self) -> SpriteType {
+        self.sprite_type
+    }
+
+    pub fn is_256_color(&self) -> bool {
+        self.is_256_color
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    pub fn fragment_bytes_count(&self) -> usize {
+        self.fragment_bytes_count
+    }
+
+    pub fn anim_group_count(&self) -> usize {
+        self.anim_group_count
+    }
+
+    /// Return a cursor over the data, positioned at the u32 at `pointer + index * 4`
+    fn cursor_at_table_entry(
+        &self,
+        pointer: u64,
+        index: usize,
+    ) -> Result<Cursor<&'a [u8]>, WanError> {
+        let mut file = Cursor::new(self.data);
+        file.seek(SeekFrom::Start(pointer + index as u64 * 4))?;
+        Ok(file)
+    }
+
+    /// Return the palette, or [`None`] if it is stored in another file (see [`crate::WanImage::shared_palette`])
+    pub fn palette(&self) -> Result<Option<Palette>, WanError> {
+        if self.pointer_palette == 0 {
+            return Ok(None);
+        }
+        let mut file = Cursor::new(self.data);
+        file.seek(SeekFrom::Start(self.pointer_palette))?;
+        Ok(Some(Palette::new_from_bytes(&mut file)?))
+    }
+
+    /// Return the [`Fragment`]s of the given frame, without reading its [`FrameOffset`]
+    pub fn fragments(&self, frame_id: usize) -> Result<Option<Vec<Fragment>>, WanError> {
+        if frame_id >= self.frame_count {
+            return Ok(None);
+        }
+        let mut file = self.cursor_at_table_entry(self.pointer_frames_table, frame_id)?;
+        let pointer = file.read_u32::<LE>()? as u64;
+        file.seek(SeekFrom::Start(pointer))?;
+        Ok(Some(Frame::new_from_bytes(&mut file)?.fragments))
+    }
+
+    /// Return the [`FrameOffset`] of the given frame. Return [`None`] if the frame doesn't exist or if this isn't a [`SpriteType::Chara`] sprite.
+    pub fn frame_offset(&self, frame_id: usize) -> Result<Option<FrameOffset>, WanError> {
+        if frame_id >= self.frame_count || self.frame_offset_table == 0 {
+            return Ok(None);
+        }
+        let mut file = Cursor::new(self.data);
+        // a FrameOffset is made of 8 i16
+        file.seek(SeekFrom::Start(
+            self.frame_offset_table + frame_id as u64 * 16,
+        ))?;
+        Ok(Some(file.read_le()?))
+    }
+
+    pub fn frame(&self, frame_id: usize) -> Result<Option<Frame>, WanError> {
+        let fragments = match self.fragments(frame_id)? {
+            Some(fragments) => fragments,
+            None => return Ok(None),
+        };
+        Ok(Some(Frame {
+            fragments,
+            frame_offset: self.frame_offset(frame_id)?,
+        }))
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = Result<Frame, WanError>> + '_ {
+        // no panic: the frame exist
+        (0..self.frame_count).map(|frame_id| self.frame(frame_id).map(|frame| frame.unwrap()))
+    }
+
+    /// Decode the given [`FragmentBytes`]
+    pub fn fragment_bytes(
+        &self,
+        fragment_bytes_index: usize,
+    ) -> Result<Option<FragmentBytes>, WanError> {
+        if fragment_bytes_index >= self.fragment_bytes_count {
+            return Ok(None);
+        }
+        let mut file =
+            self.cursor_at_table_entry(self.pointer_fragment_bytes_table, fragment_bytes_index)?;
+        let pointer = file.read_u32::<LE>()? as u64;
+        if pointer == 0 {
+            return Err(WanError::NullFragmentBytesPointer);
+        }
+        file.seek(SeekFrom::Start(pointer))?;
+        Ok(Some(FragmentBytes::new_from_bytes(
+            &mut file,
+            self.is_256_color,
+            self.allow_non_contiguous_fragment_bytes,
+        )?))
+    }
+
+    /// Return the [`Animation`]s of the given animation group
+    pub fn anim_group(&self, anim_group_id: usize) -> Result<Option<Vec<Animation>>, WanError> {
+        if anim_group_id >= self.anim_group_count {
+            return Ok(None);
+        }
+        // each entry of the animation group table is a pointer and a length
+        let mut file =
+            self.cursor_at_table_entry(self.pointer_animation_table, anim_group_id * 2)?;
+        let pointer = file.read_u32::<LE>()? as u64;
+        let length = file.read_u32::<LE>()?;
+        if pointer == 0 || length == 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let mut animations = Vec::new();
+        for animation_id in 0..length as usize {
+            let mut file = self.cursor_at_table_entry(pointer, animation_id)?;
+            let animation_pointer = file.read_u32::<LE>()? as u64;
+            file.seek(SeekFrom::Start(animation_pointer))?;
+            animations.push(Animation::new(&mut file)?);
+        }
+        Ok(Some(animations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Animation, AnimationFrame, Fragment, FragmentBytes, FragmentFlip, Frame, FrameOffset,
+        OamShape, SpriteType, WanImage, WanView,
+    };
+
+    #[test]
+    fn test_wan_view() {
+        let mut wan = WanImage::new(SpriteType::Chara);
+        wan.palette.palette = vec![[0, 0, 0, 0], [255, 0, 0, 128]];
+        for pixel in 0..2 {
+            wan.fragment_bytes_store.fragment_bytes.push(FragmentBytes {
+                mixed_pixels: vec![pixel; 64 * 64],
+                z_index: 1,
+            });
+        }
+        for frame_id in 0..3 {
+            wan.frame_store.frames.push(Frame {
+                fragments: vec![Fragment {
+                    unk1: 0,
+                    unk3_4: None,
+                    unk5: false,
+                    fragment_bytes_index: frame_id % 2,
+                    offset_y: -32,
+                    offset_x: frame_id as i16,
+                    flip: FragmentFlip::standard(),
+                    is_mosaic: false,
+                    pal_idx: 0,
+                    alloc_counter: None,
+                    resolution: OamShape::new(0, 3).unwrap(),
+                }],
+                frame_offset: Some(FrameOffset {
+                    head: (frame_id as i16, 1),
+                    hand_left: (2, 3),
+                    hand_right: (4, 5),
+                    center: (6, 7),
+                }),
+            });
+        }
+        let animation = |frame_id| Animation {
+            frames: vec![AnimationFrame {
+                duration: 2,
+                flag: 0,
+                frame_id,
+                offset_x: 0,
+                offset_y: 0,
+                shadow_offset_x: 0,
+                shadow_offset_y: 0,
+            }],
+        };
+        wan.animation_store.anim_groups = vec![
+            vec![animation(0), animation(2)],
+            Vec::new(),
+            vec![animation(1)],
+        ];
+        let bytes = wan.to_bytes().unwrap();
+
+        let view = WanView::new(&bytes).unwrap();
+        assert_eq!(view.sprite_type(), SpriteType::Chara);
+        assert!(!view.is_256_color());
+        assert_eq!(view.frame_count(), 3);
+        assert_eq!(view.fragment_bytes_count(), 2);
+        assert_eq!(view.anim_group_count(), 3);
+
+        assert_eq!(view.palette().unwrap(), Some(wan.palette.clone()));
+        for (frame_id, frame) in view.frames().enumerate() {
+            assert_eq!(frame.unwrap(), wan.frame_store.frames[frame_id]);
+        }
+        assert_eq!(view.frame(3).unwrap(), None);
+        for index in 0..2 {
+            assert_eq!(
+                view.fragment_bytes(index).unwrap().as_ref(),
+                Some(&wan.fragment_bytes_store.fragment_bytes[index])
+            );
+        }
+        assert_eq!(view.fragment_bytes(2).unwrap(), None);
+        for anim_group_id in 0..3 {
+            assert_eq!(
+                view.anim_group(anim_group_id).unwrap().as_ref(),
+                Some(&wan.animation_store.anim_groups[anim_group_id])
+            );
+        }
+        assert_eq!(view.anim_group(3).unwrap(), None);
+
+        assert!(WanView::new(&bytes[..16]).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use pmd_sir0::Sir0WriteFooterError;
+use std::io;
+use thiserror::Error;
+
+/// An error that happened while writing a [`crate::WanImage`], or while inserting an image in it.
+/// The variants that are caused by a specific element carry its index, so it can be reported to the user.
+#[derive(Debug, Error)]
+pub enum WanWriteError {
+    #[error("an input/output error happened")]
+    IOError(#[from] io::Error),
+    #[error("Failed to write the FragmentBytes {0}")]
+    FragmentBytesError(usize, #[source] FragmentBytesWriteError),
+    #[error("Failed to write the Sir0 footer")]
+    Sir0FooterError(#[from] Sir0WriteFooterError),
+    #[error("The x coordinate of the fragment {fragment_id} of the frame {frame_id} is {offset_x}, but it should be between -256 and 255")]
+    FragmentOffsetOutOfRange {
+        frame_id: usize,
+        fragment_id: usize,
+        offset_x: i16,
+    },
+    #[error("The fragment {fragment_id} of the frame {frame_id} use the FragmentBytes {fragment_bytes_index}, but the index can be at most 32767")]
+    FragmentBytesIndexTooLarge {
+        frame_id: usize,
+        fragment_id: usize,
+        fragment_bytes_index: usize,
+    },
+    #[error("The frame {frame_id} has no fragment, which can’t be encoded")]
+    EmptyFrame { frame_id: usize },
+    #[error(
+        "The frame {frame_id} doesn't have a FrameOffset, which is mandatory for Chara sprites"
+    )]
+    MissingFrameOffset { frame_id: usize },
+    #[error("There are {0} frames, but there can be at most 65535")]
+    TooManyFrames(usize),
+    #[error("There are {0} FragmentBytes, but there can be at most 32767")]
+    TooManyFragmentBytes(usize),
+    #[error("There are {0} animation groups, but there can be at most 65535")]
+    TooManyAnimGroups(usize),
+    #[error("There are {0} colors in the palette, but there can be at most 65535")]
+    TooManyColors(usize),
+    #[error("The image is {width}×{height}, while only image smaller than 512×256 can be used")]
+    ImageTooLarge { width: u16, height: u16 },
+    #[error("The image has {pixel_amount} pixels, which doesn't correspond to its resolution of {width}×{height}")]
+    ImageSizeMismatch {
+        width: u16,
+        height: u16,
+        pixel_amount: usize,
+    },
+    #[error("The pixel at {x}, {y} of the image is {pixel}, which can’t be stored in a 16 color sprite (it should be less than 16)")]
+    PixelOutOf16ColorRange { x: u16, y: u16, pixel: u8 },
+    #[error("No sub-palette contains all the colors of the fragment at {x}, {y}")]
+    NoSubPaletteForFragment { x: i32, y: i32 },
+    #[error("{tile_amount} sub-palettes were given for the tiles of the image, but a {width}×{height} image has a different number of 8×8 tiles")]
+    TileSubPalettesMismatch {
+        width: u16,
+        height: u16,
+        tile_amount: usize,
+    },
+}
+
+/// An error that happened while writing a single [`crate::FragmentBytes`].
+/// It is part of [`WanWriteError::FragmentBytesError`], with the index of the [`crate::FragmentBytes`].
+#[derive(Debug, Error)]
+pub enum FragmentBytesWriteError {
+    #[error("an input/output error happened")]
+    IOError(#[from] io::Error),
+    #[error("The FragmentBytes has no pixel")]
+    EmptyFragmentBytes,
+    #[error("The pixel {0} can’t be stored in a 16 color sprite (it should be less than 16)")]
+    PixelOutOf16ColorRange(u8),
+    #[error("The granularity of the optimised compression should be a non-zero multiple of the number of pixels per byte, but it is {0}")]
+    InvalidCompressionGranularity(usize),
+}
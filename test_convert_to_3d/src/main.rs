@@ -31,7 +31,13 @@ fn main() {
             let out_path = out_folder.join(path.file_name().unwrap());
             let mut out_file = File::create(&out_path).unwrap();
 
-            original_wan.sprite_type = SpriteType::Engine3D;
+            let report = original_wan.convert_to(SpriteType::Engine3D);
+            if !report.is_lossless() {
+                println!(
+                    "{} FrameOffset were dropped",
+                    report.dropped_frame_offsets.len()
+                );
+            }
 
             original_wan.create_wan(&mut out_file).unwrap();
         }
@@ -1,7 +1,5 @@
 use clap::Parser;
-use pmd_cpack::CPack;
-use pmd_pkdpx::decompress_px;
-use pmd_wan::{WanError, WanImage};
+use pmd_wan::{WanError, WanImage, WanPack, WanView};
 use std::{
     fs::{read_dir, File},
     io::{Cursor, Read, Seek, SeekFrom, Write},
@@ -16,7 +14,7 @@ struct Opts {
 fn test_read_reencode<F: Read + Seek>(
     content: &mut F,
     source: &str,
-    #[allow(unused_variables)] shouldnt_be_byte_perfect: bool,
+    shouldnt_be_byte_perfect: bool,
 ) {
     println!("trying {}", source);
 
@@ -24,7 +22,7 @@ fn test_read_reencode<F: Read + Seek>(
     content.read_to_end(&mut buffer_in).unwrap();
     content.seek(SeekFrom::Start(0)).unwrap();
     //read
-    let original_wan = match WanImage::decode_wan(content) {
+    let (original_wan, layout) = match WanImage::decode_wan_with_layout(content) {
         Ok(r) => r,
         Err(e) => {
             let e = match e {
@@ -36,13 +34,19 @@ fn test_read_reencode<F: Read + Seek>(
             panic!("an error occured while reading the original file ({:?}). File written in \"in.bin\"", e);
         }
     };
+    //write with the layout of the original file
+    let mut layout_cursor = Cursor::new(Vec::new());
+    original_wan
+        .create_wan_with_layout(&mut layout_cursor, &layout)
+        .unwrap();
+    let buffer_out = layout_cursor.into_inner();
     //write
     let rewrite_buffer: Vec<u8> = Vec::new();
     let mut rewrite_cursor = Cursor::new(rewrite_buffer);
     original_wan.create_wan(&mut rewrite_cursor).unwrap();
     // copy
     let rewriter_inner = rewrite_cursor.into_inner();
-    let buffer_out = rewriter_inner.clone();
+    let buffer_reencoded = rewriter_inner.clone();
     let mut rewrite_cursor = Cursor::new(rewriter_inner);
     //re-read
     rewrite_cursor.seek(SeekFrom::Start(0)).unwrap();
@@ -56,8 +60,9 @@ fn test_read_reencode<F: Read + Seek>(
         }
     };
 
-    if reread_wan.is_none() || reread_wan.as_ref() != Some(&original_wan)
-    /*|| (!shouldnt_be_byte_perfect && buffer_in != buffer_out)*/
+    if reread_wan.is_none()
+        || reread_wan.as_ref() != Some(&original_wan)
+        || (!shouldnt_be_byte_perfect && buffer_in != buffer_out)
     {
         // write the in.bin and out.bin file
         let mut in_file = File::create("in.bin").unwrap();
@@ -66,6 +71,9 @@ fn test_read_reencode<F: Read + Seek>(
         let mut out_file = File::create("out.bin").unwrap();
         out_file.write_all(&buffer_out).unwrap();
 
+        let mut reencoded_file = File::create("out_reencoded.bin").unwrap();
+        reencoded_file.write_all(&buffer_reencoded).unwrap();
+
         panic!(
             "failed to correctly read, write and re-read the written file for {}",
             source
@@ -82,33 +90,37 @@ fn main() {
 
     env_logger::init();
 
-    for (monster_file_name, decompress) in [
-        //("EFFECT/effect.bin", false),
+    for (pack_file_name, only_wan) in [
+        ("EFFECT/effect.bin", false),
         ("MONSTER/m_attack.bin", true),
-        ("MONSTER/m_ground.bin", false),
+        ("MONSTER/m_ground.bin", true),
         ("MONSTER/monster.bin", true),
     ] {
-        let path = opts.decompressed_pmd.join(monster_file_name);
-        let cpack_file = File::open(&path).unwrap();
-        let cpack = CPack::new_from_file(cpack_file).unwrap();
-        for sub_file_id in 0..cpack.len() {
-            let mut sub_file = cpack.get_file(sub_file_id).unwrap();
-            let sub_file_vec = if decompress {
-                decompress_px(sub_file).unwrap()
-            } else {
-                let mut buffer = Vec::new();
-                sub_file.read_to_end(&mut buffer).unwrap();
-                buffer
-            };
-            let mut cursor = Cursor::new(sub_file_vec);
-            let shouldnt_be_byte_perfect = if monster_file_name == "monster.bin" {
+        let path = opts.decompressed_pmd.join(pack_file_name);
+        let pack_file = File::open(&path).unwrap();
+        let pack = WanPack::new(pack_file).unwrap();
+        for sub_file_id in 0..pack.len() {
+            let bytes = pack.get_bytes(sub_file_id).unwrap();
+            // effect.bin also contains files in other formats
+            if !only_wan {
+                if let Err(WanError::InvalidSir0(_) | WanError::TypeOfSpriteUnknown(_)) =
+                    WanView::new(&bytes)
+                {
+                    println!(
+                        "skipping {:?} sub file n°{}, as it isn't a wan file",
+                        path, sub_file_id
+                    );
+                    continue;
+                }
+            }
+            let shouldnt_be_byte_perfect = if pack_file_name == "MONSTER/monster.bin" {
                 //they have a lot of 0 after the file... Take a look at the px decompressor, it may come from there
                 matches!(sub_file_id, 433 | 438)
             } else {
                 false
             };
             test_read_reencode(
-                &mut cursor,
+                &mut Cursor::new(bytes),
                 &format!("{:?} sub file n°{}", path, sub_file_id),
                 shouldnt_be_byte_perfect,
             );